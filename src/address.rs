@@ -0,0 +1,477 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use failure::{format_err, Fail};
+
+use crate::errors::Result;
+use crate::script::Script;
+
+const ALPHABET: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+/// length of the public key and script hashes addresses carry
+pub const HASH_LEN: usize = 20;
+const CHECKSUM_LEN: usize = 4;
+const BECH32_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+/// the constant bech32m checksums are xored with, see BIP-350
+const BECH32M_CONST: u32 = 0x2bc8_30a3;
+const BECH32_CHECKSUM_LEN: usize = 6;
+/// the longest bech32 string BIP-173 allows
+const BECH32_MAX_LEN: usize = 90;
+
+/// NETWORK is the network addresses are created for and accepted from
+static NETWORK: OnceLock<Network> = OnceLock::new();
+
+/// CurrentNetwork returns the network chosen with SetNetwork, main by default
+pub fn current_network() -> Network {
+    NETWORK.get().copied().unwrap_or(Network::Main)
+}
+
+/// SetNetwork chooses the network once, before any address is created or parsed
+pub fn set_network(network: Network) -> Result<()> {
+    NETWORK
+        .set(network)
+        .map_err(|_| format_err!("the network is already set to {}", current_network()))
+}
+
+/// Network tells apart the chains an address can belong to, so coins are
+/// never sent to an address meant for another network
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Network {
+    Main,
+    Test,
+    Regtest,
+}
+
+impl Network {
+    /// Hrp is the human-readable prefix of the bech32m addresses of the network
+    pub fn hrp(self) -> &'static str {
+        match self {
+            Network::Main => "brt",
+            Network::Test => "tbrt",
+            Network::Regtest => "brtreg",
+        }
+    }
+}
+
+impl FromStr for Network {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Network> {
+        match s {
+            "main" => Ok(Network::Main),
+            "test" => Ok(Network::Test),
+            "regtest" => Ok(Network::Regtest),
+            _ => Err(format_err!("unknown network '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Network::Main => write!(f, "main"),
+            Network::Test => write!(f, "test"),
+            Network::Regtest => write!(f, "regtest"),
+        }
+    }
+}
+
+/// AddressKind is what an address pays to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressKind {
+    /// KeyHash pays to the hash of a public key
+    KeyHash,
+    /// Multisig pays to the hash of a multisig redeem script
+    Multisig,
+    /// Script pays to the hash of any other redeem script
+    Script,
+}
+
+impl fmt::Display for AddressKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AddressKind::KeyHash => write!(f, "pubkeyhash"),
+            AddressKind::Multisig => write!(f, "multisig"),
+            AddressKind::Script => write!(f, "script"),
+        }
+    }
+}
+
+const NETWORKS: [Network; 3] = [Network::Main, Network::Test, Network::Regtest];
+/// KINDS are indexed by the first data symbol of a bech32m address
+const KINDS: [AddressKind; 3] = [
+    AddressKind::KeyHash,
+    AddressKind::Multisig,
+    AddressKind::Script,
+];
+
+/// VERSIONS maps each network and kind to the version byte an address starts with
+const VERSIONS: [(Network, AddressKind, u8); 9] = [
+    (Network::Main, AddressKind::KeyHash, 0x19),
+    (Network::Main, AddressKind::Multisig, 0x32),
+    (Network::Main, AddressKind::Script, 0x37),
+    (Network::Test, AddressKind::KeyHash, 0x41),
+    (Network::Test, AddressKind::Multisig, 0x7f),
+    (Network::Test, AddressKind::Script, 0x80),
+    (Network::Regtest, AddressKind::KeyHash, 0x6f),
+    (Network::Regtest, AddressKind::Multisig, 0xc4),
+    (Network::Regtest, AddressKind::Script, 0xc6),
+];
+
+/// InvalidAddress is returned wherever a string fails to parse as an address
+#[derive(Debug)]
+pub struct InvalidAddress {
+    pub address: String,
+    pub reason: String,
+}
+
+impl fmt::Display for InvalidAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid address '{}': {}", self.address, self.reason)
+    }
+}
+
+impl Fail for InvalidAddress {}
+
+/// Address is a hash of a network, encoded either in base58 with a version
+/// byte and a checksum, or in bech32m behind the human-readable prefix of the network
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Address {
+    pub network: Network,
+    pub kind: AddressKind,
+    pub hash: Vec<u8>,
+}
+
+impl Address {
+    /// New returns the address of a hash on the current network
+    pub fn new(kind: AddressKind, hash: &[u8]) -> Address {
+        Address {
+            network: current_network(),
+            kind,
+            hash: hash.to_vec(),
+        }
+    }
+
+    /// Encode returns base58(version || hash || checksum)
+    pub fn encode(&self) -> String {
+        let version = VERSIONS
+            .iter()
+            .find(|(network, kind, _)| *network == self.network && *kind == self.kind)
+            .map(|(_, _, version)| *version)
+            .unwrap();
+        let mut payload = vec![version];
+        payload.extend_from_slice(&self.hash);
+        let checksum = checksum(&payload);
+        payload.extend_from_slice(&checksum);
+        base58_encode(&payload)
+    }
+
+    /// EncodeBech32 returns hrp || "1" || kind || hash || checksum, the hash in
+    /// five bit symbols and the checksum a bech32m one
+    pub fn encode_bech32(&self) -> String {
+        let kind = KINDS.iter().position(|kind| *kind == self.kind).unwrap() as u8;
+        let mut data = vec![kind];
+        data.extend(convert_bits(&self.hash, 8, 5, true).unwrap());
+        bech32_encode(self.network.hrp(), &data)
+    }
+
+    /// Decode parses a base58 or bech32m address of the current network,
+    /// checking its checksum and version
+    pub fn decode(address: &str) -> std::result::Result<Address, InvalidAddress> {
+        let invalid = |reason: &str| InvalidAddress {
+            address: address.to_string(),
+            reason: reason.to_string(),
+        };
+        let lower = address.to_lowercase();
+        let bech32_network = NETWORKS
+            .iter()
+            .find(|network| lower.starts_with(&format!("{}1", network.hrp())));
+        // a base58 address may happen to start like a bech32m prefix
+        let decoded = match bech32_network {
+            Some(network) => decode_bech32(address, *network)
+                .or_else(|reason| decode_base58(address).map_err(|_| reason)),
+            None => decode_base58(address),
+        };
+        let decoded = decoded.map_err(invalid)?;
+        if decoded.network != current_network() {
+            return Err(invalid(&format!(
+                "address of the {} network",
+                decoded.network
+            )));
+        }
+        Ok(decoded)
+    }
+
+    /// ScriptPubKey returns the locking script paying to the address
+    pub fn script_pub_key(&self) -> Script {
+        match self.kind {
+            AddressKind::KeyHash => Script::new_p2pkh(&self.hash),
+            AddressKind::Multisig | AddressKind::Script => Script::new_p2sh(&self.hash),
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.encode())
+    }
+}
+
+/// decode_base58 parses base58(version || hash || checksum)
+fn decode_base58(address: &str) -> std::result::Result<Address, &'static str> {
+    let payload = base58_decode(address).ok_or("not base58")?;
+    if payload.len() != 1 + HASH_LEN + CHECKSUM_LEN {
+        return Err("wrong length");
+    }
+    let (data, check) = payload.split_at(1 + HASH_LEN);
+    if checksum(data) != check {
+        return Err("checksum mismatch");
+    }
+    let (network, kind) = VERSIONS
+        .iter()
+        .find(|(_, _, version)| *version == data[0])
+        .map(|(network, kind, _)| (*network, *kind))
+        .ok_or("unknown version byte")?;
+    Ok(Address {
+        network,
+        kind,
+        hash: data[1..].to_vec(),
+    })
+}
+
+/// decode_bech32 parses an address behind the prefix of `network`
+fn decode_bech32(address: &str, network: Network) -> std::result::Result<Address, &'static str> {
+    if address.len() > BECH32_MAX_LEN {
+        return Err("longer than 90 characters");
+    }
+    let (hrp, data) = bech32_decode(address).ok_or("bech32m checksum mismatch")?;
+    if hrp != network.hrp() || data.is_empty() {
+        return Err("wrong length");
+    }
+    let kind = *KINDS.get(data[0] as usize).ok_or("unknown address type")?;
+    let hash = convert_bits(&data[1..], 5, 8, false).ok_or("invalid padding")?;
+    if hash.len() != HASH_LEN {
+        return Err("wrong length");
+    }
+    Ok(Address {
+        network,
+        kind,
+        hash,
+    })
+}
+
+/// checksum is the first bytes of the double sha256 of the data
+fn checksum(data: &[u8]) -> Vec<u8> {
+    let mut hash = [0; 32];
+    let mut hasher = Sha256::new();
+    hasher.input(data);
+    hasher.result(&mut hash);
+    hasher.reset();
+    hasher.input(&hash);
+    hasher.result(&mut hash);
+    hash[..CHECKSUM_LEN].to_vec()
+}
+
+fn base58_encode(data: &[u8]) -> String {
+    let zeros = data.iter().take_while(|b| **b == 0).count();
+    // little endian base58 digits
+    let mut digits: Vec<u8> = Vec::new();
+    for byte in &data[zeros..] {
+        let mut carry = *byte as u32;
+        for digit in digits.iter_mut() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+    let mut encoded = "1".repeat(zeros);
+    encoded.extend(digits.iter().rev().map(|d| ALPHABET[*d as usize] as char));
+    encoded
+}
+
+fn base58_decode(s: &str) -> Option<Vec<u8>> {
+    let zeros = s.bytes().take_while(|c| *c == b'1').count();
+    // little endian bytes
+    let mut bytes: Vec<u8> = Vec::new();
+    for c in s.bytes().skip(zeros) {
+        let mut carry = ALPHABET.iter().position(|a| *a == c)? as u32;
+        for byte in bytes.iter_mut() {
+            carry += *byte as u32 * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push(carry as u8);
+            carry >>= 8;
+        }
+    }
+    let mut decoded = vec![0; zeros];
+    decoded.extend(bytes.iter().rev());
+    Some(decoded)
+}
+
+/// bech32_polymod is the BCH code checksum of BIP-173
+fn bech32_polymod(values: &[u8]) -> u32 {
+    const GENERATOR: [u32; 5] = [
+        0x3b6a_57b2,
+        0x2650_8e6d,
+        0x1ea1_19fa,
+        0x3d42_33dd,
+        0x2a14_62b3,
+    ];
+    let mut chk = 1u32;
+    for value in values {
+        let top = chk >> 25;
+        chk = (chk & 0x1ff_ffff) << 5 ^ *value as u32;
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if top >> i & 1 == 1 {
+                chk ^= generator;
+            }
+        }
+    }
+    chk
+}
+
+/// bech32_hrp_expand feeds the prefix to the checksum: high bits, a zero, low bits
+fn bech32_hrp_expand(hrp: &str) -> Vec<u8> {
+    let mut values: Vec<u8> = hrp.bytes().map(|b| b >> 5).collect();
+    values.push(0);
+    values.extend(hrp.bytes().map(|b| b & 31));
+    values
+}
+
+fn bech32_encode(hrp: &str, data: &[u8]) -> String {
+    let mut values = bech32_hrp_expand(hrp);
+    values.extend_from_slice(data);
+    values.extend_from_slice(&[0; BECH32_CHECKSUM_LEN]);
+    let polymod = bech32_polymod(&values) ^ BECH32M_CONST;
+    let checksum = (0..BECH32_CHECKSUM_LEN).map(|i| (polymod >> (5 * (5 - i)) & 31) as u8);
+    let mut encoded = format!("{}1", hrp);
+    encoded.extend(
+        data.iter()
+            .copied()
+            .chain(checksum)
+            .map(|d| BECH32_CHARSET[d as usize] as char),
+    );
+    encoded
+}
+
+/// bech32_decode splits a bech32m string into its prefix and data symbols,
+/// checking the checksum and rejecting mixed case
+fn bech32_decode(s: &str) -> Option<(String, Vec<u8>)> {
+    if s.to_lowercase() != s && s.to_uppercase() != s {
+        return None;
+    }
+    let s = s.to_lowercase();
+    let (hrp, data) = s.rsplit_once('1')?;
+    if hrp.is_empty() || data.len() < BECH32_CHECKSUM_LEN {
+        return None;
+    }
+    let data = data
+        .bytes()
+        .map(|c| BECH32_CHARSET.iter().position(|x| *x == c).map(|d| d as u8))
+        .collect::<Option<Vec<u8>>>()?;
+    let mut values = bech32_hrp_expand(hrp);
+    values.extend_from_slice(&data);
+    if bech32_polymod(&values) != BECH32M_CONST {
+        return None;
+    }
+    Some((
+        hrp.to_string(),
+        data[..data.len() - BECH32_CHECKSUM_LEN].to_vec(),
+    ))
+}
+
+/// convert_bits regroups `from` bit values into `to` bit values
+fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Option<Vec<u8>> {
+    let (mut acc, mut bits) = (0u32, 0u32);
+    let max = (1 << to) - 1;
+    let mut converted = Vec::new();
+    for value in data {
+        acc = acc << from | *value as u32;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            converted.push((acc >> bits & max) as u8);
+        }
+    }
+    if pad {
+        if bits > 0 {
+            converted.push((acc << (to - bits) & max) as u8);
+        }
+    } else if bits >= from || acc << (to - bits) & max != 0 {
+        return None;
+    }
+    Some(converted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base58() {
+        assert_eq!(base58_encode(b"hello world"), "StV1DL6CwTryKyV");
+        assert_eq!(base58_encode(&[0, 0, 1]), "112");
+        assert_eq!(base58_decode("112").unwrap(), vec![0, 0, 1]);
+        assert_eq!(base58_decode("StV1DL6CwTryKyV").unwrap(), b"hello world");
+        assert!(base58_decode("0OIl").is_none());
+    }
+
+    #[test]
+    fn test_address_round_trip() {
+        for kind in [
+            AddressKind::KeyHash,
+            AddressKind::Multisig,
+            AddressKind::Script,
+        ] {
+            let address = Address::new(kind, &[7; HASH_LEN]);
+            assert_eq!(Address::decode(&address.encode()).unwrap(), address);
+        }
+        let encoded = Address::new(AddressKind::KeyHash, &[7; HASH_LEN]).encode();
+        let mut typo = encoded.clone().into_bytes();
+        typo[5] = if typo[5] == b'2' { b'3' } else { b'2' };
+        assert!(Address::decode(std::str::from_utf8(&typo).unwrap()).is_err());
+        assert!(Address::decode(&encoded[1..]).is_err());
+        let regtest = Address {
+            network: Network::Regtest,
+            ..Address::new(AddressKind::KeyHash, &[7; HASH_LEN])
+        };
+        assert!(Address::decode(&regtest.encode()).is_err());
+        assert!(Address::decode(&regtest.encode_bech32()).is_err());
+    }
+
+    #[test]
+    fn test_bech32m() {
+        for valid in [
+            "a1lqfn3a",
+            "A1LQFN3A",
+            "abcdef1l7aum6echk45nj3s0wdvt2fg8x9yrzpqzd3ryx",
+        ] {
+            assert!(bech32_decode(valid).is_some(), "{}", valid);
+        }
+        assert!(bech32_decode("a1lqfn3A").is_none());
+        assert!(bech32_decode("a1lqfn3q").is_none());
+        for kind in KINDS {
+            let address = Address::new(kind, &[9; HASH_LEN]);
+            let encoded = address.encode_bech32();
+            assert!(encoded.starts_with("brt1"));
+            assert_eq!(Address::decode(&encoded).unwrap(), address);
+            assert_eq!(Address::decode(&encoded.to_uppercase()).unwrap(), address);
+            let mut typo = encoded.into_bytes();
+            typo[8] = if typo[8] == b'q' { b'p' } else { b'q' };
+            assert!(Address::decode(std::str::from_utf8(&typo).unwrap()).is_err());
+        }
+        let long = bech32_encode(Network::Main.hrp(), &[0; 81]);
+        assert_eq!(
+            Address::decode(&long).unwrap_err().reason,
+            "longer than 90 characters"
+        );
+    }
+}
@@ -0,0 +1,138 @@
+use std::fmt;
+use std::str::FromStr;
+
+use failure::format_err;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::Result;
+
+/// number of decimals of the display unit
+pub const DECIMALS: u32 = 8;
+/// COIN is the number of base units in one coin
+pub const COIN: u64 = 100_000_000;
+/// SUBSIDY is the reward of the coinbase transaction
+pub const SUBSIDY: Amount = Amount(100 * COIN);
+
+/// Amount is a non-negative value in base units, one coin being COIN base units
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct Amount(u64);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+
+    pub const fn from_units(units: u64) -> Amount {
+        Amount(units)
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    /// CheckedAdd returns the sum, or None when it overflows
+    pub fn checked_add(self, other: Amount) -> Option<Amount> {
+        self.0.checked_add(other.0).map(Amount)
+    }
+
+    /// CheckedSub returns the difference, or None when it would be negative
+    pub fn checked_sub(self, other: Amount) -> Option<Amount> {
+        self.0.checked_sub(other.0).map(Amount)
+    }
+
+    /// CheckedSum adds up the amounts, or returns None when the total overflows
+    pub fn checked_sum<I: IntoIterator<Item = Amount>>(amounts: I) -> Option<Amount> {
+        amounts
+            .into_iter()
+            .try_fold(Amount::ZERO, |acc, a| acc.checked_add(a))
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}.{:0width$}",
+            self.0 / COIN,
+            self.0 % COIN,
+            width = DECIMALS as usize
+        )
+    }
+}
+
+impl FromStr for Amount {
+    type Err = failure::Error;
+
+    /// parses a decimal number of coins such as `12`, `0.5` or `1.00000001`
+    fn from_str(s: &str) -> Result<Amount> {
+        let invalid = || format_err!("invalid amount '{}'", s);
+        let (whole, fraction) = match s.split_once('.') {
+            Some((whole, fraction)) => (whole, fraction),
+            None => (s, ""),
+        };
+        if whole.is_empty() && fraction.is_empty() {
+            return Err(invalid());
+        }
+        if !whole
+            .chars()
+            .chain(fraction.chars())
+            .all(|c| c.is_ascii_digit())
+        {
+            return Err(invalid());
+        }
+        if fraction.len() > DECIMALS as usize {
+            return Err(format_err!(
+                "amount '{}' has more than {} decimals",
+                s,
+                DECIMALS
+            ));
+        }
+        let whole: u64 = if whole.is_empty() {
+            0
+        } else {
+            whole.parse().map_err(|_| invalid())?
+        };
+        let fraction: u64 = if fraction.is_empty() {
+            0
+        } else {
+            fraction.parse::<u64>().map_err(|_| invalid())?
+                * 10u64.pow(DECIMALS - fraction.len() as u32)
+        };
+        whole
+            .checked_mul(COIN)
+            .and_then(|units| units.checked_add(fraction))
+            .map(Amount)
+            .ok_or_else(|| format_err!("amount '{}' is too large", s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_display() {
+        assert_eq!("12".parse::<Amount>().unwrap(), Amount(12 * COIN));
+        assert_eq!("0.5".parse::<Amount>().unwrap(), Amount(COIN / 2));
+        assert_eq!("1.00000001".parse::<Amount>().unwrap(), Amount(COIN + 1));
+        assert_eq!(Amount(COIN + 1).to_string(), "1.00000001");
+        assert_eq!(SUBSIDY.to_string(), "100.00000000");
+        assert!("-1".parse::<Amount>().is_err());
+        assert!("1.000000001".parse::<Amount>().is_err());
+        assert!("1e3".parse::<Amount>().is_err());
+        assert!(".".parse::<Amount>().is_err());
+        assert!("184467440738".parse::<Amount>().is_err());
+    }
+
+    #[test]
+    fn test_checked_arithmetic() {
+        let max = Amount::from_units(u64::MAX);
+        assert_eq!(max.checked_add(Amount(1)), None);
+        assert_eq!(Amount(1).checked_sub(Amount(2)), None);
+        assert_eq!(
+            Amount::checked_sum(vec![Amount(1), Amount(2)]),
+            Some(Amount(3))
+        );
+        assert_eq!(Amount::checked_sum(vec![max, Amount(1)]), None);
+    }
+}
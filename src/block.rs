@@ -1,13 +1,32 @@
 use crate::{errors::Result, transaction::Transaction};
 use crypto::digest::Digest;
 use crypto::sha2::Sha256;
+use failure::format_err;
 use log::info;
 use merkle_cbt::merkle_tree::Merge;
 use merkle_cbt::merkle_tree::CBMT;
 use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
 use std::time::SystemTime;
 
 const TARGET_HEXT: usize = 4;
+
+/// NowMillis returns the node clock as milliseconds since the unix epoch
+pub fn now_millis() -> Result<u128> {
+    Ok(SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)?
+        .as_millis())
+}
+
+/// DefaultMiningThreads returns the number of threads used for proof-of-work when none is configured
+pub fn default_mining_threads() -> usize {
+    thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Block {
     timestamp: u128,
@@ -15,7 +34,8 @@ pub struct Block {
     prev_block_hash: String,
     hash: String,
     height: i32,
-    nonce: i32,
+    nonce: u64,
+    seal: Vec<u8>,
 }
 
 impl Block {
@@ -34,18 +54,30 @@ impl Block {
         self.hash.clone()
     }
 
-    pub fn new_genesis_block(coninbase: Transaction) -> Block {
-        Block::new_block(vec![coninbase], String::new(), 0).unwrap()
+    pub fn get_seal(&self) -> &[u8] {
+        &self.seal
+    }
+
+    pub(crate) fn set_seal(&mut self, seal: Vec<u8>, hash: String) {
+        self.seal = seal;
+        self.hash = hash;
+    }
+
+    pub fn get_timestamp(&self) -> u128 {
+        self.timestamp
     }
 
+    pub fn new_genesis_block(coninbase: Transaction) -> Result<Block> {
+        Block::new_block(vec![coninbase], String::new(), 0, now_millis()?)
+    }
+
+    /// NewBlock assembles an unsealed block, the consensus engine seals it
     pub fn new_block(
         data: Vec<Transaction>,
         prev_block_hash: String,
         height: i32,
+        timestamp: u128,
     ) -> Result<Block> {
-        let timestamp = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)?
-            .as_millis();
         let mut block = Block {
             timestamp: timestamp,
             transactions: data,
@@ -53,27 +85,104 @@ impl Block {
             hash: String::new(),
             height,
             nonce: 0,
+            seal: Vec::new(),
         };
-        block.run_proof_if_work()?;
+        block.hash = block.calculate_hash()?;
         Ok(block)
     }
-    fn run_proof_if_work(&mut self) -> Result<()> {
-        info!("Mining the block");
-        while !self.validate()? {
-            self.nonce += 1;
-        }
+
+    /// CalculateHash hashes the block header
+    pub fn calculate_hash(&self) -> Result<String> {
         let data = self.prepare_hash_data()?;
         let mut hasher = Sha256::new();
         hasher.input(&data[..]);
-        self.hash = hasher.result_str();
+        Ok(hasher.result_str())
+    }
+
+    /// RunProofOfWork searches the nonce space on `threads` threads, rolling the
+    /// coinbase extra nonce whenever every 64-bit nonce has been tried
+    pub(crate) fn run_proof_of_work(&mut self, threads: usize) -> Result<()> {
+        info!("Mining the block with {} threads", threads);
+        let mut extra_nonce: u64 = 0;
+        loop {
+            if let Some(nonce) = self.search_nonce(threads)? {
+                self.nonce = nonce;
+                break;
+            }
+            if !self.transactions.iter().any(|tx| tx.is_coinbase()) {
+                return Err(format_err!(
+                    "nonce space exhausted and the block has no coinbase for an extra nonce"
+                ));
+            }
+            extra_nonce = extra_nonce
+                .checked_add(1)
+                .ok_or_else(|| format_err!("extra nonce space exhausted"))?;
+            info!("Nonce space exhausted, extra nonce: {}", extra_nonce);
+            for tx in self.transactions.iter_mut() {
+                if tx.is_coinbase() {
+                    tx.set_extra_nonce(extra_nonce)?;
+                }
+            }
+        }
+        self.hash = self.calculate_hash()?;
         Ok(())
     }
 
-    /// HashTransactions returns a hash of the transactions in the block
+    /// SearchNonce partitions the nonce space between threads, thread `i` trying
+    /// `i`, `i + threads`, `i + 2 * threads`, ... until one of them finds a valid nonce
+    fn search_nonce(&self, threads: usize) -> Result<Option<u64>> {
+        let threads = threads.max(1) as u64;
+        let found = AtomicBool::new(false);
+        let winner = Mutex::new(None);
+        thread::scope(|s| {
+            let mut handles = Vec::new();
+            for start in 0..threads {
+                let found = &found;
+                let winner = &winner;
+                handles.push(s.spawn(move || -> Result<()> {
+                    let mut candidate = self.clone();
+                    let mut nonce = start;
+                    while !found.load(Ordering::Relaxed) {
+                        candidate.nonce = nonce;
+                        match candidate.validate() {
+                            Ok(true) => {
+                                found.store(true, Ordering::Relaxed);
+                                winner.lock().unwrap().get_or_insert(nonce);
+                                break;
+                            }
+                            Ok(false) => {}
+                            Err(e) => {
+                                // stop the other threads, the error fails the search
+                                found.store(true, Ordering::Relaxed);
+                                return Err(e);
+                            }
+                        }
+                        nonce = match nonce.checked_add(threads) {
+                            Some(n) => n,
+                            None => break,
+                        };
+                    }
+                    Ok(())
+                }));
+            }
+            for handle in handles {
+                match handle.join() {
+                    Ok(res) => res?,
+                    Err(_) => return Err(format_err!("proof of work thread panicked")),
+                }
+            }
+            Ok(())
+        })?;
+        let nonce = *winner.lock().unwrap();
+        Ok(nonce)
+    }
+
+    /// HashTransactions returns a hash of the transactions in the block,
+    /// including their unlocking scripts
     fn hash_transactions(&self) -> Result<Vec<u8>> {
         let mut transactions = Vec::new();
         for tx in &self.transactions {
-            transactions.push(tx.hash()?.as_bytes().to_owned());
+            transactions.push(tx.witness_hash()?.as_bytes().to_owned());
         }
         let tree = CBMT::<Vec<u8>, MergeTX>::build_merkle_tree(&transactions);
         Ok(tree.root())
@@ -99,7 +208,8 @@ impl Block {
         Ok(bytes)
     }
 
-    fn validate(&self) -> Result<bool> {
+    /// Validate checks the header hash against the proof-of-work target
+    pub(crate) fn validate(&self) -> Result<bool> {
         let data = self.prepare_hash_data()?;
         let mut hasher = Sha256::new();
         hasher.input(&data[..]);
@@ -135,3 +245,34 @@ impl Merge for MergeTX {
         re.to_vec()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_nonce() {
+        let cbtx = Transaction::new_coinbase(
+            String::from("BHiXGtopH6jKuamEZd3SbpK3EKMr9VmBS9"),
+            String::from("reward!"),
+        )
+        .unwrap();
+        let mut block = Block::new_block(vec![cbtx], String::new(), 0, 0).unwrap();
+        block.run_proof_of_work(4).unwrap();
+        assert!(block.validate().unwrap());
+        assert!(block.get_hash().starts_with(&"0".repeat(TARGET_HEXT)));
+        assert_eq!(block.get_hash(), block.calculate_hash().unwrap());
+
+        // rolling the extra nonce gives a new header to search
+        let mut rolled = block.clone();
+        rolled.transactions[0].set_extra_nonce(1).unwrap();
+        assert_ne!(
+            rolled.transactions[0].witness_hash().unwrap(),
+            block.transactions[0].witness_hash().unwrap()
+        );
+        assert_ne!(rolled.calculate_hash().unwrap(), block.get_hash());
+        let nonce = rolled.search_nonce(3).unwrap().unwrap();
+        rolled.nonce = nonce;
+        assert!(rolled.validate().unwrap());
+    }
+}
@@ -1,14 +1,23 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
 
 use failure::format_err;
 use log::info;
 
-use crate::block::Block;
+use crate::address::{current_network, Network};
+use crate::block::{default_mining_threads, now_millis, Block};
+use crate::consensus::{new_engine, ConsensusConfig, ConsensusEngine, SealContext};
 use crate::errors::Result;
+use crate::rpc::COOKIE_FILE;
+use crate::script::Script;
 use crate::transaction::Transaction;
-use crate::tx::TXOutputs;
+use crate::tx::{RelativeLock, TXOutputs};
+use crate::wallet::Wallet;
 
-const _TARGET_HEXT: usize = 4;
+/// number of previous blocks whose median timestamp a new block must exceed
+const MEDIAN_TIME_SPAN: usize = 11;
+/// how far ahead of the adjusted network time a block timestamp may be, in milliseconds
+const MAX_FUTURE_BLOCK_TIME: u128 = 2 * 60 * 60 * 1000;
 const GENESIS_COINBASE_DATA: &str =
     "The Times 03/Jan/2009 Chancellor on brink of second bailout for banks";
 
@@ -16,6 +25,19 @@ const GENESIS_COINBASE_DATA: &str =
 pub struct Blockchain {
     current_hash: String,
     db: sled::Db,
+    mining_threads: usize,
+    engine: Arc<dyn ConsensusEngine>,
+    signer: Option<Wallet>,
+    time_offset: i64,
+}
+
+/// TipChange lists the blocks that left and joined the best chain when the tip moved
+#[derive(Debug, Clone, Default)]
+pub struct TipChange {
+    /// Disconnected holds the blocks of the old branch, tip first
+    pub disconnected: Vec<Block>,
+    /// Connected holds the blocks of the new branch, lowest first
+    pub connected: Vec<Block>,
 }
 
 pub struct BlockchainIter<'a> {
@@ -23,25 +45,78 @@ pub struct BlockchainIter<'a> {
     bc: &'a Blockchain,
 }
 
+/// read_network reads the network stored with the chain, chains created
+/// before networks existed are on the main network
+fn read_network(db: &sled::Db) -> Result<Network> {
+    match db.get("NETWORK")? {
+        Some(data) => String::from_utf8(data.to_vec())?.parse(),
+        None => Ok(Network::Main),
+    }
+}
+
 impl Blockchain {
     pub fn new() -> Result<Blockchain> {
         info!("open blockchain");
 
-        let db = sled::open("data/blocks")?;
+        let db = match sled::open("data/blocks") {
+            Ok(db) => db,
+            // the running node holds the lock of the database
+            Err(e) if std::path::Path::new(COOKIE_FILE).exists() => {
+                return Err(format_err!(
+                    "the running node holds the block database, stop it to run this command: {}",
+                    e
+                ))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let network = read_network(&db)?;
+        if network != current_network() {
+            return Err(format_err!(
+                "the chain is on the {} network, not the {} network",
+                network,
+                current_network()
+            ));
+        }
         let hash = db
             .get("LAST")?
             .expect("Must create a new block database first");
         info!("Found block database");
         let lasthash = String::from_utf8(hash.to_vec())?;
+        // chains created before consensus engines were pluggable are proof-of-work
+        let consensus = match db.get("CONSENSUS")? {
+            Some(data) => bincode::deserialize(&data)?,
+            None => ConsensusConfig::ProofOfWork,
+        };
 
         Ok(Blockchain {
             current_hash: lasthash.clone(),
             db,
+            mining_threads: default_mining_threads(),
+            engine: new_engine(&consensus),
+            signer: None,
+            time_offset: 0,
         })
     }
 
+    /// StoredNetwork returns the network the chain on disk was created for,
+    /// None when no chain has been created yet
+    pub fn stored_network() -> Result<Option<Network>> {
+        if !std::path::Path::new("data/blocks").exists() {
+            return Ok(None);
+        }
+        let db = sled::open("data/blocks")?;
+        if db.get("LAST")?.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(read_network(&db)?))
+    }
+
     /// CreateBlockchain creates a new blockchain DB
-    pub fn create_blockchain(address: String) -> Result<Blockchain> {
+    pub fn create_blockchain(
+        address: String,
+        consensus: ConsensusConfig,
+        signer: Option<Wallet>,
+    ) -> Result<Blockchain> {
         info!("Creating new blockchain");
         if let Err(_) = std::fs::remove_dir_all("data/blocks") {
             info!("blocks not exist to delete")
@@ -49,30 +124,226 @@ impl Blockchain {
 
         let db = sled::open("data/blocks")?;
         info!("Creating new block database");
+        Blockchain::init(db, address, consensus, signer)
+    }
+
+    /// init stores the genesis block and the chain settings in an empty database
+    fn init(
+        db: sled::Db,
+        address: String,
+        consensus: ConsensusConfig,
+        signer: Option<Wallet>,
+    ) -> Result<Blockchain> {
+        let engine = new_engine(&consensus);
         let cbtx = Transaction::new_coinbase(address, String::from(GENESIS_COINBASE_DATA))?;
-        let genesis: Block = Block::new_genesis_block(cbtx);
+        let mut genesis: Block = Block::new_genesis_block(cbtx)?;
+        engine.seal(
+            &mut genesis,
+            &SealContext {
+                threads: default_mining_threads(),
+                signer: signer.as_ref(),
+            },
+        )?;
+        db.insert("CONSENSUS", bincode::serialize(&consensus)?)?;
+        db.insert("NETWORK", current_network().to_string().as_bytes())?;
         db.insert(genesis.get_hash(), bincode::serialize(&genesis)?)?;
         db.insert("LAST", genesis.get_hash().as_bytes())?;
         let bc = Blockchain {
             current_hash: genesis.get_hash(),
             db,
+            mining_threads: default_mining_threads(),
+            engine,
+            signer,
+            time_offset: 0,
         };
         bc.db.flush()?;
         Ok(bc)
     }
 
+    /// SetSigner sets the wallet used to seal blocks under proof of authority
+    pub fn set_signer(&mut self, wallet: Wallet) {
+        self.signer = Some(wallet);
+    }
+
+    /// SetMiningThreads sets how many threads proof-of-work uses when mining
+    pub fn set_mining_threads(&mut self, threads: usize) {
+        self.mining_threads = threads.max(1);
+    }
+
+    /// SetTimeOffset sets the peer-derived correction applied to the node clock, in milliseconds
+    pub fn set_time_offset(&mut self, offset: i64) {
+        self.time_offset = offset;
+    }
+
+    /// AdjustedTime returns the node clock corrected by the peer time offset
+    pub fn adjusted_time(&self) -> Result<u128> {
+        let now = now_millis()? as i128 + self.time_offset as i128;
+        Ok(now.max(0) as u128)
+    }
+
+    /// MedianTimePast returns the median timestamp of the last blocks ending at
+    /// `block_hash`, or None when that block is not known
+    pub fn median_time_past(&self, block_hash: &str) -> Result<Option<u128>> {
+        let iter = BlockchainIter {
+            current_hash: block_hash.to_string(),
+            bc: self,
+        };
+        let mut timestamps: Vec<u128> = iter
+            .take(MEDIAN_TIME_SPAN)
+            .map(|b| Ok(b?.get_timestamp()))
+            .collect::<Result<_>>()?;
+        if timestamps.is_empty() {
+            return Ok(None);
+        }
+        timestamps.sort();
+        Ok(Some(timestamps[timestamps.len() / 2]))
+    }
+
+    /// LockTimeContext returns the height of the next block and the median time
+    /// past of the tip, the point lock times of new transactions are checked at
+    pub fn lock_time_context(&self) -> Result<(i32, u128)> {
+        let time = match self.median_time_past(&self.current_hash)? {
+            Some(mtp) => mtp,
+            None => self.adjusted_time()?,
+        };
+        Ok((self.get_best_height()? + 1, time))
+    }
+
+    /// CheckPendingLockTimes checks a transaction could be included in the next block
+    pub fn check_pending_lock_times(&self, tx: &Transaction) -> Result<()> {
+        let (height, time) = self.lock_time_context()?;
+        self.check_lock_times(tx, &self.current_hash, &[], height, time)
+    }
+
+    /// CheckLockTimes checks the absolute lock time and the relative locks of
+    /// the inputs of a transaction included at `height` after `time`, in a block
+    /// whose parent is `prev_hash`. Coins are looked up along that block's own
+    /// ancestry, those created by `block_txs`, the transactions of the same
+    /// block, count as confirmed at `height` and `time`
+    pub fn check_lock_times(
+        &self,
+        tx: &Transaction,
+        prev_hash: &str,
+        block_txs: &[Transaction],
+        height: i32,
+        time: u128,
+    ) -> Result<()> {
+        if tx.is_coinbase() {
+            return Ok(());
+        }
+        if !tx.is_final(height, time) {
+            return Err(format_err!(
+                "ERROR: Transaction {} is locked until {}",
+                tx.id,
+                tx.lock_time
+            ));
+        }
+        for vin in &tx.vin {
+            let lock = match vin.relative_lock() {
+                Some(lock) => lock,
+                None => continue,
+            };
+            let (coin_height, coin_time) = if block_txs.iter().any(|t| t.id == vin.txid) {
+                (height, time)
+            } else {
+                self.find_branch_transaction_block(prev_hash, &vin.txid)?
+            };
+            let locked = match lock {
+                RelativeLock::Blocks(blocks) => height < coin_height + blocks,
+                RelativeLock::Millis(millis) => time < coin_time + millis,
+            };
+            if locked {
+                return Err(format_err!(
+                    "ERROR: Transaction {} spends {} before its relative lock {:?}",
+                    tx.id,
+                    vin.txid,
+                    lock
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// ValidateBlock checks the seal, the height, the timestamp rules and the
+    /// transactions of a block
+    ///
+    /// A block whose parent is not known yet cannot be checked against its
+    /// parent height, the median time past or the outputs it spends and is only
+    /// held to the future drift limit, AddBlock checks it again before its branch
+    /// joins the best chain
+    pub fn validate_block(&self, block: &Block) -> Result<()> {
+        if !self.engine.verify_seal(block)? {
+            return Err(format_err!("ERROR: Invalid block seal"));
+        }
+        let prev_hash = block.get_prev_hash();
+        let expected_height = if prev_hash.is_empty() {
+            Some(0)
+        } else if self.db.contains_key(&prev_hash)? {
+            Some(self.get_block(&prev_hash)?.get_height() + 1)
+        } else {
+            None
+        };
+        if let Some(height) = expected_height {
+            if block.get_height() != height {
+                return Err(format_err!(
+                    "ERROR: Block {} has height {}, expected {}",
+                    block.get_hash(),
+                    block.get_height(),
+                    height
+                ));
+            }
+        }
+        if let Some(mtp) = self.median_time_past(&block.get_prev_hash())? {
+            if block.get_timestamp() <= mtp {
+                return Err(format_err!(
+                    "ERROR: Block timestamp {} is not after the median time past {}",
+                    block.get_timestamp(),
+                    mtp
+                ));
+            }
+            for tx in block.get_transaction() {
+                let prev_hash = block.get_prev_hash();
+                let block_txs = block.get_transaction();
+                self.check_lock_times(tx, &prev_hash, block_txs, block.get_height(), mtp)?;
+            }
+            self.verify_block_transactions(block)?;
+        }
+        let limit = self.adjusted_time()? + MAX_FUTURE_BLOCK_TIME;
+        if block.get_timestamp() > limit {
+            return Err(format_err!(
+                "ERROR: Block timestamp {} is too far in the future",
+                block.get_timestamp()
+            ));
+        }
+        Ok(())
+    }
+
     pub fn mine_block(&mut self, transactions: Vec<Transaction>) -> Result<Block> {
         info!("Mining a new block");
+        let (height, time) = self.lock_time_context()?;
         for tx in &transactions {
             if !self.verify_transaction(tx)? {
                 return Err(format_err!("ERROR: Invalid transaction"));
             }
+            self.check_lock_times(tx, &self.current_hash, &transactions, height, time)?;
+        }
+        let lasthash = String::from_utf8(self.db.get("LAST")?.unwrap().to_vec())?;
+        let mut timestamp = self.adjusted_time()?;
+        if let Some(mtp) = self.median_time_past(&lasthash)? {
+            timestamp = timestamp.max(mtp + 1);
         }
-        let lasthash = self.db.get("LAST")?.unwrap();
-        let newblock = Block::new_block(
+        let mut newblock = Block::new_block(
             transactions,
-            String::from_utf8(lasthash.to_vec())?,
+            lasthash,
             self.get_best_height()? + 1,
+            timestamp,
+        )?;
+        self.engine.seal(
+            &mut newblock,
+            &SealContext {
+                threads: self.mining_threads,
+                signer: self.signer.as_ref(),
+            },
         )?;
         self.db
             .insert(newblock.get_hash(), bincode::serialize(&newblock)?)?;
@@ -84,11 +355,20 @@ impl Blockchain {
 
     // GetBlock finds a block by its hash and returns it
     pub fn get_block(&self, block_hash: &str) -> Result<Block> {
-        let data = self.db.get(block_hash)?.unwrap();
+        let data = match self.db.get(block_hash)? {
+            Some(data) => data,
+            None => return Err(format_err!("block {} is not found", block_hash)),
+        };
         let block = bincode::deserialize(&data.to_vec())?;
         Ok(block)
     }
 
+    /// Flush writes the buffered changes of the block database to disk
+    pub fn flush(&self) -> Result<()> {
+        self.db.flush()?;
+        Ok(())
+    }
+
     pub fn get_best_height(&self) -> Result<i32> {
         let lasthash = if let Some(h) = self.db.get("LAST")? {
             h
@@ -101,12 +381,12 @@ impl Blockchain {
         Ok(last_block.get_height())
     }
 
-    pub fn get_block_hashs(&self) -> Vec<String> {
+    pub fn get_block_hashs(&self) -> Result<Vec<String>> {
         let mut list = Vec::new();
         for b in self.iter() {
-            list.push(b.get_hash());
+            list.push(b?.get_hash());
         }
-        list
+        Ok(list)
     }
 
     /// VerifyTransaction verifies transaction input signatures
@@ -118,19 +398,68 @@ impl Blockchain {
         tx.verify(prev_txs)
     }
 
-    pub fn add_block(&mut self, block: Block) -> Result<()> {
+    /// AddBlock stores a block and makes it the tip when the consensus engine
+    /// prefers it, returning how the best chain changed
+    pub fn add_block(&mut self, block: Block) -> Result<TipChange> {
         let data = bincode::serialize(&block)?;
         if let Some(_) = self.db.get(block.get_hash())? {
-            return Ok(());
+            return Ok(TipChange::default());
+        }
+        self.validate_block(&block)?;
+        let (better, old_tip) = match self.db.get("LAST")? {
+            Some(lasthash) => {
+                let tip = self.get_block(&String::from_utf8(lasthash.to_vec())?)?;
+                (self.engine.prefer(&block, &tip), Some(tip))
+            }
+            None => (true, None),
+        };
+        if !better {
+            self.db.insert(block.get_hash(), data)?;
+            return Ok(TipChange::default());
+        }
+        // a branch whose ancestors are unknown fails here, before the block is stored
+        let change = match old_tip {
+            Some(tip) => self.tip_change(tip, block.clone())?,
+            None => TipChange {
+                disconnected: Vec::new(),
+                connected: vec![block.clone()],
+            },
+        };
+        // blocks stored before their parent arrived were never checked against
+        // their ancestors
+        for connected in &change.connected {
+            if connected.get_hash() != block.get_hash() {
+                self.validate_block(connected)?;
+            }
         }
         self.db.insert(block.get_hash(), data)?;
-        let lastheight = self.get_best_height()?;
-        if block.get_height() > lastheight {
-            self.db.insert("LAST", block.get_hash().as_bytes())?;
-            self.current_hash = block.get_hash();
-            self.db.flush()?;
+        self.db.insert("LAST", block.get_hash().as_bytes())?;
+        self.current_hash = block.get_hash();
+        self.db.flush()?;
+        Ok(change)
+    }
+
+    /// tip_change walks both tips back to their common ancestor
+    fn tip_change(&self, old_tip: Block, new_tip: Block) -> Result<TipChange> {
+        let mut change = TipChange::default();
+        let mut old = old_tip;
+        let mut new = new_tip;
+        while old.get_hash() != new.get_hash() {
+            if old.get_height() >= new.get_height() {
+                let parent = old.get_prev_hash();
+                change.disconnected.push(old);
+                if parent.is_empty() {
+                    break;
+                }
+                old = self.get_block(&parent)?;
+            } else {
+                let parent = new.get_prev_hash();
+                change.connected.push(new);
+                new = self.get_block(&parent)?;
+            }
         }
-        Ok(())
+        change.connected.reverse();
+        Ok(change)
     }
 
     // pub fn add_block(&mut self, transactions: Vec<Transaction>) -> Result<Block> {
@@ -148,51 +477,13 @@ impl Blockchain {
     //     Ok(new_block)
     // }
 
-    /// FindUnspentTransactions returns a list of transactions containing unspent outputs
-    fn find_unspent_transactions(&self, address: &[u8]) -> Vec<Transaction> {
-        let mut spent_txos: HashMap<String, Vec<i32>> = HashMap::new();
-        let mut unspend_txs: Vec<Transaction> = Vec::new();
-
-        for block in self.iter() {
-            for tx in block.get_transaction() {
-                for index in 0..tx.vout.len() {
-                    if let Some(ids) = spent_txos.get(&tx.id) {
-                        if ids.contains(&(index as i32)) {
-                            continue;
-                        }
-                    }
-
-                    if tx.vout[index].can_be_unlock_with(address) {
-                        unspend_txs.push(tx.to_owned())
-                    }
-                }
-
-                if !tx.is_coinbase() {
-                    for i in &tx.vin {
-                        if i.can_unlock_output_with(address) {
-                            match spent_txos.get_mut(&i.txid) {
-                                Some(v) => {
-                                    v.push(i.vout);
-                                }
-                                None => {
-                                    spent_txos.insert(i.txid.clone(), vec![i.vout]);
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        }
-
-        unspend_txs
-    }
-
     /// FindUTXO finds and returns all unspent transaction outputs
-    pub fn find_UTXO(&self) -> HashMap<String, TXOutputs> {
+    pub fn find_UTXO(&self) -> Result<HashMap<String, TXOutputs>> {
         let mut utxos: HashMap<String, TXOutputs> = HashMap::new();
         let mut spend_txos: HashMap<String, Vec<i32>> = HashMap::new();
 
         for block in self.iter() {
+            let block = block?;
             for tx in block.get_transaction() {
                 for index in 0..tx.vout.len() {
                     if let Some(ids) = spend_txos.get(&tx.id) {
@@ -200,20 +491,17 @@ impl Blockchain {
                             continue;
                         }
                     }
-
-                    match utxos.get_mut(&tx.id) {
-                        Some(v) => {
-                            v.outputs.push(tx.vout[index].clone());
-                        }
-                        None => {
-                            utxos.insert(
-                                tx.id.clone(),
-                                TXOutputs {
-                                    outputs: vec![tx.vout[index].clone()],
-                                },
-                            );
-                        }
+                    if tx.vout[index].is_data() {
+                        continue;
                     }
+
+                    utxos
+                        .entry(tx.id.clone())
+                        .or_insert_with(|| TXOutputs {
+                            outputs: BTreeMap::new(),
+                        })
+                        .outputs
+                        .insert(index as i32, tx.vout[index].clone());
                 }
 
                 if !tx.is_coinbase() {
@@ -230,7 +518,7 @@ impl Blockchain {
                 }
             }
         }
-        utxos
+        Ok(utxos)
     }
 
     pub fn iter(&self) -> BlockchainIter {
@@ -240,10 +528,38 @@ impl Blockchain {
         }
     }
 
+    /// FindData finds the earliest block and transaction anchoring a data output with the payload
+    pub fn find_data(&self, data: &[u8]) -> Result<Option<(Block, Transaction)>> {
+        let mut found = None;
+        for b in self.iter() {
+            let b = b?;
+            let anchor = b.get_transaction().iter().find(|tx| {
+                tx.vout
+                    .iter()
+                    .any(|out| out.script_pub_key.data_payload() == Some(data))
+            });
+            if let Some(tx) = anchor {
+                let tx = tx.clone();
+                found = Some((b, tx));
+            }
+        }
+        Ok(found)
+    }
+
     /// FindTransaction finds a transaction by its ID
     pub fn find_transaction(&self, id: &str) -> Result<Transaction> {
-        for b in self.iter() {
-            for tx in b.get_transaction() {
+        self.find_branch_transaction(&self.current_hash, id)
+    }
+
+    /// find_branch_transaction is FindTransaction on the chain ending at
+    /// `block_hash`, which need not be the best chain
+    fn find_branch_transaction(&self, block_hash: &str, id: &str) -> Result<Transaction> {
+        let iter = BlockchainIter {
+            current_hash: block_hash.to_string(),
+            bc: self,
+        };
+        for b in iter {
+            for tx in b?.get_transaction() {
                 if tx.id == id {
                     return Ok(tx.clone());
                 }
@@ -252,6 +568,142 @@ impl Blockchain {
         Err(format_err!("Transaction is not found in blockchain"))
     }
 
+    /// FindAddressTransactions returns the transactions paying to or spending
+    /// outputs locked with the script, the pending ones first and then those of
+    /// the best chain newest first, with the height of their block
+    pub fn find_address_transactions(
+        &self,
+        lock: &Script,
+        pending: &[Transaction],
+    ) -> Result<Vec<(Option<i32>, Transaction)>> {
+        let mut funded = HashSet::new();
+        let mut blocks = Vec::new();
+        for b in self.iter() {
+            blocks.push(b?);
+        }
+        let blocks_txs = blocks.iter().flat_map(|b| b.get_transaction());
+        for tx in pending.iter().chain(blocks_txs) {
+            for (n, out) in tx.vout.iter().enumerate() {
+                if out.is_locked_to(lock) {
+                    funded.insert((tx.id.clone(), n as i32));
+                }
+            }
+        }
+        let touches = |tx: &Transaction| {
+            tx.vout.iter().any(|out| out.is_locked_to(lock))
+                || tx
+                    .vin
+                    .iter()
+                    .any(|vin| funded.contains(&(vin.txid.clone(), vin.vout)))
+        };
+
+        let mut found: Vec<(Option<i32>, Transaction)> = pending
+            .iter()
+            .filter(|tx| touches(tx))
+            .map(|tx| (None, tx.clone()))
+            .collect();
+        for b in &blocks {
+            for tx in b.get_transaction() {
+                if touches(tx) {
+                    found.push((Some(b.get_height()), tx.clone()));
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    /// FindTransactionBlock returns the height and median time past of the block holding a transaction
+    pub fn find_transaction_block(&self, id: &str) -> Result<(i32, u128)> {
+        self.find_branch_transaction_block(&self.current_hash, id)
+    }
+
+    /// find_branch_transaction_block is FindTransactionBlock on the chain ending
+    /// at `block_hash`, which need not be the best chain
+    fn find_branch_transaction_block(&self, block_hash: &str, id: &str) -> Result<(i32, u128)> {
+        let iter = BlockchainIter {
+            current_hash: block_hash.to_string(),
+            bc: self,
+        };
+        for b in iter {
+            let b = b?;
+            if b.get_transaction().iter().any(|tx| tx.id == id) {
+                let mtp = self
+                    .median_time_past(&b.get_hash())?
+                    .unwrap_or(b.get_timestamp());
+                return Ok((b.get_height(), mtp));
+            }
+        }
+        Err(format_err!("Transaction is not found in blockchain"))
+    }
+
+    /// verify_block_transactions verifies the transactions of a block whose
+    /// parent is known, the outputs they spend being found earlier in the block
+    /// or along the block's own ancestry and not spent anywhere before them
+    fn verify_block_transactions(&self, block: &Block) -> Result<()> {
+        let block_txs = block.get_transaction();
+        match block_txs.first() {
+            Some(tx) if tx.is_coinbase() => {}
+            _ => {
+                return Err(format_err!(
+                    "ERROR: The first transaction of block {} is not a coinbase",
+                    block.get_hash()
+                ))
+            }
+        }
+        if block_txs[1..].iter().any(|tx| tx.is_coinbase()) {
+            return Err(format_err!(
+                "ERROR: Block {} has more than one coinbase",
+                block.get_hash()
+            ));
+        }
+        let mut spent = self.branch_spent_outputs(&block.get_prev_hash())?;
+        for tx in &block_txs[1..] {
+            for vin in &tx.vin {
+                if !spent.insert((vin.txid.clone(), vin.vout)) {
+                    return Err(format_err!(
+                        "ERROR: Transaction {} spends output {}:{} that is already spent",
+                        tx.id,
+                        vin.txid,
+                        vin.vout
+                    ));
+                }
+            }
+        }
+        for (i, tx) in block_txs.iter().enumerate().skip(1) {
+            let mut prev_txs = HashMap::new();
+            for vin in &tx.vin {
+                let prev_tx = match block_txs[..i].iter().find(|prev| prev.id == vin.txid) {
+                    Some(prev) => prev.clone(),
+                    None => self.find_branch_transaction(&block.get_prev_hash(), &vin.txid)?,
+                };
+                prev_txs.insert(prev_tx.id.clone(), prev_tx);
+            }
+            if !tx.verify(prev_txs)? {
+                return Err(format_err!("ERROR: Invalid transaction {} in block", tx.id));
+            }
+        }
+        Ok(())
+    }
+
+    /// branch_spent_outputs returns every output spent on the chain ending at `block_hash`
+    fn branch_spent_outputs(&self, block_hash: &str) -> Result<HashSet<(String, i32)>> {
+        let iter = BlockchainIter {
+            current_hash: block_hash.to_string(),
+            bc: self,
+        };
+        let mut spent = HashSet::new();
+        for b in iter {
+            for tx in b?.get_transaction() {
+                if !tx.is_coinbase() {
+                    for vin in &tx.vin {
+                        spent.insert((vin.txid.clone(), vin.vout));
+                    }
+                }
+            }
+        }
+        Ok(spent)
+    }
+
     fn get_prev_TXs(&self, tx: &Transaction) -> Result<HashMap<String, Transaction>> {
         let mut prev_txs = HashMap::new();
         for vin in &tx.vin {
@@ -261,10 +713,15 @@ impl Blockchain {
         Ok(prev_txs)
     }
 
-    /// SignTransaction signs inputs of a Transaction
-    pub fn sign_transaction(&self, tx: &mut Transaction, private_key: &[u8]) -> Result<()> {
+    /// SignTransaction signs inputs of a Transaction with the given sighash type
+    pub fn sign_transaction(
+        &self,
+        tx: &mut Transaction,
+        private_key: &[u8],
+        hash_type: u8,
+    ) -> Result<()> {
         let prev_txs = self.get_prev_TXs(tx)?;
-        tx.sign(private_key, prev_txs)?;
+        tx.sign(private_key, prev_txs, hash_type)?;
         Ok(())
     }
 
@@ -276,29 +733,118 @@ impl Blockchain {
 }
 
 impl<'a> Iterator for BlockchainIter<'a> {
-    type Item = Block;
+    type Item = Result<Block>;
 
+    /// Next walks back to the genesis block. A block that cannot be read is
+    /// yielded as an error and ends the walk
     fn next(&mut self) -> Option<Self::Item> {
-        if let Ok(encode_block) = self.bc.db.get(&self.current_hash) {
-            return match encode_block {
-                Some(b) => {
-                    if let Ok(block) = bincode::deserialize::<Block>(&b) {
-                        self.current_hash = block.get_prev_hash();
-                        Some(block)
-                    } else {
-                        None
-                    }
-                }
-                None => None,
-            };
+        let data = match self.bc.db.get(&self.current_hash) {
+            Ok(Some(data)) => data,
+            Ok(None) => return None,
+            Err(e) => {
+                self.current_hash.clear();
+                return Some(Err(e.into()));
+            }
+        };
+        match bincode::deserialize::<Block>(&data) {
+            Ok(block) => {
+                self.current_hash = block.get_prev_hash();
+                Some(Ok(block))
+            }
+            Err(e) => {
+                let hash = std::mem::take(&mut self.current_hash);
+                Some(Err(format_err!("block {} cannot be decoded: {}", hash, e)))
+            }
         }
-        None
     }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
+    use crate::transaction::SIGHASH_ALL;
+    use crate::tx::{TXInput, TXOutput, SEQUENCE_FINAL};
+    use crypto::ed25519;
+
+    pub(crate) fn test_wallet(seed: u8) -> Wallet {
+        let (secret_key, public_key) = ed25519::keypair(&[seed; 32]);
+        Wallet {
+            secret_key: secret_key.to_vec(),
+            public_key: public_key.to_vec(),
+        }
+    }
+
+    /// test_chain creates a proof-of-authority chain in a temporary database,
+    /// its genesis coinbase paying the authority
+    pub(crate) fn test_chain(authority: &Wallet) -> Blockchain {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let consensus = ConsensusConfig::ProofOfAuthority {
+            authorities: vec![authority.public_key.clone()],
+        };
+        Blockchain::init(
+            db,
+            authority.get_address(),
+            consensus,
+            Some(authority.clone()),
+        )
+        .unwrap()
+    }
+
+    pub(crate) fn coinbase(to: &Wallet, data: &str) -> Transaction {
+        Transaction::new_coinbase(to.get_address(), data.to_string()).unwrap()
+    }
+
+    /// spend pays the whole of output 0 of `prev` to `to`, signed by `owner`
+    pub(crate) fn spend(
+        bc: &Blockchain,
+        owner: &Wallet,
+        prev: &Transaction,
+        to: &Wallet,
+    ) -> Transaction {
+        let mut tx = Transaction {
+            id: String::new(),
+            vin: vec![TXInput {
+                txid: prev.id.clone(),
+                vout: 0,
+                script_sig: Script::default(),
+                sequence: SEQUENCE_FINAL,
+            }],
+            vout: vec![TXOutput::new(prev.vout[0].value, to.get_address()).unwrap()],
+            lock_time: 0,
+        };
+        tx.id = tx.hash().unwrap();
+        bc.sign_transaction(&mut tx, &owner.secret_key, SIGHASH_ALL)
+            .unwrap();
+        tx
+    }
+
+    /// next_block seals the transactions in a block on top of the tip
+    pub(crate) fn next_block(bc: &Blockchain, transactions: Vec<Transaction>) -> Block {
+        let tip = bc.get_block(&bc.current_hash).unwrap();
+        block_at(bc, transactions, tip.get_timestamp() + 1)
+    }
+
+    /// block_at seals the transactions in a block on top of the tip with the timestamp
+    pub(crate) fn block_at(
+        bc: &Blockchain,
+        transactions: Vec<Transaction>,
+        timestamp: u128,
+    ) -> Block {
+        let tip = bc.get_block(&bc.current_hash).unwrap();
+        let mut block = Block::new_block(
+            transactions,
+            tip.get_hash(),
+            tip.get_height() + 1,
+            timestamp,
+        )
+        .unwrap();
+        let ctx = SealContext {
+            threads: 1,
+            signer: bc.signer.as_ref(),
+        };
+        bc.engine.seal(&mut block, &ctx).unwrap();
+        block
+    }
 
     #[test]
     fn test_add_block() {
@@ -308,7 +854,106 @@ mod tests {
         // b.add_block("data2".to_string());
 
         for item in b.iter() {
-            println!("item: {:?}", item)
+            println!("item: {:?}", item.unwrap())
         }
     }
+
+    #[test]
+    fn test_double_spend_block() {
+        let alice = test_wallet(1);
+        let bob = test_wallet(2);
+        let mut bc = test_chain(&alice);
+        let genesis = bc.get_block(&bc.current_hash).unwrap();
+        let funding = genesis.get_transaction()[0].clone();
+        let to_bob = spend(&bc, &alice, &funding, &bob);
+        let to_alice = spend(&bc, &alice, &funding, &alice);
+
+        let block = next_block(
+            &bc,
+            vec![coinbase(&alice, "1"), to_bob.clone(), to_alice.clone()],
+        );
+        let err = bc.add_block(block).unwrap_err();
+        assert!(err.to_string().contains("already spent"), "{}", err);
+
+        let block = next_block(&bc, vec![coinbase(&alice, "1"), to_bob]);
+        bc.add_block(block).unwrap();
+        let block = next_block(&bc, vec![coinbase(&alice, "2"), to_alice]);
+        let err = bc.add_block(block).unwrap_err();
+        assert!(err.to_string().contains("already spent"), "{}", err);
+    }
+
+    #[test]
+    fn test_coinbase_position() {
+        let alice = test_wallet(1);
+        let bob = test_wallet(2);
+        let mut bc = test_chain(&alice);
+        let genesis = bc.get_block(&bc.current_hash).unwrap();
+        let payment = spend(&bc, &alice, &genesis.get_transaction()[0], &bob);
+
+        let block = next_block(&bc, vec![coinbase(&alice, "1"), coinbase(&alice, "2")]);
+        let err = bc.add_block(block).unwrap_err();
+        assert!(
+            err.to_string().contains("more than one coinbase"),
+            "{}",
+            err
+        );
+
+        let block = next_block(&bc, vec![payment.clone(), coinbase(&alice, "1")]);
+        let err = bc.add_block(block).unwrap_err();
+        assert!(err.to_string().contains("is not a coinbase"), "{}", err);
+
+        let block = next_block(&bc, vec![coinbase(&alice, "1"), payment]);
+        bc.add_block(block).unwrap();
+        assert_eq!(bc.get_best_height().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_block_height() {
+        let alice = test_wallet(1);
+        let mut bc = test_chain(&alice);
+        let tip = bc.get_block(&bc.current_hash).unwrap();
+        let ctx = SealContext {
+            threads: 1,
+            signer: Some(&alice),
+        };
+        for (prev_hash, height) in [(tip.get_hash(), 2), (tip.get_hash(), 0), (String::new(), 1)] {
+            let txs = vec![coinbase(&alice, "1")];
+            let mut block =
+                Block::new_block(txs, prev_hash, height, tip.get_timestamp() + 1).unwrap();
+            bc.engine.seal(&mut block, &ctx).unwrap();
+            let err = bc.add_block(block).unwrap_err();
+            assert!(err.to_string().contains("has height"), "{}", err);
+        }
+        let block = next_block(&bc, vec![coinbase(&alice, "1")]);
+        bc.add_block(block).unwrap();
+    }
+
+    #[test]
+    fn test_block_timestamp() {
+        let alice = test_wallet(1);
+        let mut bc = test_chain(&alice);
+        let genesis = bc.get_block(&bc.current_hash).unwrap();
+        let start = genesis.get_timestamp();
+
+        // the median of the last blocks moves up as blocks are added
+        for (i, gap) in [5, 6, 9].iter().enumerate() {
+            let block = block_at(&bc, vec![coinbase(&alice, &i.to_string())], start + gap);
+            bc.add_block(block).unwrap();
+        }
+        let mtp = bc.median_time_past(&bc.current_hash).unwrap().unwrap();
+        assert_eq!(mtp, start + 6);
+        for timestamp in [start + 5, mtp] {
+            let block = block_at(&bc, vec![coinbase(&alice, "late")], timestamp);
+            let err = bc.add_block(block).unwrap_err();
+            assert!(err.to_string().contains("median time past"), "{}", err);
+        }
+
+        let later = now_millis().unwrap() + MAX_FUTURE_BLOCK_TIME + 60 * 1000;
+        let block = block_at(&bc, vec![coinbase(&alice, "early")], later);
+        let err = bc.add_block(block.clone()).unwrap_err();
+        assert!(err.to_string().contains("too far in the future"), "{}", err);
+        // peers agreeing the clock is behind make the block acceptable
+        bc.set_time_offset(2 * 60 * 1000);
+        bc.add_block(block).unwrap();
+    }
 }
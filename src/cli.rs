@@ -1,14 +1,37 @@
 use std::process::exit;
 
-use bitcoincash_addr::Address;
-use clap::{arg, Command};
+use clap::{arg, Arg, ArgAction, ArgMatches, Command};
+use failure::format_err;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde_json::{json, Value};
 
+use crate::address::{set_network, Address, Network};
+use crate::amount::Amount;
+use crate::block::{now_millis, Block};
 use crate::blockchain::Blockchain;
+use crate::coinselect::CoinSelection;
+use crate::consensus::ConsensusConfig;
 use crate::errors::Result;
+use crate::psbt::PartiallySignedTransaction;
+use crate::rpc::{
+    accounts_json, addresses_json, anchor_json, parse_amount, swap_json, unspent_json, RpcClient,
+    RpcConfig, DEFAULT_RPC_BIND,
+};
+use crate::script::{sha256_hash, Htlc};
 use crate::server::Server;
-use crate::transaction::Transaction;
+use crate::transaction::{
+    SendOptions, Transaction, SIGHASH_ALL, SIGHASH_ANYONECANPAY, SIGHASH_NONE, SIGHASH_SINGLE,
+};
+use crate::tx::address_to_script;
 use crate::utxoset::UTXOSet;
-use crate::wallet::Wallets;
+use crate::wallet::{
+    key_address, verify_message, write_private_file, AddressLabel, Wallets, DEFAULT_WALLET,
+};
+
+const SWAP_SECRET_LEN: usize = 32;
+/// SWAP_PARTICIPANT_TIMEOUT is the default refund delay (ms) of a participating contract
+const SWAP_PARTICIPANT_TIMEOUT: u128 = 24 * 60 * 60 * 1000;
 
 pub struct Cli {}
 
@@ -22,41 +45,333 @@ impl Cli {
             .version("0.1")
             .author("qiao@gmail.com")
             .about("blockchain in rust: a simple blockchain for learning")
+            .arg(
+                arg!(--wallet <NAME> "'loaded wallet the command uses, the default wallet if omitted'")
+                    .global(true),
+            )
+            .arg(
+                arg!(--network <NETWORK> "'network whose addresses are created and accepted, the network of the chain if omitted'")
+                    .value_parser(["main", "test", "regtest"])
+                    .global(true),
+            )
             .subcommand(Command::new("printchain").about("print all the chain blocks"))
-            .subcommand(Command::new("createwallet").about("create a wallet"))
-            .subcommand(Command::new("listaddresses").about("list all the address in the wallet"))
+            .subcommand(
+                Command::new("createwallet")
+                    .about("create a wallet address")
+                    .arg(arg!(--name <NAME> "'create the address in this named wallet, creating and loading it'"))
+                    .arg(arg!(--label <LABEL> "'label of the address'"))
+                    .arg(arg!(--account <ACCOUNT> "'account the address is grouped under'")),
+            )
+            .subcommand(
+                Command::new("loadwallet")
+                    .about("load a named wallet so commands can select it with --wallet")
+                    .arg(arg!(<NAME>"'wallet name'")),
+            )
+            .subcommand(
+                Command::new("unloadwallet")
+                    .about("unload a named wallet")
+                    .arg(arg!(<NAME>"'wallet name'")),
+            )
+            .subcommand(Command::new("listwallets").about("list the loaded wallets"))
+            .subcommand(
+                Command::new("setlabel")
+                    .about("set the label of a wallet address")
+                    .arg(arg!(<ADDRESS>"'wallet address'"))
+                    .arg(arg!(<LABEL>"'label'")),
+            )
+            .subcommand(
+                Command::new("setaccount")
+                    .about("group a wallet address under an account")
+                    .arg(arg!(<ADDRESS>"'wallet address'"))
+                    .arg(arg!(<ACCOUNT>"'account'")),
+            )
+            .subcommand(Command::new("listaccounts").about("list the accounts of the wallet and their balances"))
+            .subcommand(
+                Command::new("restorewallet")
+                    .about("restore the wallet addresses from a mnemonic")
+                    .arg(arg!(<MNEMONIC>"'the words of the mnemonic'").num_args(1..)),
+            )
+            .subcommand(
+                Command::new("listaddresses")
+                    .about("list all the address in the wallet")
+                    .arg(arg!(--account <ACCOUNT> "'only list the addresses of this account'")),
+            )
+            .subcommand(
+                Command::new("encryptwallet")
+                    .about("encrypt the wallet keys with a passphrase read from stdin"),
+            )
+            .subcommand(
+                Command::new("walletpassphrase")
+                    .about("unlock the wallet keys with the passphrase read from stdin")
+                    .arg(arg!(<TIMEOUT>"'seconds the wallet stays unlocked'")),
+            )
+            .subcommand(Command::new("walletlock").about("lock the wallet keys"))
+            .subcommand(
+                Command::new("dumpprivkey")
+                    .about("print the private key of a wallet address")
+                    .arg(arg!(<ADDRESS>"'wallet address'")),
+            )
+            .subcommand(
+                Command::new("importprivkey")
+                    .about("import a private key and rescan the blockchain")
+                    .arg(arg!(<PRIVKEY>"'hex ed25519 private key, as printed by dumpprivkey'")),
+            )
+            .subcommand(
+                Command::new("importaddress")
+                    .about("watch an address without its private key and rescan the blockchain")
+                    .arg(arg!(<ADDRESS>"'key address to watch'")),
+            )
+            .subcommand(
+                Command::new("backupwallet")
+                    .about("write the seed, keys and addresses of the wallet to a text file")
+                    .arg(arg!(<FILE>"'backup file'")),
+            )
+            .subcommand(
+                Command::new("importwallet")
+                    .about("import a backup written by backupwallet and rescan the blockchain")
+                    .arg(arg!(<FILE>"'backup file'")),
+            )
             .subcommand(Command::new("reindex").about("reindex the blockchain"))
+            .subcommand(
+                Command::new("rescan")
+                    .about("rebuild the wallet coins and transactions from the blockchain")
+                    .arg(arg!([FROM_HEIGHT]"'height to rescan from, the genesis block by default'")),
+            )
+            .subcommand(
+                Command::new("listunspent")
+                    .about("list the unspent coins of the wallet")
+                    .arg(arg!([ADDRESS]"'only list the coins of this address'")),
+            )
             .subcommand(
                 Command::new("getbalance")
-                    .about("get balance in the blochain")
-                    .arg(arg!(<ADDRESS>"'The Address it get balance for'")),
+                    .about("get balance in the blochain, of the whole wallet without an address")
+                    .arg(arg!([ADDRESS]"'The Address it get balance for'"))
+                    .arg(arg!(--account <ACCOUNT> "'get the balance of an account of the wallet'")),
             )
             .subcommand(
                 Command::new("startnode")
                     .about("start the node server")
-                    .arg(arg!(<PORT>"'The port server bind to locally'")),
+                    .arg(arg!(<PORT>"'The port server bind to locally'"))
+                    .args(rpc_args()),
             )
             .subcommand(
                 Command::new("create")
                     .about("Create new blochain")
-                    .arg(arg!(<ADDRESS>"'The Address to send gensis block reqward to' ")),
+                    .arg(arg!(<ADDRESS>"'The Address to send gensis block reqward to' "))
+                    .arg(
+                        arg!(-c --consensus <ENGINE> "'consensus engine: pow or poa'")
+                            .value_parser(["pow", "poa"])
+                            .default_value("pow"),
+                    )
+                    .arg(
+                        arg!(-a --authority <KEY> "'authority public key (hex) or wallet address for poa'")
+                            .action(ArgAction::Append),
+                    ),
             )
             .subcommand(
                 Command::new("send")
                     .about("send in the blockchain")
                     .arg(arg!(<FROM>" 'Source wallet address'"))
                     .arg(arg!(<TO>" 'Destination wallet address'"))
-                    .arg(arg!(<AMOUNT>" 'Destination wallet address'"))
+                    .arg(arg!(<AMOUNT>" 'Amount to send in coins, up to 8 decimals'"))
+                    .arg(arg!(-m --mine " 'the from address mine immediately'"))
+                    .arg(arg!(-t --threads <THREADS> " 'number of proof-of-work threads'"))
+                    .arg(arg!(--locktime <LOCKTIME> " 'height or timestamp (ms) before which the transaction cannot be mined'"))
+                    .arg(arg!(--"lock-until" <LOCKTIME> " 'height or timestamp (ms) before which the recipient cannot spend the payment'"))
+                    .arg(
+                        arg!(--sighash <TYPE> " 'outputs the signatures commit to: all, none or single'")
+                            .value_parser(["all", "none", "single"])
+                            .default_value("all"),
+                    )
+                    .arg(arg!(--anyonecanpay " 'sign only our own inputs so others can add theirs'"))
+                    .arg(
+                        arg!(--"coin-selection" <STRATEGY> " 'how coins are chosen: bnb, largest, smallest or random'")
+                            .value_parser(["bnb", "largest", "smallest", "random"])
+                            .default_value("bnb"),
+                    )
+                    .arg(
+                        arg!(--coin <OUTPOINT> " 'spend exactly this TXID:VOUT, may be repeated'")
+                            .action(ArgAction::Append),
+                    ),
+            )
+            .subcommand(
+                Command::new("sendmany")
+                    .about("pay many recipients in one transaction")
+                    .arg(arg!(<FROM>" 'Source wallet address'"))
+                    .arg(
+                        arg!([PAYMENTS]" 'ADDRESS=AMOUNT pairs'")
+                            .num_args(1..),
+                    )
+                    .arg(arg!(-f --file <FILE> " 'CSV (address,amount per line) or JSON file of payments'"))
+                    .arg(arg!(-m --mine " 'the from address mine immediately'"))
+                    .arg(arg!(-t --threads <THREADS> " 'number of proof-of-work threads'"))
+                    .arg(
+                        arg!(--"coin-selection" <STRATEGY> " 'how coins are chosen: bnb, largest, smallest or random'")
+                            .value_parser(["bnb", "largest", "smallest", "random"])
+                            .default_value("bnb"),
+                    )
+                    .arg(
+                        arg!(--coin <OUTPOINT> " 'spend exactly this TXID:VOUT, may be repeated'")
+                            .action(ArgAction::Append),
+                    ),
+            )
+            .subcommand(
+                Command::new("senddata")
+                    .about("anchor data in an unspendable output")
+                    .arg(arg!(<FROM>" 'Source wallet address'"))
+                    .arg(arg!(<HEXDATA>" 'Data to anchor (hex), at most 80 bytes'"))
+                    .arg(arg!(-m --mine " 'the from address mine immediately'")),
+            )
+            .subcommand(
+                Command::new("finddata")
+                    .about("find the block that anchored data")
+                    .arg(arg!(<HEXDATA>" 'Anchored data (hex)'")),
+            )
+            .subcommand(
+                Command::new("validateaddress")
+                    .about("check an address and print its network, type and hash")
+                    .arg(arg!(<ADDRESS>"'address to check'")),
+            )
+            .subcommand(
+                Command::new("signmessage")
+                    .about("sign a message with the key of a wallet address to prove owning it")
+                    .arg(arg!(<ADDRESS>"'wallet address'"))
+                    .arg(arg!(<MESSAGE>"'message to sign'")),
+            )
+            .subcommand(
+                Command::new("verifymessage")
+                    .about("check a message signature made by signmessage")
+                    .arg(arg!(<ADDRESS>"'address that signed'"))
+                    .arg(arg!(<SIGNATURE>"'signature (hex)'"))
+                    .arg(arg!(<MESSAGE>"'message that was signed'")),
+            )
+            .subcommand(
+                Command::new("getpubkey")
+                    .about("print the public key of a wallet address")
+                    .arg(arg!(<ADDRESS>"'wallet address'")),
+            )
+            .subcommand(
+                Command::new("createmultisig")
+                    .about("create an M-of-N multisig address")
+                    .arg(arg!(<NREQUIRED>"'number of signatures required'"))
+                    .arg(
+                        arg!(<KEYS>"'public keys (hex) or wallet addresses of the participants'")
+                            .num_args(1..),
+                    ),
+            )
+            .subcommand(
+                Command::new("sendmultisig")
+                    .about("create a transaction spending from a multisig address for cosigning")
+                    .arg(arg!(<FROM>" 'Source multisig address'"))
+                    .arg(arg!(<TO>" 'Destination wallet address'"))
+                    .arg(arg!(<AMOUNT>" 'Amount to send'"))
+                    .arg(arg!(<FILE>" 'File the partially signed transaction is written to'")),
+            )
+            .subcommand(
+                Command::new("createpsbt")
+                    .about("create an unsigned transaction for offline signing")
+                    .arg(arg!(<FROM>" 'Source key address, or multisig address of the wallet'"))
+                    .arg(arg!(<FILE>" 'File the partially signed transaction is written to'"))
+                    .arg(
+                        arg!([PAYMENTS]" 'ADDRESS=AMOUNT pairs'")
+                            .num_args(1..),
+                    )
+                    .arg(arg!(-f --file <FILE> " 'CSV (address,amount per line) or JSON file of payments'"))
+                    .arg(
+                        arg!(--"coin-selection" <STRATEGY> " 'how coins are chosen: bnb, largest, smallest or random'")
+                            .value_parser(["bnb", "largest", "smallest", "random"])
+                            .default_value("bnb"),
+                    )
+                    .arg(
+                        arg!(--coin <OUTPOINT> " 'spend exactly this TXID:VOUT, may be repeated'")
+                            .action(ArgAction::Append),
+                    ),
+            )
+            .subcommand(
+                Command::new("signpsbt")
+                    .about("add the signatures of the wallet keys, without needing the blockchain")
+                    .arg(arg!(<FILE>" 'File holding the partially signed transaction'")),
+            )
+            .subcommand(
+                Command::new("combinepsbt")
+                    .about("merge the signatures of copies of a partially signed transaction")
+                    .arg(arg!(<OUT>" 'File the combined transaction is written to'"))
+                    .arg(arg!(<FILES>" 'Files holding the copies'").num_args(1..)),
+            )
+            .subcommand(
+                Command::new("finalizepsbt")
+                    .about("build the signed transaction once every input has its signatures")
+                    .arg(arg!(<FILE>" 'File holding the partially signed transaction'"))
+                    .arg(arg!(<TXFILE>" 'File the signed transaction is written to'")),
+            )
+            .subcommand(
+                Command::new("broadcast")
+                    .about("verify a signed transaction and send it to the network")
+                    .arg(arg!(<TXFILE>" 'File holding the signed transaction'"))
+                    .arg(arg!(-m --mine <ADDRESS> " 'mine the transaction immediately, rewarding ADDRESS'")),
+            )
+            .subcommand(
+                Command::new("cosign")
+                    .about("add the signatures of local wallets to a multisig transaction")
+                    .arg(arg!(<FILE>" 'File holding the partially signed transaction'"))
+                    .arg(arg!(-s --send " 'send the transaction once it is fully signed'"))
+                    .arg(arg!(-m --mine <ADDRESS> " 'mine the fully signed transaction immediately, rewarding ADDRESS'")),
+            )
+            .subcommand(
+                Command::new("initiateswap")
+                    .about("lock coins in a hash time-locked contract paying the participant")
+                    .arg(arg!(<FROM>" 'Source wallet address'"))
+                    .arg(arg!(<PARTICIPANT>" 'Address that can redeem the contract with the secret'"))
+                    .arg(arg!(<AMOUNT>" 'Amount to lock'"))
+                    .arg(arg!(--"secret-hash" <HASH> " 'sha256 of the secret (hex) when participating in a swap'"))
+                    .arg(arg!(--timeout <LOCKTIME> " 'height or timestamp (ms) after which the contract can be refunded'"))
                     .arg(arg!(-m --mine " 'the from address mine immediately'")),
             )
+            .subcommand(
+                Command::new("redeemswap")
+                    .about("redeem a swap contract paying a local wallet with the secret")
+                    .arg(arg!(<CONTRACT>" 'Id of the contract transaction'"))
+                    .arg(arg!(<SECRET>" 'Secret (hex) whose sha256 is the contract secret hash'"))
+                    .arg(arg!(-m --mine " 'the redeeming address mine immediately'")),
+            )
+            .subcommand(
+                Command::new("refundswap")
+                    .about("refund a swap contract to a local wallet after its timeout")
+                    .arg(arg!(<CONTRACT>" 'Id of the contract transaction'"))
+                    .arg(arg!(-m --mine " 'the refunding address mine immediately'")),
+            )
+            .subcommand(
+                Command::new("auditswap")
+                    .about("print the terms of a swap contract and any secret revealed by its redemption")
+                    .arg(arg!(<CONTRACT>" 'Id of the contract transaction'")),
+            )
             .subcommand(
                 Command::new("startminer")
                     .about("start the miner server")
                     .arg(arg!(<PORT>"'The port server bind to locally'"))
-                    .arg(arg!(<ADDRESS>"'wallet address' ")),
+                    .arg(arg!(<ADDRESS>"'wallet address' "))
+                    .arg(arg!(-t --threads <THREADS> " 'number of proof-of-work threads'"))
+                    .args(rpc_args()),
             )
             .get_matches();
 
+        // commands the running node serves go through it, it holds the databases
+        let node = RpcClient::connect();
+        set_network(cmd_network(&matches, node.as_ref())?)?;
+
+        let wallet = match matches.get_one::<String>("wallet") {
+            Some(name) => {
+                if !Wallets::loaded()?.contains(name) {
+                    return Err(format_err!(
+                        "wallet {} is not loaded, load it with loadwallet",
+                        name
+                    ));
+                }
+                name.as_str()
+            }
+            None => DEFAULT_WALLET,
+        };
+
         if let Some(ref matches) = matches.subcommand_matches("startminer") {
             let port = if let Some(port) = matches.get_one::<String>("PORT") {
                 port
@@ -70,9 +385,26 @@ impl Cli {
                 println!("ADDRESS not supply!: usage");
                 exit(1);
             };
-            let bc = Blockchain::new()?;
+            let mut bc = Blockchain::new()?;
+            if let Some(threads) = parse_threads(matches)? {
+                bc.set_mining_threads(threads);
+            }
+            // the node keeps the loaded wallets open, the signer is taken from them
+            let loaded = Wallets::open_loaded()?;
+            let signer = match loaded.get(wallet) {
+                Some(wallets) => wallets.get_signing_wallet(address).ok().cloned(),
+                None => Wallets::new(wallet)?
+                    .get_signing_wallet(address)
+                    .ok()
+                    .cloned(),
+            };
+            // a locked wallet cannot seal proof of authority blocks
+            if let Some(signer) = signer {
+                bc.set_signer(signer);
+            }
             let utxo_set = UTXOSet { blockchain: bc };
-            let server = Server::new(port, address, utxo_set)?;
+            let server = Server::new(port, address, utxo_set, loaded)?;
+            server.start_rpc(parse_rpc_config(matches));
             server.start_server()?;
         }
 
@@ -80,17 +412,233 @@ impl Cli {
             if let Some(port) = matches.get_one::<String>("PORT") {
                 let bc = Blockchain::new()?;
                 let utxo_set = UTXOSet { blockchain: bc };
-                let server = Server::new(port, "", utxo_set)?;
+                let server = Server::new(port, "", utxo_set, Wallets::open_loaded()?)?;
+                server.start_rpc(parse_rpc_config(matches));
                 server.start_server()?;
             }
         }
 
-        if let Some(_) = matches.subcommand_matches("createwallet") {
+        if let Some(_) = matches.subcommand_matches("printchain") {
+            match &node {
+                Some(node) => {
+                    let best_height = node.call(None, "getblockcount", Vec::new())?;
+                    for height in (0..=best_height.as_i64().unwrap_or(-1)).rev() {
+                        let hash = node.call(None, "getblockhash", vec![json!(height)])?;
+                        let block = node.call(None, "getblock", vec![hash, json!(0)])?;
+                        let block: Block = bincode::deserialize(&hex::decode(json_str(&block))?)?;
+                        println!("block: {:#?}", block);
+                    }
+                }
+                None => {
+                    let bc = Blockchain::new()?;
+                    for b in bc.iter() {
+                        println!("block: {:#?}", b?);
+                    }
+                }
+            }
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("createwallet") {
             // let mut ws = Wallets::new()?;
             // let address = ws.create_wallet();
             // ws.save_all()?;
             // println!("success: address: {}", address);
-            println!("address: {}", cmd_create_wallet()?);
+            let wallet = match matches.get_one::<String>("name") {
+                Some(name) => {
+                    if !Wallets::exists(name)? {
+                        match &node {
+                            Some(node) => {
+                                node.call(None, "createwallet", vec![json!(name)])?;
+                            }
+                            None => {
+                                Wallets::new(name)?;
+                                Wallets::load(name)?;
+                            }
+                        }
+                        println!("created wallet {}", name);
+                    } else if !Wallets::loaded()?.contains(name) {
+                        return Err(format_err!(
+                            "wallet {} is not loaded, load it with loadwallet",
+                            name
+                        ));
+                    }
+                    name.as_str()
+                }
+                None => wallet,
+            };
+            let label = AddressLabel {
+                label: matches
+                    .get_one::<String>("label")
+                    .cloned()
+                    .unwrap_or_default(),
+                account: matches
+                    .get_one::<String>("account")
+                    .cloned()
+                    .unwrap_or_default(),
+            };
+            let (address, mnemonic) = match &node {
+                Some(node) => {
+                    let params = vec![json!(label.label), json!(label.account)];
+                    let created = node.call(Some(wallet), "getnewaddress", params)?;
+                    let address = created["address"].as_str().unwrap_or_default().to_string();
+                    (address, created["mnemonic"].as_str().map(String::from))
+                }
+                None => cmd_create_wallet(wallet, &label)?,
+            };
+            if let Some(mnemonic) = mnemonic {
+                println!("mnemonic: {}", mnemonic);
+                println!("write the mnemonic down, restorewallet recreates every address from it");
+            }
+            println!("address: {}", address);
+            println!("bech32: {}", Address::decode(&address)?.encode_bech32());
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("loadwallet") {
+            if let Some(name) = matches.get_one::<String>("NAME") {
+                if let Some(node) = &node {
+                    node.call(None, "loadwallet", vec![json!(name)])?;
+                } else {
+                    Wallets::load(name)?;
+                    // catch up with the blocks connected while the wallet was not loaded
+                    let bc = Blockchain::new()?;
+                    let mut wallets = Wallets::new(name)?;
+                    wallets.rescan(&bc, 0)?;
+                    wallets.save_all()?;
+                }
+                println!("wallet {} loaded", name);
+            }
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("unloadwallet") {
+            if let Some(name) = matches.get_one::<String>("NAME") {
+                match &node {
+                    Some(node) => {
+                        node.call(None, "unloadwallet", vec![json!(name)])?;
+                    }
+                    None => Wallets::unload(name)?,
+                }
+                println!("wallet {} unloaded", name);
+            }
+        }
+
+        if let Some(_) = matches.subcommand_matches("listwallets") {
+            for name in Wallets::loaded()? {
+                println!("wallet: {}", name);
+            }
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("setlabel") {
+            let address = matches.get_one::<String>("ADDRESS").unwrap();
+            let label = matches.get_one::<String>("LABEL").unwrap();
+            if let Some(node) = &node {
+                node.call(Some(wallet), "setlabel", vec![json!(address), json!(label)])?;
+            } else {
+                let mut wallets = Wallets::new(wallet)?;
+                wallets.set_label(address, label)?;
+                wallets.save_all()?;
+            }
+            println!("success!");
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("setaccount") {
+            let address = matches.get_one::<String>("ADDRESS").unwrap();
+            let account = matches.get_one::<String>("ACCOUNT").unwrap();
+            if let Some(node) = &node {
+                node.call(
+                    Some(wallet),
+                    "setaccount",
+                    vec![json!(address), json!(account)],
+                )?;
+            } else {
+                let mut wallets = Wallets::new(wallet)?;
+                wallets.set_account(address, account)?;
+                wallets.save_all()?;
+            }
+            println!("success!");
+        }
+
+        if let Some(_) = matches.subcommand_matches("listaccounts") {
+            let accounts = match &node {
+                Some(node) => node.call(Some(wallet), "listaccounts", Vec::new())?,
+                None => accounts_json(&Wallets::new(wallet)?)?,
+            };
+            for account in accounts.as_array().into_iter().flatten() {
+                println!(
+                    "account: '{}' {}",
+                    json_str(&account["account"]),
+                    json_str(&account["balance"])
+                );
+            }
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("restorewallet") {
+            let mnemonic = match matches.get_many::<String>("MNEMONIC") {
+                Some(words) => words.map(|w| w.as_str()).collect::<Vec<&str>>().join(" "),
+                None => {
+                    println!("MNEMONIC not supply!: usage");
+                    exit(1);
+                }
+            };
+            let restored = match &node {
+                Some(node) => node.call(Some(wallet), "restorewallet", vec![json!(mnemonic)])?,
+                None => {
+                    let bc = Blockchain::new()?;
+                    let mut wallets = unlocked_wallets(wallet)?;
+                    let restored = wallets.restore_seed(&mnemonic, &bc)?;
+                    wallets.save_all()?;
+                    json!({"restored": restored, "addresses": wallets.get_all_address()})
+                }
+            };
+            for address in restored["addresses"].as_array().into_iter().flatten() {
+                println!("address: {}", json_str(address));
+            }
+            println!("restored {} addresses", restored["restored"]);
+        }
+
+        if let Some(_) = matches.subcommand_matches("encryptwallet") {
+            if node.is_none() && Wallets::new(wallet)?.is_encrypted() {
+                return Err(format_err!("the wallet is already encrypted"));
+            }
+            let passphrase = read_passphrase("passphrase: ")?;
+            if read_passphrase("repeat passphrase: ")? != passphrase {
+                return Err(format_err!("the passphrases do not match"));
+            }
+            match &node {
+                Some(node) => {
+                    node.call(Some(wallet), "encryptwallet", vec![json!(passphrase)])?;
+                }
+                None => {
+                    Wallets::new(wallet)?.encrypt_wallet(&passphrase)?;
+                }
+            }
+            println!("wallet encrypted, unlock it with walletpassphrase to sign transactions");
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("walletpassphrase") {
+            let timeout: u64 = match matches.get_one::<String>("TIMEOUT") {
+                Some(timeout) => timeout.parse()?,
+                None => {
+                    println!("TIMEOUT not supply!: usage");
+                    exit(1);
+                }
+            };
+            // the keys stay unlocked in the memory of the node, never on disk
+            let node = node.as_ref().ok_or_else(|| {
+                format_err!("the wallet stays unlocked only in a running node, start one with startnode; without a node commands that sign ask for the passphrase")
+            })?;
+            let params = vec![json!(read_passphrase("passphrase: ")?), json!(timeout)];
+            node.call(Some(wallet), "walletpassphrase", params)?;
+            println!("wallet unlocked for {} seconds", timeout);
+        }
+
+        if let Some(_) = matches.subcommand_matches("walletlock") {
+            match &node {
+                Some(node) => {
+                    node.call(Some(wallet), "walletlock", Vec::new())?;
+                }
+                None => Wallets::new(wallet)?.lock()?,
+            }
+            println!("wallet locked");
         }
 
         if let Some(_) = matches.subcommand_matches("reindex") {
@@ -102,50 +650,291 @@ impl Cli {
             //     "Done! There are {} transactions in the blockchain UTXO set",
             //     count
             // );
-            let count = cmd_reindex()?;
+            let count = match &node {
+                Some(node) => {
+                    let count = node.call(None, "reindex", Vec::new())?;
+                    count.as_i64().unwrap_or_default() as i32
+                }
+                None => cmd_reindex()?,
+            };
             println!("Done! There are {} transactions in the UTXO set.", count);
         }
 
-        if let Some(_) = matches.subcommand_matches("listaddresses") {
-            let ws = Wallets::new()?;
-            let addresses = ws.get_all_address();
-            println!("addresses: {:?}", addresses);
-            for ad in addresses {
-                println!("address: {}", ad);
+        if let Some(ref matches) = matches.subcommand_matches("rescan") {
+            let from_height: i32 = match matches.get_one::<String>("FROM_HEIGHT") {
+                Some(height) => height.parse()?,
+                None => 0,
+            };
+            let blocks = match &node {
+                Some(node) => {
+                    let blocks = node.call(Some(wallet), "rescan", vec![json!(from_height)])?;
+                    blocks.as_u64().unwrap_or_default() as usize
+                }
+                None => {
+                    let bc = Blockchain::new()?;
+                    let mut wallets = Wallets::new(wallet)?;
+                    let blocks = wallets.rescan(&bc, from_height)?;
+                    wallets.save_all()?;
+                    blocks
+                }
+            };
+            println!("Rescanned {} blocks from height {}", blocks, from_height);
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("listunspent") {
+            let address = matches.get_one::<String>("ADDRESS").map(|a| a.as_str());
+            let coins = match &node {
+                Some(node) => node.call(Some(wallet), "listunspent", vec![json!(address)])?,
+                None => {
+                    let best_height = Blockchain::new()?.get_best_height()?;
+                    unspent_json(&Wallets::new(wallet)?, address, best_height)
+                }
+            };
+            for coin in coins.as_array().into_iter().flatten() {
+                println!(
+                    "{}:{} {} {} confirmations: {}{}",
+                    json_str(&coin["txid"]),
+                    coin["vout"],
+                    json_str(&coin["address"]),
+                    json_str(&coin["value"]),
+                    coin["confirmations"],
+                    if coin["watchonly"] == json!(true) {
+                        " (watch-only)"
+                    } else {
+                        ""
+                    }
+                );
+            }
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("listaddresses") {
+            let account = matches.get_one::<String>("account").map(|a| a.as_str());
+            let listed = match &node {
+                Some(node) => node.call(Some(wallet), "listaddresses", vec![json!(account)])?,
+                None => addresses_json(&Wallets::new(wallet)?, account),
+            };
+            let addresses: Vec<&Value> = listed["addresses"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .collect();
+            if account.is_some() {
+                for ad in addresses {
+                    println!(
+                        "address: {} label: '{}'",
+                        json_str(&ad["address"]),
+                        json_str(&ad["label"])
+                    );
+                }
+            } else {
+                let names: Vec<&str> = addresses
+                    .iter()
+                    .map(|ad| json_str(&ad["address"]))
+                    .collect();
+                println!("addresses: {:?}", names);
+                for ad in addresses {
+                    println!(
+                        "address: {} label: '{}' account: '{}'",
+                        json_str(&ad["address"]),
+                        json_str(&ad["label"]),
+                        json_str(&ad["account"])
+                    );
+                }
+                for ad in listed["watchonly"].as_array().into_iter().flatten() {
+                    println!("address: {} (watch-only)", json_str(ad));
+                }
+            }
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("dumpprivkey") {
+            if let Some(address) = matches.get_one::<String>("ADDRESS") {
+                let private_key = match &node {
+                    Some(node) => {
+                        let key = node.call(Some(wallet), "dumpprivkey", vec![json!(address)])?;
+                        json_str(&key).to_string()
+                    }
+                    None => hex::encode(unlocked_wallets(wallet)?.dump_private_key(address)?),
+                };
+                println!("private key: {}", private_key);
+            }
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("importprivkey") {
+            if let Some(private_key) = matches.get_one::<String>("PRIVKEY") {
+                let address = match &node {
+                    Some(node) => {
+                        let address =
+                            node.call(Some(wallet), "importprivkey", vec![json!(private_key)])?;
+                        json_str(&address).to_string()
+                    }
+                    None => {
+                        let private_key = hex::decode(private_key)
+                            .map_err(|_| format_err!("the private key must be hex"))?;
+                        let wallets = unlocked_wallets(wallet)?;
+                        cmd_import(wallets, |wallets| wallets.import_private_key(&private_key))?
+                    }
+                };
+                println!("address: {}", address);
+            }
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("importaddress") {
+            if let Some(address) = matches.get_one::<String>("ADDRESS") {
+                match &node {
+                    Some(node) => {
+                        node.call(Some(wallet), "importaddress", vec![json!(address)])?;
+                    }
+                    None => cmd_import(Wallets::new(wallet)?, |wallets| {
+                        wallets.import_address(address)
+                    })?,
+                }
+                println!("watching {}", address);
+            }
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("backupwallet") {
+            if let Some(file) = matches.get_one::<String>("FILE") {
+                let dump = match &node {
+                    Some(node) => {
+                        json_str(&node.call(Some(wallet), "dumpwallet", Vec::new())?).to_string()
+                    }
+                    None => unlocked_wallets(wallet)?.dump()?,
+                };
+                write_private_file(file, dump.as_bytes())?;
+                println!("wallet written to {}", file);
+            }
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("importwallet") {
+            if let Some(file) = matches.get_one::<String>("FILE") {
+                let text = std::fs::read_to_string(file)?;
+                let entries = match &node {
+                    Some(node) => {
+                        let entries = node.call(Some(wallet), "importwallet", vec![json!(text)])?;
+                        entries.as_u64().unwrap_or_default() as usize
+                    }
+                    None => cmd_import(unlocked_wallets(wallet)?, |wallets| {
+                        wallets.import_dump(&text)
+                    })?,
+                };
+                println!("imported {} entries", entries);
             }
         }
 
         if let Some(ref matches) = matches.subcommand_matches("create") {
+            if node.is_some() {
+                return Err(format_err!(
+                    "the running node holds the chain, stop it to create a new one"
+                ));
+            }
             if let Some(address) = matches.get_one::<String>("ADDRESS") {
                 let address = String::from(address);
+                let consensus = matches.get_one::<String>("consensus").map(|s| s.as_str());
+                // the loaded wallets are opened once, to sign and then to rescan the new chain
+                let mut loaded = Wallets::open_loaded()?;
+                let mut unloaded;
+                let wallets = match loaded.get_mut(wallet) {
+                    Some(wallets) => wallets,
+                    None => {
+                        unloaded = Wallets::new(wallet)?;
+                        &mut unloaded
+                    }
+                };
+                if consensus == Some("poa") && wallets.get_wallet(&address).is_some() {
+                    unlock_for_command(wallets)?;
+                }
+                let signer = wallets.get_wallet(&address).cloned();
+                let consensus = match consensus {
+                    Some("poa") => {
+                        if signer.is_some() {
+                            // sealing the genesis block needs the secret key
+                            wallets.get_signing_wallet(&address)?;
+                        }
+                        let mut authorities = Vec::new();
+                        if let Some(wallet) = &signer {
+                            authorities.push(wallet.public_key.clone());
+                        }
+                        if let Some(keys) = matches.get_many::<String>("authority") {
+                            for key in keys {
+                                let public_key = wallets.resolve_public_key(key)?;
+                                if !authorities.contains(&public_key) {
+                                    authorities.push(public_key);
+                                }
+                            }
+                        }
+                        ConsensusConfig::ProofOfAuthority { authorities }
+                    }
+                    _ => ConsensusConfig::ProofOfWork,
+                };
                 // Blockchain::create_blockchain(address.clone())?;
-                let bc = Blockchain::create_blockchain(address.clone())?;
+                let bc = Blockchain::create_blockchain(address.clone(), consensus, signer)?;
                 let utxo_set = UTXOSet { blockchain: bc };
                 utxo_set.reindex()?;
+                // the wallets forget the coins of the chain they had before
+                for wallets in loaded.values_mut() {
+                    wallets.rescan(&utxo_set.blockchain, 0)?;
+                    wallets.save_all()?;
+                }
                 println!("create blockchain");
             }
         }
         if let Some(ref matches) = matches.subcommand_matches("getbalance") {
+            if matches.get_one::<String>("ADDRESS").is_none() {
+                let account = matches.get_one::<String>("account");
+                let balance = match &node {
+                    Some(node) => {
+                        let params = vec![Value::Null, json!(account)];
+                        Some(parse_amount(&node.call(
+                            Some(wallet),
+                            "getbalance",
+                            params,
+                        )?)?)
+                    }
+                    None => Wallets::new(wallet)?.total_balance(account.map(|a| a.as_str())),
+                }
+                .ok_or_else(|| format_err!("balance overflows the amount range"))?;
+                match account {
+                    Some(account) => println!(
+                        "Balance of account '{}' of wallet {}; {} ",
+                        account, wallet, balance
+                    ),
+                    None => println!("Balance of wallet {}; {} ", wallet, balance),
+                }
+            }
             if let Some(address) = matches.get_one::<String>("ADDRESS") {
-                let pub_key_hash = Address::decode(address).unwrap().body;
-                let bc = Blockchain::new()?;
-                // let utxos = bc.find_UTXO(&pub_key_hash);
-                let utxo_set = UTXOSet { blockchain: bc };
-                let utxos = utxo_set.find_UTXO(&pub_key_hash)?;
-                let mut blance = 0;
-                for out in utxos.outputs {
-                    blance += out.value;
+                let blance = if let Some(node) = &node {
+                    Some(parse_amount(&node.call(
+                        None,
+                        "getbalance",
+                        vec![json!(address)],
+                    )?)?)
+                } else {
+                    let wallets = Wallets::new(wallet)?;
+                    let base58 = Address::decode(address)?.encode();
+                    if wallets.is_mine(&base58) {
+                        wallets.balance(&base58)
+                    } else {
+                        let lock = address_to_script(address)?;
+                        let bc = Blockchain::new()?;
+                        // let utxos = bc.find_UTXO(&pub_key_hash);
+                        let utxo_set = UTXOSet { blockchain: bc };
+                        let utxos = utxo_set.find_UTXO(&lock)?;
+                        Amount::checked_sum(utxos.iter().map(|out| out.value))
+                    }
                 }
+                .ok_or_else(|| format_err!("balance overflows the amount range"))?;
                 println!("Balance of '{}'; {} ", address, blance);
             }
         }
         if let Some(ref matches) = matches.subcommand_matches("send") {
             let from = if let Some(address) = matches.get_one::<String>("FROM") {
-                address
+                // the wallet knows its addresses by their base58 form
+                Address::decode(address)?.encode()
             } else {
                 println!("from not supply!: usage");
                 exit(1);
             };
+            let from = &from;
 
             let to = if let Some(address) = matches.get_one::<String>("TO") {
                 address
@@ -154,17 +943,44 @@ impl Cli {
                 exit(1);
             };
 
-            let amount: i32 = if let Some(amount) = matches.get_one::<String>("AMOUNT") {
+            let amount: Amount = if let Some(amount) = matches.get_one::<String>("AMOUNT") {
                 amount.parse()?
             } else {
                 println!("amount not supply!: usage");
                 exit(1);
             };
 
-            if matches.contains_id("mine") {
-                cmd_send(from, to, amount, true)?;
+            let threads = parse_threads(matches)?;
+            let mut options = SendOptions::default();
+            if let Some(lock_time) = matches.get_one::<String>("locktime") {
+                options.lock_time = lock_time.parse()?;
+            }
+            if let Some(until) = matches.get_one::<String>("lock-until") {
+                options.lock_output_until = Some(until.parse()?);
+            }
+            options.sighash_type = match matches.get_one::<String>("sighash").map(|s| s.as_str()) {
+                Some("none") => SIGHASH_NONE,
+                Some("single") => SIGHASH_SINGLE,
+                _ => SIGHASH_ALL,
+            };
+            if matches.get_flag("anyonecanpay") {
+                options.sighash_type |= SIGHASH_ANYONECANPAY;
+            }
+            options.coin_selection = parse_coin_selection(matches)?;
+            if let Some(node) = &node {
+                let recipients = [(to.clone(), amount)];
+                node_send(
+                    node,
+                    wallet,
+                    from,
+                    &recipients,
+                    matches.get_flag("mine"),
+                    &options,
+                )?;
+            } else if matches.contains_id("mine") {
+                cmd_send(wallet, from, to, amount, true, threads, &options)?;
             } else {
-                cmd_send(from, to, amount, false)?;
+                cmd_send(wallet, from, to, amount, false, threads, &options)?;
             }
             // let mut bc = Blockchain::new()?;
             // let mut utxo_set = UTXOSet { blockchain: bc };
@@ -175,28 +991,811 @@ impl Cli {
             // utxo_set.update(&new_block)?;
             // bc.add_block(vec![tx])?;
             // println!("success!");
+        }
 
-            if let Some(_) = matches.subcommand_matches("printchain") {
-                let bc = Blockchain::new()?;
-                for b in &mut bc.iter() {
-                    println!("block: {:#?}", b);
+        if let Some(ref matches) = matches.subcommand_matches("sendmany") {
+            let from = matches.get_one::<String>("FROM").unwrap();
+            let recipients = parse_recipients(matches)?;
+            let threads = parse_threads(matches)?;
+            let options = SendOptions {
+                coin_selection: parse_coin_selection(matches)?,
+                ..Default::default()
+            };
+            if let Some(node) = &node {
+                node_send(
+                    node,
+                    wallet,
+                    from,
+                    &recipients,
+                    matches.get_flag("mine"),
+                    &options,
+                )?;
+            } else {
+                cmd_send_many(
+                    wallet,
+                    from,
+                    &recipients,
+                    matches.get_flag("mine"),
+                    threads,
+                    &options,
+                )?;
+            }
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("senddata") {
+            let from = matches.get_one::<String>("FROM").unwrap();
+            let data = hex::decode(matches.get_one::<String>("HEXDATA").unwrap())?;
+            match &node {
+                Some(node) => {
+                    refuse_mine_on_node(matches.get_flag("mine"), "send")?;
+                    let params = vec![json!(from), json!(hex::encode(&data))];
+                    let txid = node.call(Some(wallet), "senddata", params)?;
+                    println!("transaction: {}", json_str(&txid));
+                }
+                None => cmd_send_data(wallet, from, &data, matches.get_flag("mine"))?,
+            }
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("finddata") {
+            let data = hex::decode(matches.get_one::<String>("HEXDATA").unwrap())?;
+            let anchor = match &node {
+                Some(node) => node.call(None, "finddata", vec![json!(hex::encode(&data))])?,
+                None => anchor_json(&Blockchain::new()?, &data)?,
+            };
+            if anchor.is_null() {
+                println!("data is not anchored in the blockchain");
+            } else {
+                println!("block: {}", json_str(&anchor["block"]));
+                println!("height: {}", anchor["height"]);
+                println!("timestamp: {}", anchor["time"]);
+                println!("transaction: {}", json_str(&anchor["txid"]));
+            }
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("validateaddress") {
+            let address = matches.get_one::<String>("ADDRESS").unwrap();
+            match Address::decode(address) {
+                Ok(decoded) => {
+                    let base58 = decoded.encode();
+                    let (is_mine, watch_only) = match &node {
+                        Some(node) => {
+                            let info =
+                                node.call(Some(wallet), "getaddressinfo", vec![json!(base58)])?;
+                            (
+                                info["ismine"] == json!(true),
+                                info["iswatchonly"] == json!(true),
+                            )
+                        }
+                        None => {
+                            let wallets = Wallets::new(wallet)?;
+                            (wallets.is_mine(&base58), wallets.is_watch_only(&base58))
+                        }
+                    };
+                    println!("address: {}", address);
+                    println!("valid: true");
+                    println!("network: {}", decoded.network);
+                    println!("type: {}", decoded.kind);
+                    println!("hash: {}", hex::encode(&decoded.hash));
+                    println!("base58: {}", base58);
+                    println!("bech32: {}", decoded.encode_bech32());
+                    println!("ismine: {}", is_mine);
+                    println!("watchonly: {}", watch_only);
+                }
+                Err(err) => {
+                    println!("address: {}", address);
+                    println!("valid: false");
+                    println!("error: {}", err.reason);
+                }
+            }
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("signmessage") {
+            let address = Address::decode(matches.get_one::<String>("ADDRESS").unwrap())?.encode();
+            let message = matches.get_one::<String>("MESSAGE").unwrap();
+            let signature = match &node {
+                Some(node) => {
+                    let params = vec![json!(address), json!(message)];
+                    json_str(&node.call(Some(wallet), "signmessage", params)?).to_string()
+                }
+                None => {
+                    let wallets = unlocked_wallets(wallet)?;
+                    hex::encode(wallets.get_signing_wallet(&address)?.sign_message(message))
+                }
+            };
+            println!("signature: {}", signature);
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("verifymessage") {
+            let address = matches.get_one::<String>("ADDRESS").unwrap();
+            let signature = hex::decode(matches.get_one::<String>("SIGNATURE").unwrap())?;
+            let message = matches.get_one::<String>("MESSAGE").unwrap();
+            if verify_message(address, &signature, message)? {
+                println!("signature is valid");
+            } else {
+                println!("signature is NOT valid");
+                exit(1);
+            }
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("getpubkey") {
+            if let Some(address) = matches.get_one::<String>("ADDRESS") {
+                let public_key = match &node {
+                    Some(node) => {
+                        json_str(&node.call(Some(wallet), "getpubkey", vec![json!(address)])?)
+                            .to_string()
+                    }
+                    None => match Wallets::new(wallet)?.get_wallet(address) {
+                        Some(wallet) => hex::encode(&wallet.public_key),
+                        None => return Err(format_err!("{} is not in the wallet", address)),
+                    },
+                };
+                println!("public key: {}", public_key);
+            }
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("createmultisig") {
+            let m: usize = match matches.get_one::<String>("NREQUIRED") {
+                Some(m) => m.parse()?,
+                None => {
+                    println!("NREQUIRED not supply!: usage");
+                    exit(1);
+                }
+            };
+            let keys: Vec<&String> = matches
+                .get_many::<String>("KEYS")
+                .into_iter()
+                .flatten()
+                .collect();
+            let address = match &node {
+                Some(node) => {
+                    let params = vec![json!(m), json!(keys)];
+                    json_str(&node.call(Some(wallet), "addmultisigaddress", params)?).to_string()
+                }
+                None => {
+                    let mut wallets = Wallets::new(wallet)?;
+                    let mut pub_keys = Vec::new();
+                    for key in keys {
+                        pub_keys.push(wallets.resolve_public_key(key)?);
+                    }
+                    let address = wallets.create_multisig(m, &pub_keys)?;
+                    wallets.save_all()?;
+                    address
+                }
+            };
+            println!("multisig address: {}", address);
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("sendmultisig") {
+            let from = matches.get_one::<String>("FROM").unwrap();
+            let to = matches.get_one::<String>("TO").unwrap();
+            let amount: Amount = matches.get_one::<String>("AMOUNT").unwrap().parse()?;
+            let file = matches.get_one::<String>("FILE").unwrap();
+            let tx = match &node {
+                Some(node) => {
+                    let params = vec![json!(from), json!(to), json!(amount.to_string())];
+                    let tx = node.call(Some(wallet), "createmultisigtransaction", params)?;
+                    bincode::deserialize(&hex::decode(json_str(&tx))?)?
+                }
+                None => cmd_send_multisig(wallet, from, to, amount)?,
+            };
+            std::fs::write(file, hex::encode(bincode::serialize(&tx)?))?;
+            print_multisig_status(&tx);
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("createpsbt") {
+            let from = matches.get_one::<String>("FROM").unwrap();
+            let file = matches.get_one::<String>("FILE").unwrap();
+            let options = SendOptions {
+                coin_selection: parse_coin_selection(matches)?,
+                ..Default::default()
+            };
+            let recipients = parse_recipients(matches)?;
+            let psbt = match &node {
+                Some(node) => {
+                    let params = vec![
+                        json!(from),
+                        payments_json(&recipients),
+                        serde_json::to_value(&options)?,
+                    ];
+                    let psbt = node.call(Some(wallet), "walletcreatefundedpsbt", params)?;
+                    PartiallySignedTransaction::decode(json_str(&psbt))?
+                }
+                None => {
+                    let wallets = Wallets::new(wallet)?;
+                    let bc = Blockchain::new()?;
+                    let utxo_set = UTXOSet { blockchain: bc };
+                    let tx = Transaction::new_unsigned_many(
+                        from,
+                        wallets.get_multisig(from),
+                        &recipients,
+                        &options,
+                        &utxo_set,
+                    )?;
+                    PartiallySignedTransaction::new(tx, &utxo_set.blockchain)?
+                }
+            };
+            psbt.write(file)?;
+            print_psbt_status(&psbt)?;
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("signpsbt") {
+            let file = matches.get_one::<String>("FILE").unwrap();
+            let mut psbt = PartiallySignedTransaction::read(file)?;
+            let added = match &node {
+                Some(node) => {
+                    let params = vec![json!(psbt.encode()?)];
+                    let signed = node.call(Some(wallet), "walletprocesspsbt", params)?;
+                    psbt = PartiallySignedTransaction::decode(json_str(&signed["psbt"]))?;
+                    signed["added"].as_u64().unwrap_or_default() as usize
+                }
+                None => psbt.sign(&unlocked_wallets(wallet)?)?,
+            };
+            psbt.write(file)?;
+            println!("added {} signatures", added);
+            print_psbt_status(&psbt)?;
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("combinepsbt") {
+            let out = matches.get_one::<String>("OUT").unwrap();
+            let mut files = matches.get_many::<String>("FILES").unwrap();
+            let mut psbt = PartiallySignedTransaction::read(files.next().unwrap())?;
+            for file in files {
+                psbt.combine(&PartiallySignedTransaction::read(file)?)?;
+            }
+            psbt.write(out)?;
+            print_psbt_status(&psbt)?;
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("finalizepsbt") {
+            let file = matches.get_one::<String>("FILE").unwrap();
+            let tx_file = matches.get_one::<String>("TXFILE").unwrap();
+            let tx = PartiallySignedTransaction::read(file)?.finalize()?;
+            std::fs::write(tx_file, hex::encode(bincode::serialize(&tx)?))?;
+            println!("txid: {}", tx.id);
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("broadcast") {
+            let tx_file = matches.get_one::<String>("TXFILE").unwrap();
+            let data = hex::decode(std::fs::read_to_string(tx_file)?.trim())?;
+            let tx: Transaction = bincode::deserialize(&data)?;
+            let mine = matches.get_one::<String>("mine");
+            if let Some(node) = &node {
+                refuse_mine_on_node(mine.is_some(), "broadcast")?;
+                let txid = node.call(None, "sendrawtransaction", vec![json!(hex::encode(data))])?;
+                println!("txid: {}", txid.as_str().unwrap_or_default());
+                println!("success!");
+            } else {
+                let utxo_set = UTXOSet {
+                    blockchain: Blockchain::new()?,
+                };
+                cmd_broadcast(tx, mine, utxo_set)?;
+            }
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("cosign") {
+            let file = matches.get_one::<String>("FILE").unwrap();
+            let mine = matches.get_one::<String>("mine");
+            match &node {
+                Some(node) => {
+                    refuse_mine_on_node(mine.is_some(), "send")?;
+                    node_cosign(node, wallet, file, matches.get_flag("send"))?;
+                }
+                None => cmd_cosign(wallet, file, matches.get_flag("send"), mine)?,
+            }
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("initiateswap") {
+            let from = matches.get_one::<String>("FROM").unwrap();
+            let participant = matches.get_one::<String>("PARTICIPANT").unwrap();
+            let amount: Amount = matches.get_one::<String>("AMOUNT").unwrap().parse()?;
+            let secret_hash = match matches.get_one::<String>("secret-hash") {
+                Some(hash) => Some(hex::decode(hash)?),
+                None => None,
+            };
+            let timeout = match matches.get_one::<String>("timeout") {
+                Some(timeout) => Some(timeout.parse()?),
+                None => None,
+            };
+            let (secret, secret_hash, timeout) = swap_terms(secret_hash, timeout)?;
+            let mine = matches.get_flag("mine");
+            let contract = match &node {
+                Some(node) => {
+                    refuse_mine_on_node(mine, "initiate the swap")?;
+                    let params = vec![
+                        json!(from),
+                        json!(participant),
+                        json!(amount.to_string()),
+                        json!(hex::encode(&secret_hash)),
+                        json!(timeout),
+                    ];
+                    json_str(&node.call(Some(wallet), "initiateswap", params)?).to_string()
+                }
+                None => cmd_initiate_swap(
+                    wallet,
+                    from,
+                    participant,
+                    amount,
+                    &secret_hash,
+                    timeout,
+                    mine,
+                )?,
+            };
+            if let Some(secret) = secret {
+                println!("secret: {}", hex::encode(secret));
+            }
+            println!("secret hash: {}", hex::encode(&secret_hash));
+            println!("timeout: {}", timeout);
+            println!("contract: {}", contract);
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("redeemswap") {
+            let contract = matches.get_one::<String>("CONTRACT").unwrap();
+            let secret = hex::decode(matches.get_one::<String>("SECRET").unwrap())?;
+            match &node {
+                Some(node) => {
+                    refuse_mine_on_node(matches.get_flag("mine"), "redeem")?;
+                    let params = vec![json!(contract), json!(hex::encode(&secret))];
+                    println!(
+                        "txid: {}",
+                        json_str(&node.call(Some(wallet), "redeemswap", params)?)
+                    );
+                    println!("success!");
+                }
+                None => cmd_spend_swap(wallet, contract, Some(&secret), matches.get_flag("mine"))?,
+            }
+        }
+
+        if let Some(ref matches) = matches.subcommand_matches("refundswap") {
+            let contract = matches.get_one::<String>("CONTRACT").unwrap();
+            match &node {
+                Some(node) => {
+                    refuse_mine_on_node(matches.get_flag("mine"), "refund")?;
+                    let params = vec![json!(contract)];
+                    println!(
+                        "txid: {}",
+                        json_str(&node.call(Some(wallet), "refundswap", params)?)
+                    );
+                    println!("success!");
                 }
+                None => cmd_spend_swap(wallet, contract, None, matches.get_flag("mine"))?,
             }
         }
+
+        if let Some(ref matches) = matches.subcommand_matches("auditswap") {
+            let contract = matches.get_one::<String>("CONTRACT").unwrap();
+            let swap = match &node {
+                Some(node) => node.call(None, "auditswap", vec![json!(contract)])?,
+                None => swap_json(&Blockchain::new()?, contract)?,
+            };
+            print_swap(&swap);
+        }
         Ok(())
     }
 }
 
-fn cmd_send(from: &str, to: &str, amount: i32, mine_now: bool) -> Result<()> {
+fn parse_threads(matches: &ArgMatches) -> Result<Option<usize>> {
+    match matches.get_one::<String>("threads") {
+        Some(threads) => Ok(Some(threads.parse()?)),
+        None => Ok(None),
+    }
+}
+
+/// rpc_args are the JSON-RPC options of the node commands
+fn rpc_args() -> Vec<Arg> {
+    vec![
+        arg!(--rpcbind <ADDR> "'address the JSON-RPC server listens on'")
+            .default_value(DEFAULT_RPC_BIND),
+        arg!(--rpcuser <USER> "'JSON-RPC user, besides the cookie in data/.cookie'")
+            .requires("rpcpassword"),
+        arg!(--rpcpassword <PASSWORD> "'JSON-RPC password'").requires("rpcuser"),
+        arg!(--rest " 'serve the read-only REST explorer API on the RPC address without credentials'"),
+        arg!(--"public-events" " 'serve the /events stream without credentials, to pages of any origin'"),
+    ]
+}
+
+fn parse_rpc_config(matches: &ArgMatches) -> RpcConfig {
+    RpcConfig {
+        bind: matches.get_one::<String>("rpcbind").unwrap().clone(),
+        user: matches.get_one::<String>("rpcuser").cloned(),
+        password: matches.get_one::<String>("rpcpassword").cloned(),
+        rest: matches.get_flag("rest"),
+        public_events: matches.get_flag("public-events"),
+    }
+}
+
+/// parse_recipients reads the ADDRESS=AMOUNT arguments and the payments file
+fn parse_recipients(matches: &ArgMatches) -> Result<Vec<(String, Amount)>> {
+    let mut recipients = Vec::new();
+    if let Some(payments) = matches.get_many::<String>("PAYMENTS") {
+        for payment in payments {
+            recipients.push(parse_payment(payment, '=')?);
+        }
+    }
+    if let Some(file) = matches.get_one::<String>("file") {
+        recipients.extend(read_payments(file)?);
+    }
+    Ok(recipients)
+}
+
+/// parse_coin_selection reads the coin selection strategy, or the coins to
+/// spend when they are given with --coin
+fn parse_coin_selection(matches: &ArgMatches) -> Result<CoinSelection> {
+    if let Some(coins) = matches.get_many::<String>("coin") {
+        let mut outpoints = Vec::new();
+        for coin in coins {
+            match coin.rsplit_once(':') {
+                Some((txid, vout)) => outpoints.push((txid.to_string(), vout.parse()?)),
+                None => return Err(format_err!("coin '{}' is not TXID:VOUT", coin)),
+            }
+        }
+        return Ok(CoinSelection::Manual(outpoints));
+    }
+    Ok(
+        match matches
+            .get_one::<String>("coin-selection")
+            .map(|s| s.as_str())
+        {
+            Some("largest") => CoinSelection::LargestFirst,
+            Some("smallest") => CoinSelection::SmallestFirst,
+            Some("random") => CoinSelection::Random,
+            _ => CoinSelection::BranchAndBound,
+        },
+    )
+}
+
+fn cmd_send(
+    wallet: &str,
+    from: &str,
+    to: &str,
+    amount: Amount,
+    mine_now: bool,
+    threads: Option<usize>,
+    options: &SendOptions,
+) -> Result<()> {
+    cmd_send_many(
+        wallet,
+        from,
+        &[(to.to_string(), amount)],
+        mine_now,
+        threads,
+        options,
+    )
+}
+
+/// node_send has the running node build, sign and relay the payment from the wallet
+fn node_send(
+    node: &RpcClient,
+    wallet: &str,
+    from: &str,
+    recipients: &[(String, Amount)],
+    mine_now: bool,
+    options: &SendOptions,
+) -> Result<()> {
+    refuse_mine_on_node(mine_now, "send")?;
+    let params = vec![
+        payments_json(recipients),
+        json!(from),
+        serde_json::to_value(options)?,
+    ];
+    let txid = node.call(Some(wallet), "sendmany", params)?;
+    println!("txid: {}", txid.as_str().unwrap_or_default());
+    println!("success!");
+    Ok(())
+}
+
+/// payments_json lists the payments as [address, amount] pairs, the form of
+/// RPC parameters that may pay an address twice
+fn payments_json(recipients: &[(String, Amount)]) -> Value {
+    let payments: Vec<Value> = recipients
+        .iter()
+        .map(|(address, amount)| json!([address, amount.to_string()]))
+        .collect();
+    json!(payments)
+}
+
+/// refuse_mine_on_node fails a command asked to mine while a node, which
+/// mines on its own, serves it
+fn refuse_mine_on_node(mine: bool, command: &str) -> Result<()> {
+    if mine {
+        return Err(format_err!(
+            "the running node mines on its own, {} without --mine",
+            command
+        ));
+    }
+    Ok(())
+}
+
+fn cmd_send_many(
+    wallet: &str,
+    from: &str,
+    recipients: &[(String, Amount)],
+    mine_now: bool,
+    threads: Option<usize>,
+    options: &SendOptions,
+) -> Result<()> {
+    let mut bc = Blockchain::new()?;
+    if let Some(threads) = threads {
+        bc.set_mining_threads(threads);
+    }
+    // the wallet is closed before mining reopens the loaded ones
+    let wallet = unlocked_wallets(wallet)?.get_signing_wallet(from)?.clone();
+    bc.set_signer(wallet.clone());
+    let mut utxo_set = UTXOSet { blockchain: bc };
+    let tx = Transaction::new_many_UTXO(&wallet, recipients, options, &utxo_set)?;
+    if mine_now {
+        let cbtx = Transaction::new_coinbase(from.to_string(), String::from("reward!"))?;
+        let new_block = utxo_set.blockchain.mine_block(vec![cbtx, tx])?;
+        utxo_set.update(&new_block)?;
+        connect_to_wallets(&new_block)?;
+    } else {
+        Server::send_transaction(&tx, utxo_set)?;
+    }
+    println!("success!");
+    Ok(())
+}
+
+fn cmd_send_data(wallet: &str, from: &str, data: &[u8], mine_now: bool) -> Result<()> {
+    let mut bc = Blockchain::new()?;
+    let wallet = unlocked_wallets(wallet)?.get_signing_wallet(from)?.clone();
+    bc.set_signer(wallet.clone());
+    let mut utxo_set = UTXOSet { blockchain: bc };
+    let tx = Transaction::new_data_UTXO(&wallet, data, &utxo_set)?;
+    let txid = tx.id.clone();
+    if mine_now {
+        let cbtx = Transaction::new_coinbase(from.to_string(), String::from("reward!"))?;
+        let new_block = utxo_set.blockchain.mine_block(vec![cbtx, tx])?;
+        utxo_set.update(&new_block)?;
+        connect_to_wallets(&new_block)?;
+    } else {
+        Server::send_transaction(&tx, utxo_set)?;
+    }
+    println!("transaction: {}", txid);
+    Ok(())
+}
+
+/// parse_payment splits an address and an amount separated by `sep`
+fn parse_payment(payment: &str, sep: char) -> Result<(String, Amount)> {
+    match payment.split_once(sep) {
+        Some((address, amount)) => Ok((address.trim().to_string(), amount.trim().parse()?)),
+        None => Err(format_err!(
+            "payment '{}' is not ADDRESS{}AMOUNT",
+            payment,
+            sep
+        )),
+    }
+}
+
+/// read_payments reads a JSON file, either an object mapping addresses to amounts
+/// or a list of {"address", "amount"} objects, or else a CSV file of address,amount lines
+fn read_payments(file: &str) -> Result<Vec<(String, Amount)>> {
+    let content = std::fs::read_to_string(file)?;
+    let mut recipients = Vec::new();
+    match content.trim_start().chars().next() {
+        Some('{') | Some('[') => {
+            let json: serde_json::Value = serde_json::from_str(&content)?;
+            let entries: Vec<(String, &serde_json::Value)> = match &json {
+                serde_json::Value::Object(map) => map.iter().map(|(k, v)| (k.clone(), v)).collect(),
+                serde_json::Value::Array(list) => {
+                    let mut entries = Vec::new();
+                    for entry in list {
+                        match (entry.get("address"), entry.get("amount")) {
+                            (Some(serde_json::Value::String(address)), Some(amount)) => {
+                                entries.push((address.clone(), amount))
+                            }
+                            _ => {
+                                return Err(format_err!(
+                                    "payment {} needs an address and an amount",
+                                    entry
+                                ))
+                            }
+                        }
+                    }
+                    entries
+                }
+                _ => unreachable!(),
+            };
+            for (address, amount) in entries {
+                // amounts may be written as strings or as JSON numbers
+                let amount = match amount {
+                    serde_json::Value::String(s) => s.parse()?,
+                    serde_json::Value::Number(n) => n.to_string().parse()?,
+                    _ => return Err(format_err!("invalid amount {} for {}", amount, address)),
+                };
+                recipients.push((address, amount));
+            }
+        }
+        _ => {
+            for line in content.lines() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                recipients.push(parse_payment(line, ',')?);
+            }
+        }
+    }
+    if recipients.is_empty() {
+        return Err(format_err!("no payments in {}", file));
+    }
+    Ok(recipients)
+}
+
+fn cmd_send_multisig(wallet: &str, from: &str, to: &str, amount: Amount) -> Result<Transaction> {
+    let wallets = unlocked_wallets(wallet)?;
+    let redeem_script = match wallets.get_multisig(from) {
+        Some(script) => script.clone(),
+        None => {
+            return Err(format_err!(
+                "{} is not a multisig address of the wallet",
+                from
+            ))
+        }
+    };
     let bc = Blockchain::new()?;
+    let utxo_set = UTXOSet { blockchain: bc };
+    let mut tx = Transaction::new_multisig_UTXO(&redeem_script, to, amount, &utxo_set)?;
+    wallets.sign_transaction(&utxo_set.blockchain, &mut tx)?;
+    Ok(tx)
+}
+
+fn cmd_cosign(wallet: &str, file: &str, send: bool, mine: Option<&String>) -> Result<()> {
+    let data = hex::decode(std::fs::read_to_string(file)?.trim())?;
+    let mut tx: Transaction = bincode::deserialize(&data)?;
+    let bc = Blockchain::new()?;
+    unlocked_wallets(wallet)?.sign_transaction(&bc, &mut tx)?;
+    std::fs::write(file, hex::encode(bincode::serialize(&tx)?))?;
+    let complete = print_multisig_status(&tx);
+    if !complete || (!send && mine.is_none()) {
+        return Ok(());
+    }
+    cmd_broadcast(tx, mine, UTXOSet { blockchain: bc })
+}
+
+/// node_cosign has the node add the signatures of the wallet and, once the
+/// transaction is complete, send it when asked to
+fn node_cosign(node: &RpcClient, wallet: &str, file: &str, send: bool) -> Result<()> {
+    let data = std::fs::read_to_string(file)?;
+    let signed = node.call(
+        Some(wallet),
+        "signrawtransactionwithwallet",
+        vec![json!(data.trim())],
+    )?;
+    std::fs::write(file, json_str(&signed))?;
+    let tx: Transaction = bincode::deserialize(&hex::decode(json_str(&signed))?)?;
+    if !print_multisig_status(&tx) || !send {
+        return Ok(());
+    }
+    let txid = node.call(None, "sendrawtransaction", vec![signed])?;
+    println!("txid: {}", json_str(&txid));
+    println!("success!");
+    Ok(())
+}
+
+/// cmd_broadcast verifies a signed transaction against the blockchain, then
+/// mines it when a reward address is given or sends it to the network
+fn cmd_broadcast(tx: Transaction, mine: Option<&String>, mut utxo_set: UTXOSet) -> Result<()> {
+    if !utxo_set.blockchain.verify_transaction(&tx)? {
+        return Err(format_err!("ERROR: Invalid transaction"));
+    }
+    if let Some(address) = mine {
+        let cbtx = Transaction::new_coinbase(address.to_string(), String::from("reward!"))?;
+        let new_block = utxo_set.blockchain.mine_block(vec![cbtx, tx])?;
+        utxo_set.update(&new_block)?;
+        connect_to_wallets(&new_block)?;
+    } else {
+        Server::send_transaction(&tx, utxo_set)?;
+    }
+    println!("success!");
+    Ok(())
+}
+
+/// print_psbt_status prints the signatures collected per input
+fn print_psbt_status(psbt: &PartiallySignedTransaction) -> Result<()> {
+    let mut complete = true;
+    for (i, (signed, required)) in psbt.status()?.iter().enumerate() {
+        println!("input {}: {} of {} signatures", i, signed, required);
+        complete &= signed >= required;
+    }
+    if complete {
+        println!("complete, finalize it with finalizepsbt");
+    }
+    Ok(())
+}
+
+/// print_multisig_status prints the signatures collected per input and
+/// returns whether every input has enough of them
+fn print_multisig_status(tx: &Transaction) -> bool {
+    let mut complete = true;
+    for (i, (signed, required)) in tx.multisig_status().iter().enumerate() {
+        println!("input {}: {} of {} signatures", i, signed, required);
+        complete &= signed >= required;
+    }
+    complete
+}
+
+/// swap_terms picks the secret hash and timeout of a new contract: the
+/// initiator creates the secret, the participant reuses its hash
+fn swap_terms(
+    secret_hash: Option<Vec<u8>>,
+    timeout: Option<u64>,
+) -> Result<(Option<Vec<u8>>, Vec<u8>, u64)> {
+    // the initiator picks the secret and must leave the participant time to
+    // redeem the counter contract first, so its timeout is twice as long
+    let (secret, secret_hash, default_timeout) = match secret_hash {
+        Some(hash) => (None, hash, SWAP_PARTICIPANT_TIMEOUT),
+        None => {
+            let mut secret = vec![0; SWAP_SECRET_LEN];
+            OsRng.fill_bytes(&mut secret);
+            let hash = sha256_hash(&secret);
+            (Some(secret), hash, 2 * SWAP_PARTICIPANT_TIMEOUT)
+        }
+    };
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => (now_millis()? + default_timeout) as u64,
+    };
+    Ok((secret, secret_hash, timeout))
+}
+
+/// cmd_initiate_swap locks coins of the wallet in a swap contract, returning its id
+fn cmd_initiate_swap(
+    wallet: &str,
+    from: &str,
+    participant: &str,
+    amount: Amount,
+    secret_hash: &[u8],
+    timeout: u64,
+    mine_now: bool,
+) -> Result<String> {
+    let wallet = unlocked_wallets(wallet)?.get_signing_wallet(from)?.clone();
+    let htlc = Htlc::new(
+        participant,
+        &wallet.public_key,
+        secret_hash.to_vec(),
+        timeout,
+    )?;
+    let mut bc = Blockchain::new()?;
+    bc.set_signer(wallet.clone());
     let mut utxo_set = UTXOSet { blockchain: bc };
-    let wallets = Wallets::new()?;
-    let wallet = wallets.get_wallet(from).unwrap();
-    let tx = Transaction::new_UTXO(wallet, to, amount, &utxo_set)?;
+    let tx = Transaction::new_htlc_UTXO(&wallet, &htlc, amount, &utxo_set)?;
+    let contract = tx.id.clone();
     if mine_now {
         let cbtx = Transaction::new_coinbase(from.to_string(), String::from("reward!"))?;
         let new_block = utxo_set.blockchain.mine_block(vec![cbtx, tx])?;
         utxo_set.update(&new_block)?;
+        connect_to_wallets(&new_block)?;
+    } else {
+        Server::send_transaction(&tx, utxo_set)?;
+    }
+    Ok(contract)
+}
+
+/// cmd_spend_swap redeems the contract with the secret or, without one, refunds it
+fn cmd_spend_swap(
+    wallet: &str,
+    contract: &str,
+    secret: Option<&[u8]>,
+    mine_now: bool,
+) -> Result<()> {
+    let mut bc = Blockchain::new()?;
+    let contract_tx = bc.find_transaction(contract)?;
+    let (vout, htlc) = contract_tx.htlc_output()?;
+    let pub_key_hash = match secret {
+        Some(_) => &htlc.recipient,
+        None => &htlc.refund,
+    };
+    let address = key_address(pub_key_hash);
+    let wallet = unlocked_wallets(wallet)?
+        .get_signing_wallet(&address)?
+        .clone();
+    let tx = Transaction::new_htlc_spend(&wallet, &contract_tx, vout, secret)?;
+    bc.set_signer(wallet.clone());
+    let mut utxo_set = UTXOSet { blockchain: bc };
+    if mine_now {
+        let cbtx = Transaction::new_coinbase(address, String::from("reward!"))?;
+        let new_block = utxo_set.blockchain.mine_block(vec![cbtx, tx])?;
+        utxo_set.update(&new_block)?;
+        connect_to_wallets(&new_block)?;
     } else {
         Server::send_transaction(&tx, utxo_set)?;
     }
@@ -204,11 +1803,108 @@ fn cmd_send(from: &str, to: &str, amount: i32, mine_now: bool) -> Result<()> {
     Ok(())
 }
 
-fn cmd_create_wallet() -> Result<String> {
-    let mut ws = Wallets::new()?;
-    let address = ws.create_wallet();
+/// print_swap prints the terms of a swap contract and how it was spent
+fn print_swap(swap: &Value) {
+    println!("amount: {}", json_str(&swap["amount"]));
+    println!("recipient: {}", json_str(&swap["recipient"]));
+    println!("refund: {}", json_str(&swap["refund"]));
+    println!("secret hash: {}", json_str(&swap["secrethash"]));
+    println!("timeout: {}", swap["timeout"]);
+    let spent = &swap["spent"];
+    match (spent.is_null(), spent["secret"].as_str()) {
+        (true, _) => println!("unspent"),
+        (false, Some(secret)) => {
+            println!(
+                "redeemed by {}, secret: {}",
+                json_str(&spent["txid"]),
+                secret
+            )
+        }
+        (false, None) => println!("refunded by {}", json_str(&spent["txid"])),
+    }
+}
+
+/// connect_to_wallets records the coins of a block the CLI mined in every loaded wallet
+fn connect_to_wallets(block: &Block) -> Result<()> {
+    Wallets::update_loaded(|wallets| {
+        wallets.connect_block(block);
+        Ok(())
+    })
+}
+
+/// cmd_network picks the network of the command: the one of the chain, which
+/// --network must match, or --network alone when creating a new chain
+fn cmd_network(matches: &ArgMatches, node: Option<&RpcClient>) -> Result<Network> {
+    let chosen = match matches.get_one::<String>("network") {
+        Some(network) => Some(network.parse::<Network>()?),
+        None => None,
+    };
+    if matches.subcommand_name() == Some("create") {
+        return Ok(chosen.unwrap_or(Network::Main));
+    }
+    let stored = match node {
+        Some(node) => {
+            Some(json_str(&node.call(None, "getblockchaininfo", vec![])?["chain"]).parse()?)
+        }
+        None => Blockchain::stored_network()?,
+    };
+    match (chosen, stored) {
+        (Some(chosen), Some(stored)) if chosen != stored => Err(format_err!(
+            "the chain is on the {} network, not the {} network",
+            stored,
+            chosen
+        )),
+        (chosen, stored) => Ok(chosen.or(stored).unwrap_or(Network::Main)),
+    }
+}
+
+/// json_str returns a string of a node answer, empty for any other value
+fn json_str(value: &Value) -> &str {
+    value.as_str().unwrap_or_default()
+}
+
+/// unlocked_wallets opens the wallet for a command that signs, see unlock_for_command
+fn unlocked_wallets(wallet: &str) -> Result<Wallets> {
+    let mut wallets = Wallets::new(wallet)?;
+    unlock_for_command(&mut wallets)?;
+    Ok(wallets)
+}
+
+/// unlock_for_command asks for the passphrase of a locked wallet, whose keys
+/// then stay unlocked in memory until the command ends
+fn unlock_for_command(wallets: &mut Wallets) -> Result<()> {
+    if wallets.is_locked() {
+        wallets.unlock(&read_passphrase("passphrase: ")?)?;
+    }
+    Ok(())
+}
+
+/// read_passphrase prompts on stderr and reads one line from stdin
+fn read_passphrase(prompt: &str) -> Result<String> {
+    eprint!("{}", prompt);
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line)?;
+    Ok(line.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+/// cmd_import applies an import to the wallet and rescans the blockchain for
+/// the coins of the imported addresses
+fn cmd_import<T>(
+    mut wallets: Wallets,
+    import: impl FnOnce(&mut Wallets) -> Result<T>,
+) -> Result<T> {
+    let bc = Blockchain::new()?;
+    let imported = import(&mut wallets)?;
+    wallets.rescan(&bc, 0)?;
+    wallets.save_all()?;
+    Ok(imported)
+}
+
+fn cmd_create_wallet(wallet: &str, label: &AddressLabel) -> Result<(String, Option<String>)> {
+    let mut ws = unlocked_wallets(wallet)?;
+    let created = ws.new_address(label)?;
     ws.save_all()?;
-    Ok(address)
+    Ok(created)
 }
 
 fn cmd_reindex() -> Result<i32> {
@@ -219,3 +1915,58 @@ fn cmd_reindex() -> Result<i32> {
     println!("{} transactions", count);
     Ok(count)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// recipients parses the sendmany payment arguments, writing `file` to a
+    /// payments file first when it is given
+    fn recipients(args: &[&str], file: Option<&str>) -> Result<Vec<(String, Amount)>> {
+        let command = Command::new("sendmany")
+            .arg(arg!([PAYMENTS]" 'ADDRESS=AMOUNT pairs'").num_args(1..))
+            .arg(arg!(-f --file <FILE> " 'payments file'"));
+        let path = std::env::temp_dir().join(format!("payments-{}", std::process::id()));
+        let mut argv = vec!["sendmany".to_string()];
+        argv.extend(args.iter().map(|arg| arg.to_string()));
+        if let Some(content) = file {
+            std::fs::write(&path, content)?;
+            argv.push("--file".to_string());
+            argv.push(path.to_string_lossy().into_owned());
+        }
+        let result = parse_recipients(&command.get_matches_from(argv));
+        let _ = std::fs::remove_file(&path);
+        result
+    }
+
+    fn payment(address: &str, amount: &str) -> (String, Amount) {
+        (address.to_string(), amount.parse().unwrap())
+    }
+
+    #[test]
+    fn test_parse_recipients() {
+        let paid = vec![payment("a", "1.5"), payment("b", "2")];
+        assert_eq!(recipients(&["a=1.5", "b = 2"], None).unwrap(), paid);
+        let csv = "# address,amount\na,1.5\n\nb, 2\n";
+        assert_eq!(recipients(&[], Some(csv)).unwrap(), paid);
+        let object = r#"{"a": "1.5", "b": 2}"#;
+        assert_eq!(recipients(&[], Some(object)).unwrap(), paid);
+        let list = r#"[{"address": "a", "amount": 1.5}, {"address": "b", "amount": "2"}]"#;
+        assert_eq!(recipients(&[], Some(list)).unwrap(), paid);
+
+        // the file adds to the arguments, and an address may be paid twice
+        let twice = recipients(&["a=1.5"], Some("a,1.5\nb,2")).unwrap();
+        assert_eq!(
+            twice,
+            vec![payment("a", "1.5"), payment("a", "1.5"), payment("b", "2")]
+        );
+
+        assert!(recipients(&["a"], None).is_err());
+        assert!(recipients(&["a=1.5.0"], None).is_err());
+        assert!(recipients(&[], Some("a,-1")).is_err());
+        assert!(recipients(&[], Some(r#"[{"address": "a", "amount": true}]"#)).is_err());
+        assert!(recipients(&[], Some(r#"[{"address": "a"}]"#)).is_err());
+        assert!(recipients(&["a=1"], Some("")).is_err());
+        assert!(recipients(&[], Some("# nothing to pay\n")).is_err());
+    }
+}
@@ -0,0 +1,231 @@
+use std::cmp::Reverse;
+
+use failure::format_err;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+
+use crate::amount::Amount;
+use crate::errors::Result;
+
+/// branch and bound gives up after trying this many combinations
+const BNB_MAX_TRIES: usize = 100_000;
+
+/// Coin is an unspent output the wallet can spend
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Coin {
+    pub txid: String,
+    pub vout: i32,
+    pub value: Amount,
+}
+
+/// CoinSelection picks which coins fund a transaction
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum CoinSelection {
+    /// BranchAndBound looks for coins adding up to exactly the target so the
+    /// transaction needs no change, falling back to LargestFirst
+    #[default]
+    BranchAndBound,
+    /// LargestFirst spends the fewest, largest coins
+    LargestFirst,
+    /// SmallestFirst consolidates small coins
+    SmallestFirst,
+    /// Random picks coins in random order so the choice does not link them
+    Random,
+    /// Manual spends exactly the given outpoints
+    Manual(Vec<(String, i32)>),
+}
+
+/// SelectCoins chooses coins worth at least `target` from `coins`
+pub fn select_coins(
+    mut coins: Vec<Coin>,
+    target: Amount,
+    strategy: &CoinSelection,
+) -> Result<Vec<Coin>> {
+    match strategy {
+        CoinSelection::BranchAndBound => {
+            coins.sort_by_key(|c| Reverse(c.value));
+            match branch_and_bound(&coins, target) {
+                Some(selected) => Ok(selected),
+                None => accumulate(coins, target),
+            }
+        }
+        CoinSelection::LargestFirst => {
+            coins.sort_by_key(|c| Reverse(c.value));
+            accumulate(coins, target)
+        }
+        CoinSelection::SmallestFirst => {
+            coins.sort_by_key(|c| c.value);
+            accumulate(coins, target)
+        }
+        CoinSelection::Random => {
+            coins.shuffle(&mut rand::thread_rng());
+            accumulate(coins, target)
+        }
+        CoinSelection::Manual(outpoints) => {
+            let mut selected = Vec::new();
+            for (txid, vout) in outpoints {
+                match coins.iter().find(|c| &c.txid == txid && c.vout == *vout) {
+                    Some(coin) if !selected.contains(coin) => selected.push(coin.clone()),
+                    Some(_) => return Err(format_err!("coin {}:{} is selected twice", txid, vout)),
+                    None => {
+                        return Err(format_err!(
+                            "coin {}:{} is not a spendable output of the wallet",
+                            txid,
+                            vout
+                        ))
+                    }
+                }
+            }
+            let total = total_value(&selected)?;
+            if total < target {
+                return Err(format_err!(
+                    "Not Enough balance: selected coins are worth {}",
+                    total
+                ));
+            }
+            Ok(selected)
+        }
+    }
+}
+
+/// accumulate takes coins in order until they cover the target
+fn accumulate(coins: Vec<Coin>, target: Amount) -> Result<Vec<Coin>> {
+    let mut selected = Vec::new();
+    let mut total = Amount::ZERO;
+    for coin in coins {
+        if total >= target {
+            break;
+        }
+        total = total
+            .checked_add(coin.value)
+            .ok_or_else(|| format_err!("spendable outputs overflow the amount range"))?;
+        selected.push(coin);
+    }
+    if total < target {
+        return Err(format_err!("Not Enough balance: current balance {}", total));
+    }
+    Ok(selected)
+}
+
+/// branch_and_bound searches, largest coins first, for a subset of `coins`
+/// (sorted by decreasing value) worth exactly `target`
+fn branch_and_bound(coins: &[Coin], target: Amount) -> Option<Vec<Coin>> {
+    // remaining[i] is the value of coins[i..], bounding what a branch can still add
+    let mut remaining = vec![Amount::ZERO; coins.len() + 1];
+    for i in (0..coins.len()).rev() {
+        remaining[i] = remaining[i + 1].checked_add(coins[i].value)?;
+    }
+    // the search is depth first with an explicit stack of the chosen coins, a
+    // recursion as deep as the coin count could overflow the stack
+    let mut chosen: Vec<usize> = Vec::new();
+    let mut total = Amount::ZERO;
+    let mut index = 0;
+    let mut tries = 0;
+    loop {
+        if total == target {
+            return Some(chosen.into_iter().map(|i| coins[i].clone()).collect());
+        }
+        tries += 1;
+        if tries > BNB_MAX_TRIES {
+            return None;
+        }
+        // total < target here, so neither sum can overflow past the precomputed bound
+        if index < coins.len() && total.checked_add(remaining[index]).unwrap() >= target {
+            let with = total.checked_add(coins[index].value).unwrap();
+            if with <= target {
+                chosen.push(index);
+                total = with;
+            }
+            index += 1;
+            continue;
+        }
+        // a dead end, the last chosen coin is left out instead
+        let last = chosen.pop()?;
+        total = total.checked_sub(coins[last].value).unwrap();
+        index = last + 1;
+    }
+}
+
+fn total_value(coins: &[Coin]) -> Result<Amount> {
+    Amount::checked_sum(coins.iter().map(|c| c.value))
+        .ok_or_else(|| format_err!("selected coins overflow the amount range"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coins(values: &[u64]) -> Vec<Coin> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, v)| Coin {
+                txid: format!("tx{}", i),
+                vout: 0,
+                value: Amount::from_units(*v),
+            })
+            .collect()
+    }
+
+    fn values(selected: &[Coin]) -> Vec<Amount> {
+        let mut values: Vec<Amount> = selected.iter().map(|c| c.value).collect();
+        values.sort();
+        values
+    }
+
+    fn amounts(units: &[u64]) -> Vec<Amount> {
+        units.iter().map(|u| Amount::from_units(*u)).collect()
+    }
+
+    #[test]
+    fn test_strategies() {
+        let pool = coins(&[5, 1, 7, 3, 10]);
+        let target = Amount::from_units(8);
+        let exact = select_coins(pool.clone(), target, &CoinSelection::BranchAndBound).unwrap();
+        assert_eq!(total_value(&exact).unwrap(), target);
+        let largest = select_coins(pool.clone(), target, &CoinSelection::LargestFirst).unwrap();
+        assert_eq!(values(&largest), amounts(&[10]));
+        let smallest = select_coins(pool.clone(), target, &CoinSelection::SmallestFirst).unwrap();
+        assert_eq!(values(&smallest), amounts(&[1, 3, 5]));
+        let random = select_coins(pool.clone(), target, &CoinSelection::Random).unwrap();
+        assert!(total_value(&random).unwrap() >= target);
+        // no exact match falls back to largest first
+        let fallback = select_coins(
+            coins(&[4, 6]),
+            Amount::from_units(5),
+            &CoinSelection::BranchAndBound,
+        )
+        .unwrap();
+        assert_eq!(values(&fallback), amounts(&[6]));
+        assert!(select_coins(pool, Amount::from_units(100), &CoinSelection::LargestFirst).is_err());
+    }
+
+    #[test]
+    fn test_branch_and_bound_many_coins() {
+        // no subset of even coins sums to an odd target, the search runs out
+        // deep in the coin list and falls back to largest first
+        let pool = coins(&vec![2; 50_000]);
+        let selected = select_coins(
+            pool,
+            Amount::from_units(99_999),
+            &CoinSelection::BranchAndBound,
+        )
+        .unwrap();
+        assert_eq!(selected.len(), 50_000);
+    }
+
+    #[test]
+    fn test_manual() {
+        let pool = coins(&[5, 1]);
+        let manual = CoinSelection::Manual(vec![("tx1".to_string(), 0)]);
+        assert_eq!(
+            values(&select_coins(pool.clone(), Amount::from_units(1), &manual).unwrap()),
+            amounts(&[1])
+        );
+        assert!(select_coins(pool.clone(), Amount::from_units(2), &manual).is_err());
+        let missing = CoinSelection::Manual(vec![("tx9".to_string(), 0)]);
+        assert!(select_coins(pool.clone(), Amount::from_units(1), &missing).is_err());
+        let twice = CoinSelection::Manual(vec![("tx0".to_string(), 0), ("tx0".to_string(), 0)]);
+        assert!(select_coins(pool, Amount::from_units(1), &twice).is_err());
+    }
+}
@@ -0,0 +1,212 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use crypto::ed25519;
+use failure::format_err;
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::block::Block;
+use crate::errors::Result;
+use crate::wallet::Wallet;
+
+const PUBLIC_KEY_LEN: usize = 32;
+const SIGNATURE_LEN: usize = 64;
+
+/// ConsensusConfig selects the consensus engine of a chain, it is chosen at
+/// `create` time and stored in the block database
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConsensusConfig {
+    ProofOfWork,
+    ProofOfAuthority { authorities: Vec<Vec<u8>> },
+}
+
+/// SealContext carries the local resources an engine may use to seal a block
+pub struct SealContext<'a> {
+    pub threads: usize,
+    pub signer: Option<&'a Wallet>,
+}
+
+/// ConsensusEngine decides how blocks are sealed, which seals are valid and
+/// which of two competing tips the node follows
+pub trait ConsensusEngine: Debug + Send + Sync {
+    /// Seal fills in the nonce, seal and hash of a freshly assembled block
+    fn seal(&self, block: &mut Block, ctx: &SealContext) -> Result<()>;
+
+    /// VerifySeal checks that the block hash and seal are valid for this engine
+    fn verify_seal(&self, block: &Block) -> Result<bool>;
+
+    /// Prefer is the fork choice rule: whether `candidate` should replace `current` as the tip
+    fn prefer(&self, candidate: &Block, current: &Block) -> bool;
+}
+
+/// NewEngine builds the consensus engine described by the config
+pub fn new_engine(config: &ConsensusConfig) -> Arc<dyn ConsensusEngine> {
+    match config {
+        ConsensusConfig::ProofOfWork => Arc::new(ProofOfWork {}),
+        ConsensusConfig::ProofOfAuthority { authorities } => Arc::new(ProofOfAuthority {
+            authorities: authorities.clone(),
+        }),
+    }
+}
+
+/// ProofOfWork seals blocks by searching for a nonce whose hash meets the target
+#[derive(Debug)]
+pub struct ProofOfWork {}
+
+impl ConsensusEngine for ProofOfWork {
+    fn seal(&self, block: &mut Block, ctx: &SealContext) -> Result<()> {
+        block.run_proof_of_work(ctx.threads)
+    }
+
+    fn verify_seal(&self, block: &Block) -> Result<bool> {
+        Ok(block.get_seal().is_empty()
+            && block.calculate_hash()? == block.get_hash()
+            && block.validate()?)
+    }
+
+    fn prefer(&self, candidate: &Block, current: &Block) -> bool {
+        candidate.get_height() > current.get_height()
+    }
+}
+
+/// ProofOfAuthority seals blocks with an ed25519 signature from one of a fixed
+/// set of authority keys, the seal is the signer public key followed by the signature
+#[derive(Debug)]
+pub struct ProofOfAuthority {
+    authorities: Vec<Vec<u8>>,
+}
+
+impl ConsensusEngine for ProofOfAuthority {
+    fn seal(&self, block: &mut Block, ctx: &SealContext) -> Result<()> {
+        let signer = match ctx.signer {
+            Some(w) => w,
+            None => return Err(format_err!("proof of authority needs a signing wallet")),
+        };
+        if !self.authorities.contains(&signer.public_key) {
+            return Err(format_err!("signing wallet is not a configured authority"));
+        }
+        info!("Sealing the block as authority");
+        let hash = block.calculate_hash()?;
+        let signature = ed25519::signature(hash.as_bytes(), &signer.secret_key);
+        let mut seal = signer.public_key.clone();
+        seal.extend_from_slice(&signature);
+        block.set_seal(seal, hash);
+        Ok(())
+    }
+
+    fn verify_seal(&self, block: &Block) -> Result<bool> {
+        let seal = block.get_seal();
+        if seal.len() != PUBLIC_KEY_LEN + SIGNATURE_LEN {
+            return Ok(false);
+        }
+        let (public_key, signature) = seal.split_at(PUBLIC_KEY_LEN);
+        if !self.authorities.iter().any(|a| a == public_key) {
+            return Ok(false);
+        }
+        let hash = block.calculate_hash()?;
+        Ok(hash == block.get_hash() && ed25519::verify(hash.as_bytes(), public_key, signature))
+    }
+
+    fn prefer(&self, candidate: &Block, current: &Block) -> bool {
+        // authorities can seal competing blocks at the same height instantly,
+        // so ties are broken by the lower hash to let every node converge
+        candidate.get_height() > current.get_height()
+            || (candidate.get_height() == current.get_height()
+                && candidate.get_hash() < current.get_hash())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::Transaction;
+
+    fn test_wallet(seed: u8) -> Wallet {
+        let (secret_key, public_key) = ed25519::keypair(&[seed; 32]);
+        Wallet {
+            secret_key: secret_key.to_vec(),
+            public_key: public_key.to_vec(),
+        }
+    }
+
+    fn test_block(height: i32) -> Block {
+        let cbtx = Transaction::new_coinbase(test_wallet(9).get_address(), height.to_string());
+        Block::new_block(vec![cbtx.unwrap()], String::new(), height, 0).unwrap()
+    }
+
+    #[test]
+    fn test_proof_of_work() {
+        let engine = ProofOfWork {};
+        let ctx = SealContext {
+            threads: 2,
+            signer: None,
+        };
+        let mut block = test_block(1);
+        engine.seal(&mut block, &ctx).unwrap();
+        assert!(engine.verify_seal(&block).unwrap());
+
+        let mut forged = block.clone();
+        forged.set_seal(Vec::new(), "0000".repeat(16));
+        assert!(!engine.verify_seal(&forged).unwrap());
+        let mut sealed = block.clone();
+        sealed.set_seal(vec![1], block.get_hash());
+        assert!(!engine.verify_seal(&sealed).unwrap());
+
+        let mut higher = test_block(2);
+        engine.seal(&mut higher, &ctx).unwrap();
+        assert!(engine.prefer(&higher, &block));
+        assert!(!engine.prefer(&block, &higher));
+        let mut rival = test_block(2);
+        rival.set_seal(Vec::new(), String::new());
+        assert!(!engine.prefer(&rival, &higher) && !engine.prefer(&higher, &rival));
+    }
+
+    #[test]
+    fn test_proof_of_authority() {
+        let authority = test_wallet(1);
+        let outsider = test_wallet(2);
+        let engine = ProofOfAuthority {
+            authorities: vec![authority.public_key.clone()],
+        };
+        let ctx = SealContext {
+            threads: 1,
+            signer: Some(&authority),
+        };
+        let mut block = test_block(1);
+        engine.seal(&mut block, &ctx).unwrap();
+        assert!(engine.verify_seal(&block).unwrap());
+
+        let mut tampered = block.clone();
+        let mut seal = block.get_seal().to_vec();
+        seal[PUBLIC_KEY_LEN] ^= 1;
+        tampered.set_seal(seal, block.get_hash());
+        assert!(!engine.verify_seal(&tampered).unwrap());
+        let mut truncated = block.clone();
+        truncated.set_seal(
+            block.get_seal()[..PUBLIC_KEY_LEN].to_vec(),
+            block.get_hash(),
+        );
+        assert!(!engine.verify_seal(&truncated).unwrap());
+
+        let outsider_ctx = SealContext {
+            threads: 1,
+            signer: Some(&outsider),
+        };
+        assert!(engine.seal(&mut test_block(1), &outsider_ctx).is_err());
+        let other = ProofOfAuthority {
+            authorities: vec![outsider.public_key.clone()],
+        };
+        assert!(!other.verify_seal(&block).unwrap());
+
+        let mut higher = test_block(2);
+        engine.seal(&mut higher, &ctx).unwrap();
+        assert!(engine.prefer(&higher, &block));
+        assert!(!engine.prefer(&block, &higher));
+        // a tie goes to the lower hash
+        let mut rival = test_block(1);
+        rival.set_seal(Vec::new(), "0".repeat(64));
+        assert!(engine.prefer(&rival, &block));
+        assert!(!engine.prefer(&block, &rival));
+    }
+}
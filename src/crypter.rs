@@ -0,0 +1,106 @@
+use crypto::aead::{AeadDecryptor, AeadEncryptor};
+use crypto::chacha20poly1305::ChaCha20Poly1305;
+use crypto::scrypt::{scrypt, ScryptParams};
+use crypto::util::secure_memset;
+use failure::format_err;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::Result;
+
+pub const KEY_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 8;
+const TAG_LEN: usize = 16;
+
+/// KdfParams are the scrypt parameters turning a passphrase into a key
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub salt: Vec<u8>,
+    pub log_n: u8,
+    pub r: u32,
+    pub p: u32,
+}
+
+impl KdfParams {
+    /// New picks a random salt with the default cost, about 32 MiB of memory
+    pub fn new() -> KdfParams {
+        KdfParams {
+            salt: random_bytes(SALT_LEN),
+            log_n: 15,
+            r: 8,
+            p: 1,
+        }
+    }
+
+    /// DeriveKey stretches the passphrase into a cipher key
+    pub fn derive_key(&self, passphrase: &str) -> Vec<u8> {
+        let params = ScryptParams::new(self.log_n, self.r, self.p);
+        let mut key = vec![0; KEY_LEN];
+        scrypt(passphrase.as_bytes(), &self.salt, &params, &mut key);
+        key
+    }
+}
+
+/// RandomBytes returns `len` bytes from the operating system random generator
+pub fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0; len];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+/// Wipe overwrites a secret with zeros before forgetting it
+pub fn wipe(secret: &mut Vec<u8>) {
+    secure_memset(secret, 0);
+    secret.clear();
+}
+
+/// Encrypt seals the plaintext with ChaCha20-Poly1305 under a fresh random
+/// nonce, the result is the nonce, the ciphertext and the authentication tag
+pub fn encrypt(key: &[u8], plaintext: &[u8]) -> Vec<u8> {
+    let nonce = random_bytes(NONCE_LEN);
+    let mut ciphertext = vec![0; plaintext.len()];
+    let mut tag = vec![0; TAG_LEN];
+    ChaCha20Poly1305::new(key, &nonce, &[]).encrypt(plaintext, &mut ciphertext, &mut tag);
+    let mut sealed = nonce;
+    sealed.extend_from_slice(&ciphertext);
+    sealed.extend_from_slice(&tag);
+    sealed
+}
+
+/// Decrypt opens data sealed by Encrypt, failing when the key is wrong or the data was altered
+pub fn decrypt(key: &[u8], sealed: &[u8]) -> Result<Vec<u8>> {
+    if sealed.len() < NONCE_LEN + TAG_LEN {
+        return Err(format_err!("encrypted data is truncated"));
+    }
+    let (nonce, rest) = sealed.split_at(NONCE_LEN);
+    let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+    let mut plaintext = vec![0; ciphertext.len()];
+    if !ChaCha20Poly1305::new(key, nonce, &[]).decrypt(ciphertext, &mut plaintext, tag) {
+        return Err(format_err!("decryption failed"));
+    }
+    Ok(plaintext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let params = KdfParams {
+            log_n: 4,
+            ..KdfParams::new()
+        };
+        let key = params.derive_key("correct horse");
+        assert_eq!(key, params.derive_key("correct horse"));
+        let sealed = encrypt(&key, b"secret key");
+        assert_eq!(decrypt(&key, &sealed).unwrap(), b"secret key");
+        let wrong = params.derive_key("battery staple");
+        assert!(decrypt(&wrong, &sealed).is_err());
+        let mut altered = sealed.clone();
+        altered[NONCE_LEN] ^= 1;
+        assert!(decrypt(&key, &altered).is_err());
+    }
+}
@@ -0,0 +1,218 @@
+//! Chain events streamed to subscribers as server-sent events
+
+use std::collections::HashSet;
+use std::io::Write;
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use log::info;
+use serde_json::Value;
+
+use crate::address::Address;
+use crate::errors::Result;
+
+/// a subscriber that cannot take an event this long is dropped
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+/// events queued for a subscriber that falls further behind are not kept,
+/// the subscriber is dropped instead
+const QUEUE_LEN: usize = 256;
+/// a subscriber no event was written to this long gets a comment, so a closed
+/// connection is noticed even when no event concerns it
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+/// EventKind is what changed in the best chain or the mempool
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    BlockConnected,
+    BlockDisconnected,
+    TxAdded,
+    TxRemoved,
+}
+
+impl EventKind {
+    /// Name is the SSE event name subscribers listen for
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::BlockConnected => "blockconnected",
+            EventKind::BlockDisconnected => "blockdisconnected",
+            EventKind::TxAdded => "txadded",
+            EventKind::TxRemoved => "txremoved",
+        }
+    }
+}
+
+/// ChainEvent is a change with its JSON description and the addresses it pays
+/// to or spends from
+#[derive(Debug, Clone)]
+pub struct ChainEvent {
+    pub kind: EventKind,
+    pub data: Value,
+    pub addresses: HashSet<String>,
+}
+
+impl ChainEvent {
+    /// Message formats the event as a server-sent event
+    fn message(&self) -> String {
+        format!("event: {}\ndata: {}\n\n", self.kind.name(), self.data)
+    }
+}
+
+struct Subscriber {
+    /// the queue the writer thread of the subscriber drains
+    queue: SyncSender<Arc<String>>,
+    /// an empty filter takes every event
+    addresses: HashSet<String>,
+    /// set by the writer thread once the connection failed
+    gone: Arc<AtomicBool>,
+}
+
+impl Subscriber {
+    fn wants(&self, event: &ChainEvent) -> bool {
+        self.addresses.is_empty() || !self.addresses.is_disjoint(&event.addresses)
+    }
+}
+
+/// EventHub fans chain events out to the subscribed event streams
+#[derive(Clone, Default)]
+pub struct EventHub {
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+}
+
+impl EventHub {
+    /// IsEmpty tells whether nobody listens, so events need not be built
+    pub fn is_empty(&self) -> bool {
+        self.subscribers.lock().unwrap().is_empty()
+    }
+
+    /// Subscribe answers an HTTP request with an event stream of the events of
+    /// the addresses or, without any, of all events. Public streams may be
+    /// read from pages of any origin
+    pub fn subscribe(
+        &self,
+        mut stream: TcpStream,
+        addresses: HashSet<String>,
+        public: bool,
+    ) -> Result<()> {
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        let cors = if public {
+            "Access-Control-Allow-Origin: *\r\n"
+        } else {
+            ""
+        };
+        let head = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\
+             {}\r\n",
+            cors
+        );
+        stream.write_all(head.as_bytes())?;
+        stream.flush()?;
+        info!(
+            "event subscriber {} for {:?}",
+            stream.peer_addr()?,
+            &addresses
+        );
+        let (queue, events) = mpsc::sync_channel(QUEUE_LEN);
+        let gone = Arc::new(AtomicBool::new(false));
+        let writer_gone = Arc::clone(&gone);
+        thread::spawn(move || write_events(stream, events, writer_gone));
+        self.subscribers.lock().unwrap().push(Subscriber {
+            queue,
+            addresses,
+            gone,
+        });
+        Ok(())
+    }
+
+    /// Publish queues the event for the subscribers it concerns, dropping every
+    /// subscriber whose connection is gone and those the event concerns that
+    /// fell too far behind. It never waits on a subscriber
+    pub fn publish(&self, event: &ChainEvent) {
+        let message = Arc::new(event.message());
+        self.subscribers.lock().unwrap().retain(|subscriber| {
+            if subscriber.gone.load(Ordering::Relaxed) {
+                return false;
+            }
+            !subscriber.wants(event) || subscriber.queue.try_send(Arc::clone(&message)).is_ok()
+        });
+    }
+}
+
+/// write_events writes the queued events to the stream of a subscriber, and a
+/// heartbeat comment when it is idle, until the subscriber is dropped or its
+/// connection fails
+fn write_events(mut stream: TcpStream, events: Receiver<Arc<String>>, gone: Arc<AtomicBool>) {
+    loop {
+        let written = match events.recv_timeout(HEARTBEAT_INTERVAL) {
+            Ok(message) => stream.write_all(message.as_bytes()),
+            Err(RecvTimeoutError::Timeout) => stream.write_all(b": heartbeat\n\n"),
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+        if written.is_err() {
+            info!("event subscriber gone");
+            gone.store(true, Ordering::Relaxed);
+            return;
+        }
+    }
+}
+
+/// ParseFilter reads the addresses of the `address` query parameters, each
+/// holding one or more comma separated addresses
+pub fn parse_filter(query: &str) -> Result<HashSet<String>> {
+    let mut addresses = HashSet::new();
+    for pair in query.split('&') {
+        if let Some(("address", values)) = pair.split_once('=') {
+            for address in values.split(',').filter(|address| !address.is_empty()) {
+                // events name addresses in their base58 form
+                addresses.insert(Address::decode(address)?.encode());
+            }
+        }
+    }
+    Ok(addresses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address::AddressKind;
+
+    #[test]
+    fn test_event_filter() {
+        let a = Address::new(AddressKind::KeyHash, &[1; 20]);
+        let b = Address::new(AddressKind::KeyHash, &[2; 20]);
+        let query = format!("address={},{}&x=1", a.encode(), b.encode_bech32());
+        let addresses = parse_filter(&query).unwrap();
+        assert_eq!(addresses, HashSet::from([a.encode(), b.encode()]));
+        assert!(parse_filter("address=nope").is_err());
+
+        let event = ChainEvent {
+            kind: EventKind::TxAdded,
+            data: Value::Null,
+            addresses: HashSet::from([b.encode()]),
+        };
+        assert_eq!(event.message(), "event: txadded\ndata: null\n\n");
+
+        // a subscriber whose connection failed is dropped even when the event
+        // does not concern it
+        let hub = EventHub::default();
+        let mut receivers = Vec::new();
+        for filter in [a.encode(), b.encode()] {
+            let (queue, events) = mpsc::sync_channel(QUEUE_LEN);
+            receivers.push(events);
+            hub.subscribers.lock().unwrap().push(Subscriber {
+                queue,
+                addresses: HashSet::from([filter]),
+                gone: Arc::new(AtomicBool::new(false)),
+            });
+        }
+        hub.subscribers.lock().unwrap()[0]
+            .gone
+            .store(true, Ordering::Relaxed);
+        hub.publish(&event);
+        assert_eq!(hub.subscribers.lock().unwrap().len(), 1);
+        assert!(receivers[1].try_recv().is_ok());
+    }
+}
@@ -0,0 +1,149 @@
+use crypto::digest::Digest;
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::pbkdf2::pbkdf2;
+use crypto::sha2::{Sha256, Sha512};
+use failure::format_err;
+
+use crate::crypter::random_bytes;
+use crate::errors::Result;
+
+/// the BIP39 English word list
+const WORDLIST: &str = include_str!("wordlist/english.txt");
+/// entropy of a generated mnemonic, 256 bits make 24 words
+const ENTROPY_LEN: usize = 32;
+const PBKDF2_ROUNDS: u32 = 2048;
+const HARDENED: u32 = 0x8000_0000;
+/// DERIVATION_PATH is m/44'/1'/0'/0', the key of address `i` is m/44'/1'/0'/0'/i'
+const DERIVATION_PATH: [u32; 4] = [44, 1, 0, 0];
+
+fn words() -> Vec<&'static str> {
+    WORDLIST.lines().collect()
+}
+
+/// GenerateMnemonic returns a new random BIP39 mnemonic
+pub fn generate_mnemonic() -> String {
+    entropy_to_mnemonic(&random_bytes(ENTROPY_LEN))
+}
+
+/// entropy_to_mnemonic appends the sha256 checksum to the entropy and
+/// encodes every 11 bits as a word
+fn entropy_to_mnemonic(entropy: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.input(entropy);
+    let mut checksum = [0; 32];
+    hasher.result(&mut checksum);
+    let mut bits: Vec<bool> = entropy.iter().flat_map(|b| byte_bits(*b)).collect();
+    bits.extend(byte_bits(checksum[0]).take(entropy.len() / 4));
+    let words = words();
+    bits.chunks(11)
+        .map(|chunk| words[chunk.iter().fold(0, |acc, bit| acc << 1 | *bit as usize)])
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+fn byte_bits(byte: u8) -> impl Iterator<Item = bool> {
+    (0..8).rev().map(move |i| byte >> i & 1 == 1)
+}
+
+/// MnemonicToSeed checks the words and checksum of a mnemonic and stretches it
+/// with the passphrase into the 64 byte BIP39 seed
+pub fn mnemonic_to_seed(mnemonic: &str, passphrase: &str) -> Result<Vec<u8>> {
+    let list = words();
+    let mut bits = Vec::new();
+    let mnemonic: Vec<String> = mnemonic
+        .split_whitespace()
+        .map(|w| w.to_lowercase())
+        .collect();
+    if ![12, 15, 18, 21, 24].contains(&mnemonic.len()) {
+        return Err(format_err!(
+            "a mnemonic has 12, 15, 18, 21 or 24 words, got {}",
+            mnemonic.len()
+        ));
+    }
+    for word in &mnemonic {
+        let index = match list.binary_search(&word.as_str()) {
+            Ok(index) => index,
+            Err(_) => return Err(format_err!("'{}' is not a mnemonic word", word)),
+        };
+        bits.extend((0..11).rev().map(|i| index >> i & 1 == 1));
+    }
+    let entropy_bits = bits.len() * 32 / 33;
+    let entropy: Vec<u8> = bits[..entropy_bits]
+        .chunks(8)
+        .map(|chunk| chunk.iter().fold(0, |acc, bit| acc << 1 | *bit as u8))
+        .collect();
+    let phrase = mnemonic.join(" ");
+    if entropy_to_mnemonic(&entropy) != phrase {
+        return Err(format_err!("the mnemonic checksum is invalid"));
+    }
+    let mut mac = Hmac::new(Sha512::new(), phrase.as_bytes());
+    let mut seed = vec![0; 64];
+    let salt = format!("mnemonic{}", passphrase);
+    pbkdf2(&mut mac, salt.as_bytes(), PBKDF2_ROUNDS, &mut seed);
+    Ok(seed)
+}
+
+/// DeriveKey derives the ed25519 key of address `index` from the seed along
+/// SLIP-0010 hardened paths
+pub fn derive_key(seed: &[u8], index: u32) -> [u8; 32] {
+    let (mut key, mut chain_code) = hmac_split(b"ed25519 seed", &[seed]);
+    for i in DERIVATION_PATH.iter().chain(&[index]) {
+        let child = (i | HARDENED).to_be_bytes();
+        let (k, c) = hmac_split(&chain_code, &[&[0], &key, &child]);
+        key = k;
+        chain_code = c;
+    }
+    key
+}
+
+/// hmac_split returns both halves of HMAC-SHA512(key, data)
+fn hmac_split(key: &[u8], data: &[&[u8]]) -> ([u8; 32], [u8; 32]) {
+    let mut mac = Hmac::new(Sha512::new(), key);
+    for d in data {
+        mac.input(d);
+    }
+    let code = mac.result();
+    let (mut left, mut right) = ([0; 32], [0; 32]);
+    left.copy_from_slice(&code.code()[..32]);
+    right.copy_from_slice(&code.code()[32..]);
+    (left, right)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mnemonic() {
+        let mnemonic = entropy_to_mnemonic(&[0; 16]);
+        assert_eq!(mnemonic, format!("{} about", ["abandon"; 11].join(" ")));
+        let seed = mnemonic_to_seed(&mnemonic, "TREZOR").unwrap();
+        assert_eq!(
+            hex::encode(seed),
+            "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e5349553\
+             1f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04"
+        );
+        let generated = generate_mnemonic();
+        assert_eq!(generated.split(' ').count(), 24);
+        assert!(mnemonic_to_seed(&generated, "").is_ok());
+        assert!(mnemonic_to_seed(&["abandon"; 12].join(" "), "").is_err());
+        assert!(mnemonic_to_seed("abandon about", "").is_err());
+    }
+
+    #[test]
+    fn test_slip10_vector() {
+        let seed = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        let (key, chain_code) = hmac_split(b"ed25519 seed", &[&seed]);
+        assert_eq!(
+            hex::encode(key),
+            "2b4be7f19ee27bbf30c667b642d5f4aa69fd169872f8fc3059c08ebae2eb19e7"
+        );
+        let (child, _) = hmac_split(&chain_code, &[&[0], &key, &HARDENED.to_be_bytes()]);
+        assert_eq!(
+            hex::encode(child),
+            "68e0fe46dfb67e368c75379acec591dad19df3cde26e63b93a8e704f1dade7a3"
+        );
+        assert_ne!(derive_key(&seed, 0), derive_key(&seed, 1));
+    }
+}
@@ -1,10 +1,20 @@
 use cli::Cli;
 use errors::Result;
 
+mod address;
+mod amount;
 mod block;
 mod blockchain;
 mod cli;
+mod coinselect;
+mod consensus;
+mod crypter;
 mod errors;
+mod events;
+mod hdwallet;
+mod psbt;
+mod rpc;
+mod script;
 mod server;
 mod transaction;
 mod tx;
@@ -0,0 +1,318 @@
+use std::collections::BTreeMap;
+
+use failure::format_err;
+use serde::{Deserialize, Serialize};
+
+use crate::blockchain::Blockchain;
+use crate::errors::Result;
+use crate::script::{Op, Script};
+use crate::transaction::{Transaction, SIGHASH_ALL};
+use crate::tx::TXOutput;
+use crate::wallet::{hash_pub_key, key_address, Wallets};
+
+/// PartiallySignedTransaction carries an unsigned transaction between the
+/// machines holding its keys. It includes the outputs the transaction spends,
+/// so signing needs the wallet but not the blockchain
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PartiallySignedTransaction {
+    pub tx: Transaction,
+    /// the output each input spends
+    pub prev_outputs: Vec<TXOutput>,
+    /// the signatures collected for each input, by hex public key
+    pub signatures: Vec<BTreeMap<String, Vec<u8>>>,
+}
+
+impl PartiallySignedTransaction {
+    /// New wraps an unsigned transaction with the outputs it spends
+    pub fn new(tx: Transaction, bc: &Blockchain) -> Result<PartiallySignedTransaction> {
+        let mut prev_outputs = Vec::new();
+        for vin in &tx.vin {
+            let prev_tx = bc.find_transaction(&vin.txid)?;
+            match prev_tx.vout.get(vin.vout as usize) {
+                Some(out) => prev_outputs.push(out.clone()),
+                None => return Err(format_err!("{}:{} does not exist", vin.txid, vin.vout)),
+            }
+        }
+        Ok(PartiallySignedTransaction {
+            signatures: vec![BTreeMap::new(); tx.vin.len()],
+            tx,
+            prev_outputs,
+        })
+    }
+
+    /// Read loads a partially signed transaction written by Write
+    pub fn read(file: &str) -> Result<PartiallySignedTransaction> {
+        PartiallySignedTransaction::decode(&std::fs::read_to_string(file)?).map_err(|e| {
+            format_err!(
+                "{} is not a valid partially signed transaction: {}",
+                file,
+                e
+            )
+        })
+    }
+
+    /// Write saves the partially signed transaction as hex
+    pub fn write(&self, file: &str) -> Result<()> {
+        std::fs::write(file, self.encode()?)?;
+        Ok(())
+    }
+
+    /// Decode reads a partially signed transaction from its hex form
+    pub fn decode(text: &str) -> Result<PartiallySignedTransaction> {
+        let data = hex::decode(text.trim())?;
+        let psbt: PartiallySignedTransaction = bincode::deserialize(&data)?;
+        if psbt.prev_outputs.len() != psbt.tx.vin.len()
+            || psbt.signatures.len() != psbt.tx.vin.len()
+        {
+            return Err(format_err!(
+                "the inputs do not match their outputs and signatures"
+            ));
+        }
+        Ok(psbt)
+    }
+
+    /// Encode returns the hex form of the partially signed transaction
+    pub fn encode(&self) -> Result<String> {
+        Ok(hex::encode(bincode::serialize(self)?))
+    }
+
+    /// Sign adds the signatures of the wallet keys the inputs need and returns
+    /// how many it added
+    pub fn sign(&mut self, wallets: &Wallets) -> Result<usize> {
+        let mut added = 0;
+        for in_id in 0..self.tx.vin.len() {
+            let addresses = match self.signers(in_id)? {
+                Signers::KeyHash(hash) if self.key_hash_signature(in_id, &hash).is_none() => {
+                    vec![key_address(&hash)]
+                }
+                Signers::KeyHash(_) => Vec::new(),
+                Signers::Multisig(_, pub_keys) => pub_keys
+                    .iter()
+                    .filter(|pub_key| !self.signatures[in_id].contains_key(&hex::encode(pub_key)))
+                    .map(|pub_key| key_address(&pub_key_hash(pub_key)))
+                    .collect(),
+            };
+            for address in addresses {
+                if wallets.get_wallet(&address).is_none() {
+                    continue;
+                }
+                let wallet = wallets.get_signing_wallet(&address)?;
+                let prev_out = &self.prev_outputs[in_id];
+                let signature = self.tx.sign_input(
+                    in_id,
+                    &prev_out.script_pub_key,
+                    prev_out.value,
+                    SIGHASH_ALL,
+                    &wallet.secret_key,
+                )?;
+                self.signatures[in_id].insert(hex::encode(&wallet.public_key), signature);
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
+
+    /// Combine merges the signatures of another copy of the same transaction
+    pub fn combine(&mut self, other: &PartiallySignedTransaction) -> Result<()> {
+        if other.tx.id != self.tx.id || other.signatures.len() != self.signatures.len() {
+            return Err(format_err!(
+                "cannot combine transactions {} and {}",
+                self.tx.id,
+                other.tx.id
+            ));
+        }
+        for (signatures, others) in self.signatures.iter_mut().zip(&other.signatures) {
+            for (pub_key, signature) in others {
+                signatures
+                    .entry(pub_key.clone())
+                    .or_insert_with(|| signature.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Status returns, for each input, how many signatures it holds and how many it needs
+    pub fn status(&self) -> Result<Vec<(usize, usize)>> {
+        let mut status = Vec::new();
+        for in_id in 0..self.tx.vin.len() {
+            status.push(match self.signers(in_id)? {
+                Signers::KeyHash(hash) => (self.key_hash_signature(in_id, &hash).iter().count(), 1),
+                Signers::Multisig(m, pub_keys) => {
+                    let signed = pub_keys
+                        .iter()
+                        .filter(|pub_key| {
+                            self.signatures[in_id].contains_key(&hex::encode(pub_key))
+                        })
+                        .count();
+                    (signed, m)
+                }
+            });
+        }
+        Ok(status)
+    }
+
+    /// Finalize puts the collected signatures in the unlocking scripts and
+    /// returns the signed transaction, failing when an input lacks signatures
+    pub fn finalize(&self) -> Result<Transaction> {
+        let mut tx = self.tx.clone();
+        for (in_id, (signed, required)) in self.status()?.into_iter().enumerate() {
+            if signed < required {
+                return Err(format_err!(
+                    "input {} has {} of {} signatures",
+                    in_id,
+                    signed,
+                    required
+                ));
+            }
+            match self.signers(in_id)? {
+                Signers::KeyHash(hash) => {
+                    // status found the signature
+                    let (pub_key, signature) = self.key_hash_signature(in_id, &hash).unwrap();
+                    tx.vin[in_id].script_sig = Script::new_p2pkh_unlock(signature, &pub_key);
+                }
+                Signers::Multisig(_, pub_keys) => {
+                    for (slot, pub_key) in pub_keys.iter().enumerate() {
+                        if let Some(signature) = self.signatures[in_id].get(&hex::encode(pub_key)) {
+                            tx.vin[in_id].script_sig.0[slot] = Op::Push(signature.clone());
+                        }
+                    }
+                }
+            }
+        }
+        Ok(tx)
+    }
+
+    /// signers tells who can sign an input: the key whose hash a
+    /// pay-to-pubkey-hash output names, or the keys of a multisig redeem script
+    fn signers(&self, in_id: usize) -> Result<Signers> {
+        let script_code = self.prev_outputs[in_id].script_pub_key.split_timelock().1;
+        if let Some(hash) = script_code.p2pkh_hash() {
+            return Ok(Signers::KeyHash(hash.to_vec()));
+        }
+        let redeem_script = match self.tx.vin[in_id].script_sig.redeem_script() {
+            Some(script) if script_code.p2sh_hash() == Some(&script.hash()?[..]) => script,
+            _ => {
+                return Err(format_err!(
+                    "input {} spends an output that cannot be signed offline",
+                    in_id
+                ))
+            }
+        };
+        match redeem_script.multisig_params() {
+            Some((m, pub_keys)) => Ok(Signers::Multisig(m, pub_keys)),
+            None => Err(format_err!("input {} has no multisig redeem script", in_id)),
+        }
+    }
+
+    /// key_hash_signature finds the signature of the key hashing to `hash`
+    fn key_hash_signature(&self, in_id: usize, hash: &[u8]) -> Option<(Vec<u8>, &Vec<u8>)> {
+        self.signatures[in_id]
+            .iter()
+            .find_map(|(pub_key, signature)| {
+                let pub_key = hex::decode(pub_key).ok()?;
+                if pub_key_hash(&pub_key) == hash {
+                    Some((pub_key, signature))
+                } else {
+                    None
+                }
+            })
+    }
+}
+
+/// Signers are the keys that can sign an input
+enum Signers {
+    /// KeyHash is the hash of the one key of a pay-to-pubkey-hash output
+    KeyHash(Vec<u8>),
+    /// Multisig is the number of signatures required and the keys of a multisig output
+    Multisig(usize, Vec<Vec<u8>>),
+}
+
+fn pub_key_hash(pub_key: &[u8]) -> Vec<u8> {
+    let mut hash = pub_key.to_vec();
+    hash_pub_key(&mut hash);
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::SUBSIDY;
+    use crate::blockchain::tests::{coinbase, next_block, test_chain, test_wallet};
+    use crate::tx::{TXInput, SEQUENCE_FINAL};
+    use crate::wallet::multisig_address;
+    use crate::wallet::tests::test_wallets;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_multisig_round_trip() {
+        let alice = test_wallet(1);
+        let mut bc = test_chain(&alice);
+        let keys: Vec<Vec<u8>> = (5..8).map(|seed| test_wallet(seed).public_key).collect();
+        let redeem_script = Script::new_multisig(2, &keys);
+        let multisig = multisig_address(&redeem_script).unwrap();
+
+        // alice funds the 2-of-3 address
+        let genesis = bc.iter().next().unwrap().unwrap();
+        let mut funding = Transaction {
+            id: String::new(),
+            vin: vec![TXInput {
+                txid: genesis.get_transaction()[0].id.clone(),
+                vout: 0,
+                script_sig: Script::default(),
+                sequence: SEQUENCE_FINAL,
+            }],
+            vout: vec![TXOutput::new(SUBSIDY, multisig.clone()).unwrap()],
+            lock_time: 0,
+        };
+        funding.id = funding.hash().unwrap();
+        bc.sign_transaction(&mut funding, &alice.secret_key, SIGHASH_ALL)
+            .unwrap();
+        let block = next_block(&bc, vec![coinbase(&alice, "1"), funding.clone()]);
+        bc.add_block(block).unwrap();
+
+        let mut tx = Transaction {
+            id: String::new(),
+            vin: vec![TXInput {
+                txid: funding.id.clone(),
+                vout: 0,
+                script_sig: Script::new_multisig_unlock(&redeem_script).unwrap(),
+                sequence: SEQUENCE_FINAL,
+            }],
+            vout: vec![TXOutput::new(SUBSIDY, alice.get_address()).unwrap()],
+            lock_time: 0,
+        };
+        tx.id = tx.hash().unwrap();
+        let psbt = PartiallySignedTransaction::new(tx, &bc).unwrap();
+        assert_eq!(psbt.status().unwrap(), [(0, 2)]);
+        assert!(psbt.finalize().is_err());
+
+        // two cosigners sign their own copies
+        let mut copies = Vec::new();
+        for seed in [5, 7] {
+            let mut wallets = test_wallets();
+            wallets.import_private_key(&[seed; 32]).unwrap();
+            let mut copy = PartiallySignedTransaction::decode(&psbt.encode().unwrap()).unwrap();
+            assert_eq!(copy.sign(&wallets).unwrap(), 1);
+            assert_eq!(copy.sign(&wallets).unwrap(), 0);
+            copies.push(copy);
+        }
+        let err = copies[0].finalize().unwrap_err();
+        assert!(err.to_string().contains("1 of 2"), "{}", err);
+
+        let mut combined = copies[0].clone();
+        combined.combine(&copies[1]).unwrap();
+        assert_eq!(combined.status().unwrap(), [(2, 2)]);
+        let signed = combined.finalize().unwrap();
+        assert_eq!(signed.id, psbt.tx.id);
+        let prev_txs = HashMap::from([(funding.id.clone(), funding)]);
+        assert!(signed.verify(prev_txs).unwrap());
+        assert!(bc.verify_transaction(&signed).unwrap());
+
+        // copies of another transaction do not combine
+        let mut different = psbt.tx.clone();
+        different.lock_time = 1;
+        different.id = different.hash().unwrap();
+        let other = PartiallySignedTransaction::new(different, &bc).unwrap();
+        assert!(combined.combine(&other).is_err());
+    }
+}
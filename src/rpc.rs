@@ -0,0 +1,970 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crypto::util::fixed_time_eq;
+use failure::{format_err, Fail};
+use log::{info, warn};
+use serde_json::{json, Value};
+
+use crate::amount::Amount;
+use crate::block::Block;
+use crate::blockchain::Blockchain;
+use crate::crypter::random_bytes;
+use crate::errors::Result;
+use crate::events::{parse_filter, EventHub};
+use crate::transaction::Transaction;
+use crate::wallet::{key_address, script_address, write_private_file, Wallets};
+
+pub const DEFAULT_RPC_BIND: &str = "127.0.0.1:8332";
+/// COOKIE_FILE holds the credentials of the running node, readable only by its user
+pub const COOKIE_FILE: &str = "data/.cookie";
+/// RPC_ADDRESS_FILE holds the address the running node serves JSON-RPC on
+pub const RPC_ADDRESS_FILE: &str = "data/.rpcaddress";
+const COOKIE_USER: &str = "__cookie__";
+const COOKIE_LEN: usize = 32;
+/// requests with a larger body are refused
+const MAX_BODY_LEN: usize = 1 << 20;
+/// the longest request or header line read, in bytes
+const MAX_LINE_LEN: usize = 8 * 1024;
+/// the most headers read from one request
+const MAX_HEADERS: usize = 100;
+/// connections beyond this many being served at once are turned away
+const MAX_CONNECTIONS: usize = 64;
+/// a client that stalls this long while sending or receiving is dropped
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// JSON-RPC error codes
+pub const RPC_INVALID_REQUEST: i64 = -32600;
+pub const RPC_METHOD_NOT_FOUND: i64 = -32601;
+pub const RPC_INVALID_PARAMS: i64 = -32602;
+pub const RPC_PARSE_ERROR: i64 = -32700;
+/// RPC_MISC_ERROR is returned for every failure of a method without its own code
+pub const RPC_MISC_ERROR: i64 = -1;
+
+/// RpcConfig is where the RPC server listens and the credentials it accepts
+/// besides the cookie
+#[derive(Debug, Clone)]
+pub struct RpcConfig {
+    pub bind: String,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    /// serve the read-only REST explorer API to GET requests without credentials
+    pub rest: bool,
+    /// serve the /events stream without credentials, to pages of any origin
+    pub public_events: bool,
+}
+
+impl Default for RpcConfig {
+    fn default() -> Self {
+        RpcConfig {
+            bind: DEFAULT_RPC_BIND.to_string(),
+            user: None,
+            password: None,
+            rest: false,
+            public_events: false,
+        }
+    }
+}
+
+/// RpcError is a failure with a JSON-RPC error code
+#[derive(Debug)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (code {})", self.message, self.code)
+    }
+}
+
+impl Fail for RpcError {}
+
+impl RpcError {
+    pub fn new(code: i64, message: &str) -> RpcError {
+        RpcError {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+/// HttpError is a request the server refuses to read, answered with its HTTP status
+#[derive(Debug)]
+pub struct HttpError {
+    pub status: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.status)
+    }
+}
+
+impl Fail for HttpError {}
+
+impl HttpError {
+    pub fn new(status: &'static str, message: &str) -> HttpError {
+        HttpError {
+            status,
+            message: message.to_string(),
+        }
+    }
+}
+
+/// RpcCall is a call to a method, on the wallet named by a /wallet/<name> path
+#[derive(Debug, Clone)]
+pub struct RpcCall {
+    pub wallet: Option<String>,
+    pub method: String,
+    pub params: Vec<Value>,
+}
+
+impl RpcCall {
+    /// Param returns the positional parameter `i`
+    pub fn param(&self, i: usize) -> Option<&Value> {
+        self.params.get(i).filter(|value| !value.is_null())
+    }
+
+    /// StrParam returns the string parameter `i`, failing when it is missing
+    pub fn str_param(&self, i: usize, name: &str) -> Result<&str> {
+        match self.param(i) {
+            Some(Value::String(s)) => Ok(s),
+            _ => Err(invalid_params(&format!("{} must be a string", name))),
+        }
+    }
+
+    /// OptStrParam returns the string parameter `i`, None when it is not given
+    pub fn opt_str_param(&self, i: usize, name: &str) -> Result<Option<&str>> {
+        match self.param(i) {
+            Some(_) => Ok(Some(self.str_param(i, name)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// AmountParam returns the amount parameter `i`, given as a number or a decimal string of coins
+    pub fn amount_param(&self, i: usize, name: &str) -> Result<Amount> {
+        match self.param(i) {
+            Some(value) => parse_amount(value),
+            None => Err(invalid_params(&format!("{} must be an amount", name))),
+        }
+    }
+
+    /// RecipientsParam returns the payments of parameter `i`, an object of
+    /// address: amount or, to pay an address twice, an array of [address, amount] pairs
+    pub fn recipients_param(&self, i: usize) -> Result<Vec<(String, Amount)>> {
+        let invalid = || invalid_params("amounts must be an object of address: amount");
+        match self.param(i) {
+            Some(Value::Object(amounts)) => amounts
+                .iter()
+                .map(|(address, amount)| Ok((address.clone(), parse_amount(amount)?)))
+                .collect(),
+            Some(Value::Array(pairs)) => pairs
+                .iter()
+                .map(|pair| match pair.as_array().map(|pair| pair.as_slice()) {
+                    Some([Value::String(address), amount]) => {
+                        Ok((address.clone(), parse_amount(amount)?))
+                    }
+                    _ => Err(invalid()),
+                })
+                .collect(),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// ParseAmount reads an amount given as a number or a decimal string of coins
+pub fn parse_amount(value: &Value) -> Result<Amount> {
+    match value {
+        Value::String(amount) => amount.parse(),
+        Value::Number(amount) => amount.to_string().parse(),
+        _ => Err(invalid_params("amounts must be numbers")),
+    }
+}
+
+/// RpcClient calls the JSON-RPC server of the node running on this data directory
+pub struct RpcClient {
+    address: String,
+    auth: String,
+}
+
+impl RpcClient {
+    /// Connect returns a client of the node running on this data directory,
+    /// or None when no node answers
+    pub fn connect() -> Option<RpcClient> {
+        let address = std::fs::read_to_string(RPC_ADDRESS_FILE).ok()?;
+        let cookie = std::fs::read_to_string(COOKIE_FILE).ok()?;
+        let (user, password) = cookie.trim().split_once(':')?;
+        let address = address.trim().to_string();
+        // a node that crashed leaves its files behind
+        TcpStream::connect(&address).ok()?;
+        Some(RpcClient {
+            address,
+            auth: basic_auth(user, password),
+        })
+    }
+
+    /// Call invokes a method of the node, on a wallet when one is given
+    pub fn call(&self, wallet: Option<&str>, method: &str, params: Vec<Value>) -> Result<Value> {
+        let path = match wallet {
+            Some(wallet) => format!("/wallet/{}", wallet),
+            None => String::from("/"),
+        };
+        let body = json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params});
+        let body = serde_json::to_vec(&body)?;
+        let mut stream = TcpStream::connect(&self.address)?;
+        let head = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nAuthorization: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            path,
+            self.address,
+            self.auth,
+            body.len()
+        );
+        stream.write_all(head.as_bytes())?;
+        stream.write_all(&body)?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        let (status, body) = match response.split_once("\r\n\r\n") {
+            Some((head, body)) => (head.lines().next().unwrap_or_default(), body),
+            None => return Err(format_err!("malformed response from the node")),
+        };
+        if !status.starts_with("HTTP/1.1 200") {
+            return Err(format_err!("the node answered {}", status));
+        }
+        let response: Value = serde_json::from_str(body)?;
+        match response.get("error") {
+            Some(Value::Null) | None => Ok(response["result"].clone()),
+            Some(error) => Err(format_err!(
+                "{}",
+                error["message"]
+                    .as_str()
+                    .unwrap_or("the node returned an error")
+            )),
+        }
+    }
+}
+
+/// RestFormat is the representation a REST resource is asked for in, chosen
+/// with a .json or .bin suffix on the path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestFormat {
+    Json,
+    Bin,
+}
+
+/// RestRequest is a GET of an explorer resource, its path split into segments
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RestRequest {
+    pub segments: Vec<String>,
+    pub format: RestFormat,
+}
+
+/// RestBody is the answer to a REST request, JSON or the raw bincode bytes
+pub enum RestBody {
+    Json(Value),
+    Bin(Vec<u8>),
+}
+
+/// invalid_params reports a missing or malformed parameter
+pub fn invalid_params(message: &str) -> failure::Error {
+    RpcError::new(RPC_INVALID_PARAMS, message).into()
+}
+
+/// HttpRequest is the part of an HTTP/1.1 request the node servers read
+pub struct HttpRequest {
+    pub method: String,
+    pub path: String,
+    /// header names are lower case
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+/// ReadRequest reads the request line, headers and body of an HTTP request,
+/// None when the client closed the connection without sending one. A request
+/// that is malformed or too large fails with an HttpError
+pub fn read_request(stream: &TcpStream) -> Result<Option<HttpRequest>> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    if read_line(&mut reader, &mut line, "400 Bad Request")? == 0 {
+        return Ok(None);
+    }
+    let mut parts = line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+        _ => {
+            let message = format!("malformed request line '{}'", line.trim());
+            return Err(HttpError::new("400 Bad Request", &message).into());
+        }
+    };
+    let mut headers = HashMap::new();
+    loop {
+        if read_line(
+            &mut reader,
+            &mut line,
+            "431 Request Header Fields Too Large",
+        )? == 0
+            || line.trim().is_empty()
+        {
+            break;
+        }
+        if headers.len() == MAX_HEADERS {
+            let message = format!("more than {} headers", MAX_HEADERS);
+            return Err(HttpError::new("431 Request Header Fields Too Large", &message).into());
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+    let len: usize = match headers.get("content-length") {
+        Some(len) => len
+            .parse()
+            .map_err(|_| HttpError::new("400 Bad Request", "malformed content length"))?,
+        None => 0,
+    };
+    if len > MAX_BODY_LEN {
+        let message = format!("request body of {} bytes is too large", len);
+        return Err(HttpError::new("413 Payload Too Large", &message).into());
+    }
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body)?;
+    Ok(Some(HttpRequest {
+        method,
+        path,
+        headers,
+        body,
+    }))
+}
+
+/// read_line reads a line of at most MAX_LINE_LEN bytes into `line`, a longer
+/// one fails with the status
+fn read_line<R: BufRead>(reader: &mut R, line: &mut String, status: &'static str) -> Result<usize> {
+    line.clear();
+    let read = reader
+        .by_ref()
+        .take(MAX_LINE_LEN as u64 + 1)
+        .read_line(line)?;
+    if read > MAX_LINE_LEN {
+        let message = format!("line longer than {} bytes", MAX_LINE_LEN);
+        return Err(HttpError::new(status, &message).into());
+    }
+    Ok(read)
+}
+
+/// WriteResponse writes an HTTP response and closes the connection
+pub fn write_response(
+    stream: &mut TcpStream,
+    status: &str,
+    headers: &[(&str, &str)],
+    content_type: &str,
+    body: &[u8],
+) -> Result<()> {
+    let mut head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body)?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Serve answers JSON-RPC calls on the configured address with `handler`, until
+/// a client calls stop, and subscribes GETs of /events to the chain events of
+/// `events`. Every connection is served on its own thread, up to
+/// MAX_CONNECTIONS at once. It writes the cookie and address files local
+/// clients find the node with and removes them when it returns
+pub fn serve<F, R>(config: &RpcConfig, handler: F, rest: R, events: &EventHub) -> Result<()>
+where
+    F: Fn(&RpcCall) -> Result<Value> + Send + Sync + 'static,
+    R: Fn(&RestRequest) -> Result<Option<RestBody>> + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind(&config.bind)?;
+    let cookie = hex::encode(random_bytes(COOKIE_LEN));
+    write_private_file(
+        COOKIE_FILE,
+        format!("{}:{}", COOKIE_USER, cookie).as_bytes(),
+    )?;
+    std::fs::write(RPC_ADDRESS_FILE, &config.bind)?;
+    let mut credentials = vec![basic_auth(COOKIE_USER, &cookie)];
+    if let (Some(user), Some(password)) = (&config.user, &config.password) {
+        credentials.push(basic_auth(user, password));
+    }
+    info!("RPC server listening on {}", &config.bind);
+    let credentials = Arc::new(credentials);
+    let handler = Arc::new(handler);
+    let rest = if config.rest {
+        Some(Arc::new(rest))
+    } else {
+        None
+    };
+    let stopping = Arc::new(AtomicBool::new(false));
+    let active = Arc::new(AtomicUsize::new(0));
+
+    for stream in listener.incoming() {
+        if stopping.load(Ordering::SeqCst) {
+            break;
+        }
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("RPC connection failed: {}", e);
+                continue;
+            }
+        };
+        if active.load(Ordering::SeqCst) >= MAX_CONNECTIONS {
+            warn!(
+                "RPC connection turned away, {} are being served",
+                MAX_CONNECTIONS
+            );
+            let _ = write_response(
+                &mut stream,
+                "503 Service Unavailable",
+                &[],
+                "text/plain",
+                b"",
+            );
+            continue;
+        }
+        let slot = ConnectionSlot::take(&active);
+        let credentials = Arc::clone(&credentials);
+        let handler = Arc::clone(&handler);
+        let rest = rest.clone();
+        let events = events.clone();
+        let stopping = Arc::clone(&stopping);
+        let bind = config.bind.clone();
+        let public_events = config.public_events;
+        thread::spawn(move || {
+            let _slot = slot;
+            let served = stream
+                .set_read_timeout(Some(IO_TIMEOUT))
+                .and_then(|_| stream.set_write_timeout(Some(IO_TIMEOUT)))
+                .map_err(failure::Error::from)
+                .and_then(|_| {
+                    let rest = rest.as_deref();
+                    handle_http(
+                        &mut stream,
+                        &credentials,
+                        &*handler,
+                        rest,
+                        &events,
+                        public_events,
+                    )
+                });
+            match served {
+                Ok(true) => {
+                    stopping.store(true, Ordering::SeqCst);
+                    // wake the listener up so it sees the server is stopping
+                    let _ = TcpStream::connect(&bind);
+                }
+                Ok(false) => {}
+                Err(e) => warn!("RPC request failed: {}", e),
+            }
+        });
+    }
+    let _ = std::fs::remove_file(COOKIE_FILE);
+    let _ = std::fs::remove_file(RPC_ADDRESS_FILE);
+    Ok(())
+}
+
+/// ConnectionSlot counts a connection being served until it is dropped
+struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl ConnectionSlot {
+    fn take(active: &Arc<AtomicUsize>) -> ConnectionSlot {
+        active.fetch_add(1, Ordering::SeqCst);
+        ConnectionSlot(Arc::clone(active))
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// handle_http authenticates and answers one request, returning whether it
+/// asked the server to stop. REST requests and, when public, event streams
+/// need no credentials
+fn handle_http<F, R>(
+    stream: &mut TcpStream,
+    credentials: &[String],
+    handler: &F,
+    rest: Option<&R>,
+    events: &EventHub,
+    public_events: bool,
+) -> Result<bool>
+where
+    F: Fn(&RpcCall) -> Result<Value>,
+    R: Fn(&RestRequest) -> Result<Option<RestBody>>,
+{
+    let request = match read_request(stream) {
+        Ok(Some(request)) => request,
+        Ok(None) => return Ok(false),
+        Err(e) => match e.downcast::<HttpError>() {
+            Ok(refused) => {
+                warn!("RPC request refused: {}", refused);
+                write_response(stream, refused.status, &[], "text/plain", b"")?;
+                return Ok(false);
+            }
+            Err(e) => return Err(e),
+        },
+    };
+    let events_path = match request.path.split_once('?') {
+        Some((path, _)) => path == "/events",
+        None => request.path == "/events",
+    };
+    if request.method == "GET" && events_path && public_events {
+        subscribe_events(stream, &request.path, events, true)?;
+        return Ok(false);
+    }
+    if let (Some(rest), "GET", false) = (rest, request.method.as_str(), events_path) {
+        handle_rest(stream, &request.path, rest)?;
+        return Ok(false);
+    }
+    let authorized = match request.headers.get("authorization") {
+        // compared in constant time, not to leak how much of a guess is right
+        Some(auth) => credentials
+            .iter()
+            .any(|c| fixed_time_eq(c.as_bytes(), auth.as_bytes())),
+        None => false,
+    };
+    if !authorized {
+        warn!("RPC request with wrong credentials");
+        let challenge = [("WWW-Authenticate", "Basic realm=\"jsonrpc\"")];
+        write_response(stream, "401 Unauthorized", &challenge, "text/plain", b"")?;
+        return Ok(false);
+    }
+    if request.method == "GET" && events_path {
+        subscribe_events(stream, &request.path, events, false)?;
+        return Ok(false);
+    }
+    if request.method != "POST" {
+        write_response(stream, "405 Method Not Allowed", &[], "text/plain", b"")?;
+        return Ok(false);
+    }
+    let wallet = match request.path.as_str() {
+        "/" => None,
+        path => match path.strip_prefix("/wallet/") {
+            Some(name) if !name.is_empty() => Some(name.to_string()),
+            _ => {
+                write_response(stream, "404 Not Found", &[], "text/plain", b"")?;
+                return Ok(false);
+            }
+        },
+    };
+
+    let (response, stop) = match serde_json::from_slice::<Value>(&request.body) {
+        Ok(body) => {
+            let id = body.get("id").cloned().unwrap_or(Value::Null);
+            match parse_call(&body, wallet) {
+                Ok(call) => {
+                    let result = handler(&call);
+                    let stop = call.method == "stop" && result.is_ok();
+                    (response_json(id, result), stop)
+                }
+                Err(e) => (response_json(id, Err(e)), false),
+            }
+        }
+        Err(e) => {
+            let err = RpcError::new(RPC_PARSE_ERROR, &e.to_string());
+            (response_json(Value::Null, Err(err.into())), false)
+        }
+    };
+    let body = serde_json::to_vec(&response)?;
+    write_response(stream, "200 OK", &[], "application/json", &body)?;
+    Ok(stop)
+}
+
+/// handle_rest answers a GET of an explorer resource, with 404 for unknown
+/// resources and 400 for malformed ones
+fn handle_rest<R>(stream: &mut TcpStream, path: &str, rest: &R) -> Result<()>
+where
+    R: Fn(&RestRequest) -> Result<Option<RestBody>>,
+{
+    // the dashboard may be served from another origin
+    let cors = [("Access-Control-Allow-Origin", "*")];
+    let answer = match parse_rest(path) {
+        Some(request) => rest(&request),
+        None => Ok(None),
+    };
+    match answer {
+        Ok(Some(RestBody::Json(value))) => {
+            let body = serde_json::to_vec(&value)?;
+            write_response(stream, "200 OK", &cors, "application/json", &body)
+        }
+        Ok(Some(RestBody::Bin(data))) => {
+            write_response(stream, "200 OK", &cors, "application/octet-stream", &data)
+        }
+        Ok(None) => write_response(stream, "404 Not Found", &cors, "text/plain", b"not found"),
+        Err(e) => {
+            let body = e.to_string();
+            write_response(
+                stream,
+                "400 Bad Request",
+                &cors,
+                "text/plain",
+                body.as_bytes(),
+            )
+        }
+    }
+}
+
+/// subscribe_events hands the connection to the event hub, filtered by the
+/// `address` query parameters
+fn subscribe_events(
+    stream: &mut TcpStream,
+    path: &str,
+    events: &EventHub,
+    public: bool,
+) -> Result<()> {
+    let query = path.split_once('?').map_or("", |(_, query)| query);
+    match parse_filter(query) {
+        Ok(addresses) => events.subscribe(stream.try_clone()?, addresses, public),
+        Err(e) => {
+            let body = e.to_string();
+            write_response(
+                stream,
+                "400 Bad Request",
+                &[],
+                "text/plain",
+                body.as_bytes(),
+            )
+        }
+    }
+}
+
+/// parse_rest splits a REST path into its segments, taking the format from
+/// the suffix of the last one. The query string is ignored
+fn parse_rest(path: &str) -> Option<RestRequest> {
+    let path = path.split('?').next().unwrap_or_default();
+    let mut segments: Vec<String> = path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(String::from)
+        .collect();
+    let last = segments.pop()?;
+    let (last, format) = match last.rsplit_once('.') {
+        Some((name, "json")) => (name.to_string(), RestFormat::Json),
+        Some((name, "bin")) => (name.to_string(), RestFormat::Bin),
+        _ => (last, RestFormat::Json),
+    };
+    segments.push(last);
+    Some(RestRequest { segments, format })
+}
+
+/// parse_call reads the method and positional parameters of a JSON-RPC request
+fn parse_call(body: &Value, wallet: Option<String>) -> Result<RpcCall> {
+    let method = match body.get("method") {
+        Some(Value::String(method)) => method.clone(),
+        _ => return Err(RpcError::new(RPC_INVALID_REQUEST, "method must be a string").into()),
+    };
+    let params = match body.get("params") {
+        None | Some(Value::Null) => Vec::new(),
+        Some(Value::Array(params)) => params.clone(),
+        Some(_) => return Err(invalid_params("params must be an array")),
+    };
+    Ok(RpcCall {
+        wallet,
+        method,
+        params,
+    })
+}
+
+fn response_json(id: Value, result: Result<Value>) -> Value {
+    match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "result": result, "error": null, "id": id}),
+        Err(e) => {
+            let code = e
+                .downcast_ref::<RpcError>()
+                .map_or(RPC_MISC_ERROR, |e| e.code);
+            let message = match e.downcast_ref::<RpcError>() {
+                Some(e) => e.message.clone(),
+                None => e.to_string(),
+            };
+            json!({
+                "jsonrpc": "2.0",
+                "result": null,
+                "error": {"code": code, "message": message},
+                "id": id,
+            })
+        }
+    }
+}
+
+/// BlockJson describes a block and lists the ids of its transactions
+pub fn block_json(block: &Block) -> Value {
+    let txids: Vec<&String> = block.get_transaction().iter().map(|tx| &tx.id).collect();
+    json!({
+        "hash": block.get_hash(),
+        "height": block.get_height(),
+        "previousblockhash": block.get_prev_hash(),
+        "time": block.get_timestamp() as u64,
+        "tx": txids,
+    })
+}
+
+/// TxJson describes a transaction, amounts being decimal strings of coins
+pub fn tx_json(tx: &Transaction) -> Value {
+    let vin: Vec<Value> = tx
+        .vin
+        .iter()
+        .map(|vin| json!({"txid": vin.txid, "vout": vin.vout, "sequence": vin.sequence}))
+        .collect();
+    let vout: Vec<Value> = tx
+        .vout
+        .iter()
+        .enumerate()
+        .map(|(n, out)| {
+            json!({
+                "n": n,
+                "value": out.value.to_string(),
+                "address": script_address(&out.script_pub_key),
+            })
+        })
+        .collect();
+    json!({
+        "txid": tx.id,
+        "locktime": tx.lock_time,
+        "coinbase": tx.is_coinbase(),
+        "vin": vin,
+        "vout": vout,
+    })
+}
+
+/// AccountsJson lists the accounts of the wallet with their balances
+pub fn accounts_json(wallets: &Wallets) -> Result<Value> {
+    let mut accounts = Vec::new();
+    for account in wallets.get_accounts() {
+        let balance = wallets
+            .total_balance(Some(&account))
+            .ok_or_else(|| format_err!("balance overflows the amount range"))?;
+        accounts.push(json!({"account": account, "balance": balance.to_string()}));
+    }
+    Ok(json!(accounts))
+}
+
+/// AddressesJson lists the key addresses of the wallet, or of one of its
+/// accounts, with their labels and, for the whole wallet, the watch-only addresses
+pub fn addresses_json(wallets: &Wallets, account: Option<&str>) -> Value {
+    let (addresses, watch_only) = match account {
+        Some(account) => (wallets.get_account_addresses(account), Vec::new()),
+        None => (wallets.get_all_address(), wallets.get_watch_only()),
+    };
+    let addresses: Vec<Value> = addresses
+        .iter()
+        .map(|address| {
+            let label = wallets.get_label(address);
+            json!({"address": address, "label": label.label, "account": label.account})
+        })
+        .collect();
+    json!({"addresses": addresses, "watchonly": watch_only})
+}
+
+/// UnspentJson lists the coins of the wallet, or of one of its addresses, with
+/// their confirmations at the best height
+pub fn unspent_json(wallets: &Wallets, address: Option<&str>, best_height: i32) -> Value {
+    let coins: Vec<Value> = wallets
+        .list_unspent()
+        .into_iter()
+        .filter(|coin| address.is_none() || address == Some(coin.address.as_str()))
+        .map(|coin| {
+            json!({
+                "txid": coin.txid,
+                "vout": coin.vout,
+                "address": coin.address,
+                "value": coin.value.to_string(),
+                "confirmations": best_height - coin.height + 1,
+                "watchonly": wallets.is_watch_only(&coin.address),
+            })
+        })
+        .collect();
+    json!(coins)
+}
+
+/// AnchorJson describes the block and transaction anchoring `data`, null when
+/// the data is not in the chain
+pub fn anchor_json(bc: &Blockchain, data: &[u8]) -> Result<Value> {
+    Ok(match bc.find_data(data)? {
+        Some((block, tx)) => json!({
+            "block": block.get_hash(),
+            "height": block.get_height(),
+            "time": block.get_timestamp() as u64,
+            "txid": tx.id,
+        }),
+        None => Value::Null,
+    })
+}
+
+/// SwapJson describes the terms of a swap contract and the transaction that
+/// spent it, with the secret when it redeemed the contract
+pub fn swap_json(bc: &Blockchain, contract: &str) -> Result<Value> {
+    let contract_tx = bc.find_transaction(contract)?;
+    let (vout, htlc) = contract_tx.htlc_output()?;
+    let mut spent = Value::Null;
+    'blocks: for block in bc.iter() {
+        for tx in block?.get_transaction() {
+            for vin in &tx.vin {
+                if vin.txid == contract_tx.id && vin.vout == vout {
+                    let secret = vin.script_sig.htlc_secret().map(hex::encode);
+                    spent = json!({"txid": tx.id, "secret": secret});
+                    break 'blocks;
+                }
+            }
+        }
+    }
+    Ok(json!({
+        "amount": contract_tx.vout[vout as usize].value.to_string(),
+        "recipient": key_address(&htlc.recipient),
+        "refund": key_address(&htlc.refund),
+        "secrethash": hex::encode(&htlc.secret_hash),
+        "timeout": htlc.timeout,
+        "spent": spent,
+    }))
+}
+
+/// BasicAuth returns the Authorization header value of the credentials
+pub fn basic_auth(user: &str, password: &str) -> String {
+    format!(
+        "Basic {}",
+        base64_encode(format!("{}:{}", user, password).as_bytes())
+    )
+}
+
+fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_basic_auth() {
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foobar"), "Zm9vYmFy");
+        assert_eq!(
+            basic_auth("Aladdin", "open sesame"),
+            "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ=="
+        );
+    }
+
+    #[test]
+    fn test_parse_rest() {
+        let request = parse_rest("/address/BFhFHzubbpUwYnoQSw4JGD41YecEB6Ljhu/utxo?x=1").unwrap();
+        assert_eq!(
+            request.segments,
+            ["address", "BFhFHzubbpUwYnoQSw4JGD41YecEB6Ljhu", "utxo"]
+        );
+        assert_eq!(request.format, RestFormat::Json);
+        let request = parse_rest("/block-height/12.bin").unwrap();
+        assert_eq!(request.segments, ["block-height", "12"]);
+        assert_eq!(request.format, RestFormat::Bin);
+        assert_eq!(parse_rest("/"), None);
+    }
+
+    /// read_sent reads the request a client sends over a local connection
+    fn read_sent(request: Vec<u8>) -> Result<Option<HttpRequest>> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            let _ = stream.write_all(&request);
+        });
+        let (stream, _) = listener.accept().unwrap();
+        client.join().unwrap();
+        read_request(&stream)
+    }
+
+    fn refused_status(request: Vec<u8>) -> &'static str {
+        let err = read_sent(request).err().unwrap();
+        err.downcast::<HttpError>().unwrap().status
+    }
+
+    #[test]
+    fn test_read_request() {
+        let request = read_sent(b"POST / HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}".to_vec());
+        let request = request.unwrap().unwrap();
+        assert_eq!(
+            (request.method.as_str(), request.path.as_str()),
+            ("POST", "/")
+        );
+        assert_eq!(request.body, b"{}");
+
+        let long_header = format!("GET / HTTP/1.1\r\nX: {}\r\n\r\n", "a".repeat(MAX_LINE_LEN));
+        let status = refused_status(long_header.into_bytes());
+        assert_eq!(status, "431 Request Header Fields Too Large");
+        let headers: String = (0..=MAX_HEADERS)
+            .map(|i| format!("X{}: 1\r\n", i))
+            .collect();
+        let many_headers = format!("GET / HTTP/1.1\r\n{}\r\n", headers);
+        let status = refused_status(many_headers.into_bytes());
+        assert_eq!(status, "431 Request Header Fields Too Large");
+        let long_path = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE_LEN));
+        assert_eq!(refused_status(long_path.into_bytes()), "400 Bad Request");
+        let status = refused_status(b"GET\r\n\r\n".to_vec());
+        assert_eq!(status, "400 Bad Request");
+    }
+
+    /// rest_status answers the path with handle_rest over a local connection,
+    /// returning the status line the client reads
+    fn rest_status(path: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut response = String::new();
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        });
+        let (mut stream, _) = listener.accept().unwrap();
+        let rest = |request: &RestRequest| match request.segments.as_slice() {
+            [kind, hash] if kind == "block" && hash == "known" => {
+                Ok(Some(RestBody::Json(json!({}))))
+            }
+            [kind, _] if kind == "block" => Ok(None),
+            _ => Err(format_err!("malformed request")),
+        };
+        handle_rest(&mut stream, path, &rest).unwrap();
+        drop(stream);
+        let response = client.join().unwrap();
+        response.lines().next().unwrap_or_default().to_string()
+    }
+
+    #[test]
+    fn test_handle_rest() {
+        assert_eq!(rest_status("/block/known"), "HTTP/1.1 200 OK");
+        assert_eq!(rest_status("/block/unknown"), "HTTP/1.1 404 Not Found");
+        assert_eq!(rest_status("/"), "HTTP/1.1 404 Not Found");
+        assert_eq!(rest_status("/block-height/x"), "HTTP/1.1 400 Bad Request");
+    }
+}
@@ -0,0 +1,619 @@
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use failure::format_err;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::Result;
+use crate::tx::address_to_script;
+use crate::wallet::hash_pub_key;
+
+/// lock times below this are block heights, above it unix timestamps in milliseconds
+pub const LOCKTIME_THRESHOLD: u64 = 500_000_000;
+const MAX_NUM_LEN: usize = 8;
+const MAX_STACK_SIZE: usize = 1000;
+/// data carried by an OP_RETURN output is limited to this many bytes
+pub const MAX_DATA_LEN: usize = 80;
+
+/// Op is a single script instruction
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Op {
+    /// Push pushes the data onto the stack
+    Push(Vec<u8>),
+    /// Dup duplicates the top stack item
+    Dup,
+    /// Hash160 replaces the top item with its SHA256 then RIPEMD160 hash
+    Hash160,
+    /// Equal pops two items and pushes whether they are equal
+    Equal,
+    /// EqualVerify pops two items and fails the script unless they are equal
+    EqualVerify,
+    /// CheckSig pops a public key and a signature and pushes whether the signature is valid
+    CheckSig,
+    /// CheckMultiSig pops `n`, `n` public keys, `m` and one signature slot per
+    /// public key (in the same order, empty when that key did not sign) and
+    /// pushes whether at least `m` slots hold valid signatures
+    CheckMultiSig,
+    /// CheckLockTimeVerify fails the script unless the transaction lock time
+    /// has reached the number on top of the stack, which is left in place
+    CheckLockTimeVerify,
+    /// Drop removes the top stack item
+    Drop,
+    /// Sha256 replaces the top item with its SHA256 hash
+    Sha256,
+    /// If pops the top item and runs the following branch when it is true
+    If,
+    /// Else runs the following branch when the matching If did not
+    Else,
+    /// EndIf ends an If/Else block
+    EndIf,
+    /// Return fails the script, an output starting with it is provably unspendable
+    Return,
+}
+
+/// Htlc describes a hash time-locked contract: the recipient can spend with the
+/// preimage of `secret_hash`, the refund key after `timeout`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Htlc {
+    pub secret_hash: Vec<u8>,
+    pub recipient: Vec<u8>,
+    pub refund: Vec<u8>,
+    pub timeout: u64,
+}
+
+impl Htlc {
+    /// New returns the terms of a contract paying the key address `participant`,
+    /// and refundable to `refund_key` once `timeout` has passed
+    pub fn new(
+        participant: &str,
+        refund_key: &[u8],
+        secret_hash: Vec<u8>,
+        timeout: u64,
+    ) -> Result<Htlc> {
+        let recipient = match address_to_script(participant)?.p2pkh_hash() {
+            Some(hash) => hash.to_vec(),
+            None => return Err(format_err!("{} is not a key address", participant)),
+        };
+        let mut refund = refund_key.to_vec();
+        hash_pub_key(&mut refund);
+        Ok(Htlc {
+            secret_hash,
+            recipient,
+            refund,
+            timeout,
+        })
+    }
+}
+
+/// Script is a locking or unlocking program
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Script(pub Vec<Op>);
+
+/// SignatureChecker gives scripts access to the transaction being verified
+pub trait SignatureChecker {
+    /// CheckSig verifies a signature of the spending transaction
+    fn check_sig(&self, signature: &[u8], pub_key: &[u8]) -> Result<bool>;
+
+    /// CheckLockTime checks the spending transaction lock time against `lock_time`
+    fn check_lock_time(&self, lock_time: u64) -> bool;
+}
+
+impl Script {
+    /// NewP2PKH creates the standard pay-to-pubkey-hash locking script:
+    /// DUP HASH160 <pub_key_hash> EQUALVERIFY CHECKSIG
+    pub fn new_p2pkh(pub_key_hash: &[u8]) -> Script {
+        Script(vec![
+            Op::Dup,
+            Op::Hash160,
+            Op::Push(pub_key_hash.to_vec()),
+            Op::EqualVerify,
+            Op::CheckSig,
+        ])
+    }
+
+    /// NewP2PKHUnlock creates the unlocking script for a pay-to-pubkey-hash output
+    pub fn new_p2pkh_unlock(signature: &[u8], pub_key: &[u8]) -> Script {
+        Script(vec![
+            Op::Push(signature.to_vec()),
+            Op::Push(pub_key.to_vec()),
+        ])
+    }
+
+    /// P2PKHHash returns the public key hash if this is a pay-to-pubkey-hash locking script
+    pub fn p2pkh_hash(&self) -> Option<&[u8]> {
+        match &self.0[..] {
+            [Op::Dup, Op::Hash160, Op::Push(hash), Op::EqualVerify, Op::CheckSig] => Some(hash),
+            _ => None,
+        }
+    }
+
+    /// NewMultisig creates an M-of-N script: <m> <pub_key>... <n> CHECKMULTISIG
+    pub fn new_multisig(m: usize, pub_keys: &[Vec<u8>]) -> Script {
+        let mut ops = vec![Op::Push(encode_num(m as u64))];
+        for pub_key in pub_keys {
+            ops.push(Op::Push(pub_key.clone()));
+        }
+        ops.push(Op::Push(encode_num(pub_keys.len() as u64)));
+        ops.push(Op::CheckMultiSig);
+        Script(ops)
+    }
+
+    /// MultisigParams returns the required signature count and the public keys of an M-of-N script
+    pub fn multisig_params(&self) -> Option<(usize, Vec<Vec<u8>>)> {
+        let ops = &self.0;
+        if ops.len() < 4 || ops[ops.len() - 1] != Op::CheckMultiSig {
+            return None;
+        }
+        let mut pushes = Vec::new();
+        for op in &ops[..ops.len() - 1] {
+            match op {
+                Op::Push(data) => pushes.push(data.clone()),
+                _ => return None,
+            }
+        }
+        let m = decode_num(&pushes[0]).ok()? as usize;
+        let n = decode_num(&pushes[pushes.len() - 1]).ok()? as usize;
+        let pub_keys = pushes[1..pushes.len() - 1].to_vec();
+        if n != pub_keys.len() || m == 0 || m > n {
+            return None;
+        }
+        Some((m, pub_keys))
+    }
+
+    /// NewP2SH creates a pay-to-script-hash locking script: HASH160 <script_hash> EQUAL
+    pub fn new_p2sh(script_hash: &[u8]) -> Script {
+        Script(vec![Op::Hash160, Op::Push(script_hash.to_vec()), Op::Equal])
+    }
+
+    /// P2SHHash returns the script hash if this is a pay-to-script-hash locking script
+    pub fn p2sh_hash(&self) -> Option<&[u8]> {
+        match &self.0[..] {
+            [Op::Hash160, Op::Push(hash), Op::Equal] => Some(hash),
+            _ => None,
+        }
+    }
+
+    /// NewData creates an unspendable locking script carrying data: RETURN <data>
+    pub fn new_data(data: &[u8]) -> Script {
+        Script(vec![Op::Return, Op::Push(data.to_vec())])
+    }
+
+    /// DataPayload returns the data carried by a RETURN locking script
+    pub fn data_payload(&self) -> Option<&[u8]> {
+        match &self.0[..] {
+            [Op::Return, Op::Push(data)] => Some(data),
+            _ => None,
+        }
+    }
+
+    /// IsUnspendable checks whether no unlocking script can ever spend this locking script
+    pub fn is_unspendable(&self) -> bool {
+        matches!(self.0.first(), Some(Op::Return))
+    }
+
+    /// NewMultisigUnlock creates an unsigned unlocking script for a pay-to-script-hash
+    /// multisig output: one empty signature slot per key followed by the redeem script
+    pub fn new_multisig_unlock(redeem_script: &Script) -> Result<Script> {
+        let (_, pub_keys) = redeem_script
+            .multisig_params()
+            .ok_or_else(|| format_err!("redeem script is not a multisig script"))?;
+        let mut ops: Vec<Op> = pub_keys.iter().map(|_| Op::Push(Vec::new())).collect();
+        ops.push(Op::Push(redeem_script.to_bytes()?));
+        Ok(Script(ops))
+    }
+
+    /// RedeemScript returns the script carried in the last push of an unlocking script
+    pub fn redeem_script(&self) -> Option<Script> {
+        match self.0.last() {
+            Some(Op::Push(data)) => bincode::deserialize(data).ok(),
+            _ => None,
+        }
+    }
+
+    /// ToBytes serializes the script
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    /// Hash returns the HASH160 of the serialized script, used by pay-to-script-hash
+    pub fn hash(&self) -> Result<Vec<u8>> {
+        let mut data = self.to_bytes()?;
+        hash_pub_key(&mut data);
+        Ok(data)
+    }
+
+    /// NewTimelocked prefixes a locking script with an absolute timelock:
+    /// <lock_time> CHECKLOCKTIMEVERIFY DROP <script>
+    pub fn new_timelocked(lock_time: u64, script: &Script) -> Script {
+        let mut ops = vec![
+            Op::Push(encode_num(lock_time)),
+            Op::CheckLockTimeVerify,
+            Op::Drop,
+        ];
+        ops.extend(script.0.iter().cloned());
+        Script(ops)
+    }
+
+    /// SplitTimelock separates an absolute timelock prefix from the rest of the script
+    pub fn split_timelock(&self) -> (Option<u64>, Script) {
+        if let [Op::Push(n), Op::CheckLockTimeVerify, Op::Drop, rest @ ..] = &self.0[..] {
+            if let Ok(lock_time) = decode_num(n) {
+                return (Some(lock_time), Script(rest.to_vec()));
+            }
+        }
+        (None, self.clone())
+    }
+
+    /// NewHtlc creates a hash time-locked contract script:
+    /// IF SHA256 <secret_hash> EQUALVERIFY DUP HASH160 <recipient>
+    /// ELSE <timeout> CHECKLOCKTIMEVERIFY DROP DUP HASH160 <refund>
+    /// ENDIF EQUALVERIFY CHECKSIG
+    pub fn new_htlc(htlc: &Htlc) -> Script {
+        Script(vec![
+            Op::If,
+            Op::Sha256,
+            Op::Push(htlc.secret_hash.clone()),
+            Op::EqualVerify,
+            Op::Dup,
+            Op::Hash160,
+            Op::Push(htlc.recipient.clone()),
+            Op::Else,
+            Op::Push(encode_num(htlc.timeout)),
+            Op::CheckLockTimeVerify,
+            Op::Drop,
+            Op::Dup,
+            Op::Hash160,
+            Op::Push(htlc.refund.clone()),
+            Op::EndIf,
+            Op::EqualVerify,
+            Op::CheckSig,
+        ])
+    }
+
+    /// HtlcParams returns the contract terms if this is a hash time-locked contract script
+    pub fn htlc_params(&self) -> Option<Htlc> {
+        match &self.0[..] {
+            [Op::If, Op::Sha256, Op::Push(secret_hash), Op::EqualVerify, Op::Dup, Op::Hash160, Op::Push(recipient), Op::Else, Op::Push(timeout), Op::CheckLockTimeVerify, Op::Drop, Op::Dup, Op::Hash160, Op::Push(refund), Op::EndIf, Op::EqualVerify, Op::CheckSig] => {
+                Some(Htlc {
+                    secret_hash: secret_hash.clone(),
+                    recipient: recipient.clone(),
+                    refund: refund.clone(),
+                    timeout: decode_num(timeout).ok()?,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// NewHtlcRedeem creates the unlocking script spending a contract with the secret
+    pub fn new_htlc_redeem(signature: &[u8], pub_key: &[u8], secret: &[u8]) -> Script {
+        Script(vec![
+            Op::Push(signature.to_vec()),
+            Op::Push(pub_key.to_vec()),
+            Op::Push(secret.to_vec()),
+            Op::Push(encode_num(1)),
+        ])
+    }
+
+    /// NewHtlcRefund creates the unlocking script refunding a contract after its timeout
+    pub fn new_htlc_refund(signature: &[u8], pub_key: &[u8]) -> Script {
+        Script(vec![
+            Op::Push(signature.to_vec()),
+            Op::Push(pub_key.to_vec()),
+            Op::Push(Vec::new()),
+        ])
+    }
+
+    /// HtlcSecret returns the secret revealed by an unlocking script redeeming a contract
+    pub fn htlc_secret(&self) -> Option<&[u8]> {
+        match &self.0[..] {
+            [Op::Push(_), Op::Push(_), Op::Push(secret), Op::Push(branch)] if is_true(branch) => {
+                Some(secret)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Sha256Hash returns the SHA256 hash of the data
+pub fn sha256_hash(data: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.input(data);
+    let mut hash = vec![0; 32];
+    hasher.result(&mut hash);
+    hash
+}
+
+/// EncodeNum encodes a number as minimal little-endian bytes
+pub fn encode_num(n: u64) -> Vec<u8> {
+    let mut bytes = n.to_le_bytes().to_vec();
+    while bytes.last() == Some(&0) {
+        bytes.pop();
+    }
+    bytes
+}
+
+/// DecodeNum decodes a little-endian number of at most 8 bytes
+pub fn decode_num(bytes: &[u8]) -> Result<u64> {
+    if bytes.len() > MAX_NUM_LEN {
+        return Err(format_err!(
+            "script number is longer than {} bytes",
+            MAX_NUM_LEN
+        ));
+    }
+    let mut buf = [0u8; MAX_NUM_LEN];
+    buf[..bytes.len()].copy_from_slice(bytes);
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn is_true(item: &[u8]) -> bool {
+    item.iter().any(|b| *b != 0)
+}
+
+fn pop(stack: &mut Vec<Vec<u8>>) -> Result<Vec<u8>> {
+    stack
+        .pop()
+        .ok_or_else(|| format_err!("script stack underflow"))
+}
+
+/// VerifyScript runs the unlocking script followed by the locking script and
+/// reports whether the output may be spent
+pub fn verify_script(
+    script_sig: &Script,
+    script_pub_key: &Script,
+    checker: &dyn SignatureChecker,
+) -> Result<bool> {
+    // unlocking scripts may only push data, otherwise they could rewrite
+    // what the locking script is checking
+    if script_sig.0.iter().any(|op| !matches!(op, Op::Push(_))) {
+        return Ok(false);
+    }
+    let mut stack = Vec::new();
+    if !execute(script_sig, &mut stack, checker)? {
+        return Ok(false);
+    }
+    let mut redeem_stack = stack.clone();
+    if !execute(script_pub_key, &mut stack, checker)? {
+        return Ok(false);
+    }
+    if !stack.last().map(|top| is_true(top)).unwrap_or(false) {
+        return Ok(false);
+    }
+    if script_pub_key.p2sh_hash().is_none() {
+        return Ok(true);
+    }
+    // the hash matched, now the redeem script itself must accept the remaining items
+    let redeem_script: Script = match bincode::deserialize(&pop(&mut redeem_stack)?) {
+        Ok(script) => script,
+        Err(_) => return Ok(false),
+    };
+    if !execute(&redeem_script, &mut redeem_stack, checker)? {
+        return Ok(false);
+    }
+    Ok(redeem_stack.last().map(|top| is_true(top)).unwrap_or(false))
+}
+
+/// Execute runs a script on the stack, returning false when a verify operation fails
+pub fn execute(
+    script: &Script,
+    stack: &mut Vec<Vec<u8>>,
+    checker: &dyn SignatureChecker,
+) -> Result<bool> {
+    // one entry per open If, whether its current branch runs
+    let mut branches: Vec<bool> = Vec::new();
+    for op in &script.0 {
+        let running = branches.iter().all(|b| *b);
+        match op {
+            Op::If => {
+                let taken = running && is_true(&pop(stack)?);
+                branches.push(taken);
+                continue;
+            }
+            Op::Else => {
+                let depth = branches.len();
+                if depth == 0 {
+                    return Err(format_err!("ELSE without IF"));
+                }
+                let parent_running = branches[..depth - 1].iter().all(|b| *b);
+                branches[depth - 1] = parent_running && !branches[depth - 1];
+                continue;
+            }
+            Op::EndIf => {
+                if branches.pop().is_none() {
+                    return Err(format_err!("ENDIF without IF"));
+                }
+                continue;
+            }
+            _ if !running => continue,
+            _ => {}
+        }
+        match op {
+            Op::If | Op::Else | Op::EndIf => {}
+            Op::Push(data) => stack.push(data.clone()),
+            Op::Dup => {
+                let top = stack
+                    .last()
+                    .cloned()
+                    .ok_or_else(|| format_err!("script stack underflow"))?;
+                stack.push(top);
+            }
+            Op::Hash160 => {
+                let mut data = pop(stack)?;
+                hash_pub_key(&mut data);
+                stack.push(data);
+            }
+            Op::Equal => {
+                let a = pop(stack)?;
+                let b = pop(stack)?;
+                stack.push(vec![(a == b) as u8]);
+            }
+            Op::EqualVerify => {
+                let a = pop(stack)?;
+                let b = pop(stack)?;
+                if a != b {
+                    return Ok(false);
+                }
+            }
+            Op::CheckSig => {
+                let pub_key = pop(stack)?;
+                let signature = pop(stack)?;
+                let ok = checker.check_sig(&signature, &pub_key)?;
+                stack.push(vec![ok as u8]);
+            }
+            Op::CheckMultiSig => {
+                let n = decode_num(&pop(stack)?)? as usize;
+                if n > stack.len() {
+                    return Err(format_err!("script stack underflow"));
+                }
+                let mut pub_keys = Vec::new();
+                for _ in 0..n {
+                    pub_keys.push(pop(stack)?);
+                }
+                pub_keys.reverse();
+                let m = decode_num(&pop(stack)?)? as usize;
+                if m == 0 || m > n {
+                    return Ok(false);
+                }
+                let mut signatures = Vec::new();
+                for _ in 0..n {
+                    signatures.push(pop(stack)?);
+                }
+                signatures.reverse();
+                let mut valid = 0;
+                for (signature, pub_key) in signatures.iter().zip(pub_keys.iter()) {
+                    if signature.is_empty() {
+                        continue;
+                    }
+                    if !checker.check_sig(signature, pub_key)? {
+                        return Ok(false);
+                    }
+                    valid += 1;
+                }
+                stack.push(vec![(valid >= m) as u8]);
+            }
+            Op::Drop => {
+                pop(stack)?;
+            }
+            Op::Sha256 => {
+                let data = pop(stack)?;
+                stack.push(sha256_hash(&data));
+            }
+            Op::Return => return Ok(false),
+            Op::CheckLockTimeVerify => {
+                let top = stack
+                    .last()
+                    .ok_or_else(|| format_err!("script stack underflow"))?;
+                if !checker.check_lock_time(decode_num(top)?) {
+                    return Ok(false);
+                }
+            }
+        }
+        if stack.len() > MAX_STACK_SIZE {
+            return Err(format_err!("script stack overflow"));
+        }
+    }
+    if !branches.is_empty() {
+        return Err(format_err!("IF without ENDIF"));
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestChecker {
+        lock_time: u64,
+    }
+
+    impl SignatureChecker for TestChecker {
+        fn check_sig(&self, signature: &[u8], pub_key: &[u8]) -> Result<bool> {
+            Ok(signature == b"sig".as_slice() && pub_key.starts_with(b"key"))
+        }
+
+        fn check_lock_time(&self, lock_time: u64) -> bool {
+            self.lock_time >= lock_time
+        }
+    }
+
+    #[test]
+    fn test_p2pkh() {
+        let checker = TestChecker { lock_time: 0 };
+        let mut hash = b"key1".to_vec();
+        hash_pub_key(&mut hash);
+        let lock = Script::new_p2pkh(&hash);
+        assert_eq!(lock.p2pkh_hash(), Some(hash.as_slice()));
+        let unlock = Script::new_p2pkh_unlock(b"sig", b"key1");
+        assert!(verify_script(&unlock, &lock, &checker).unwrap());
+        let unlock = Script::new_p2pkh_unlock(b"sig", b"key2");
+        assert!(!verify_script(&unlock, &lock, &checker).unwrap());
+        let unlock = Script::new_p2pkh_unlock(b"bad", b"key1");
+        assert!(!verify_script(&unlock, &lock, &checker).unwrap());
+    }
+
+    #[test]
+    fn test_multisig_and_lock_time() {
+        let checker = TestChecker { lock_time: 10 };
+        let keys = vec![b"key1".to_vec(), b"key2".to_vec(), b"key3".to_vec()];
+        let lock = Script::new_multisig(2, &keys);
+        assert_eq!(lock.multisig_params(), Some((2, keys)));
+        let slots = |s: [&[u8]; 3]| Script(s.iter().map(|d| Op::Push(d.to_vec())).collect());
+        assert!(verify_script(&slots([b"sig", b"", b"sig"]), &lock, &checker).unwrap());
+        assert!(!verify_script(&slots([b"sig", b"", b""]), &lock, &checker).unwrap());
+        assert!(!verify_script(&slots([b"sig", b"bad", b"sig"]), &lock, &checker).unwrap());
+
+        let mut unlock = Script::new_multisig_unlock(&lock).unwrap();
+        unlock.0[0] = Op::Push(b"sig".to_vec());
+        unlock.0[1] = Op::Push(b"sig".to_vec());
+        let p2sh = Script::new_p2sh(&lock.hash().unwrap());
+        assert!(verify_script(&unlock, &p2sh, &checker).unwrap());
+        unlock.0[1] = Op::Push(Vec::new());
+        assert!(!verify_script(&unlock, &p2sh, &checker).unwrap());
+
+        let cltv = |n| Script::new_timelocked(n, &lock);
+        let unlock = slots([b"sig", b"sig", b""]);
+        assert!(verify_script(&unlock, &cltv(10), &checker).unwrap());
+        assert!(!verify_script(&unlock, &cltv(11), &checker).unwrap());
+        assert_eq!(cltv(10).split_timelock(), (Some(10), lock));
+        assert_eq!(decode_num(&encode_num(123_456)).unwrap(), 123_456);
+    }
+
+    #[test]
+    fn test_htlc() {
+        let hash = |key: &[u8]| {
+            let mut data = key.to_vec();
+            hash_pub_key(&mut data);
+            data
+        };
+        let htlc = Htlc {
+            secret_hash: sha256_hash(b"secret"),
+            recipient: hash(b"key1"),
+            refund: hash(b"key2"),
+            timeout: 10,
+        };
+        let lock = Script::new_htlc(&htlc);
+        assert_eq!(lock.htlc_params(), Some(htlc));
+
+        let before = TestChecker { lock_time: 9 };
+        let after = TestChecker { lock_time: 10 };
+        let redeem = Script::new_htlc_redeem(b"sig", b"key1", b"secret");
+        assert_eq!(redeem.htlc_secret(), Some(b"secret".as_slice()));
+        assert!(verify_script(&redeem, &lock, &before).unwrap());
+        let wrong = Script::new_htlc_redeem(b"sig", b"key1", b"guess");
+        assert!(!verify_script(&wrong, &lock, &before).unwrap());
+        let refund = Script::new_htlc_refund(b"sig", b"key2");
+        assert!(!verify_script(&refund, &lock, &before).unwrap());
+        assert!(verify_script(&refund, &lock, &after).unwrap());
+        let stolen = Script::new_htlc_refund(b"sig", b"key1");
+        assert!(!verify_script(&stolen, &lock, &after).unwrap());
+    }
+
+    #[test]
+    fn test_data() {
+        let lock = Script::new_data(b"document hash");
+        assert_eq!(lock.data_payload(), Some(b"document hash".as_slice()));
+        assert!(lock.is_unspendable());
+        let checker = TestChecker { lock_time: 0 };
+        let unlock = Script(vec![Op::Push(vec![1])]);
+        assert!(!verify_script(&unlock, &lock, &checker).unwrap());
+    }
+}
@@ -1,14 +1,32 @@
 /* uses */
 
 use failure::format_err;
-use log::{debug, info};
+use log::{debug, info, warn};
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 
-use crate::{block::Block, errors::Result, transaction::Transaction, utxoset::UTXOSet};
+use crate::{
+    address::{current_network, Address},
+    amount::Amount,
+    block::{now_millis, Block},
+    blockchain::Blockchain,
+    errors::Result,
+    events::{ChainEvent, EventHub, EventKind},
+    psbt::PartiallySignedTransaction,
+    rpc::{
+        self, accounts_json, addresses_json, anchor_json, block_json, invalid_params, swap_json,
+        tx_json, unspent_json, RestBody, RestFormat, RestRequest, RpcCall, RpcConfig, RpcError,
+    },
+    script::Htlc,
+    transaction::{SendOptions, Transaction},
+    tx::address_to_script,
+    utxoset::UTXOSet,
+    wallet::{key_address, script_address, AddressLabel, Wallets, DEFAULT_WALLET},
+};
 use std::{
     collections::{HashMap, HashSet},
     io::{Read, Write},
-    net::{TcpListener, TcpStream},
+    net::{IpAddr, TcpListener, TcpStream, ToSocketAddrs},
     sync::{Arc, Mutex},
     thread,
     time::Duration,
@@ -17,11 +35,19 @@ use std::{
 const KNOWN_NODE1: &str = "localhost:3000";
 const CMD_LEN: usize = 12;
 const VERSION: i32 = 1;
+/// peers whose clocks disagree by more than this are not trusted to adjust ours, in milliseconds
+const MAX_TIME_ADJUSTMENT: i64 = 70 * 60 * 1000;
+/// a peer clock offset is counted as at most this far off, in milliseconds
+const MAX_TIME_SAMPLE_OFFSET: i64 = 24 * 60 * 60 * 1000;
+/// clock offsets are kept for this many peers, later peers are not sampled
+const MAX_TIME_SAMPLES: usize = 200;
 
+#[derive(Clone)]
 pub struct Server {
     node_address: String,
     mining_address: String,
     inner: Arc<Mutex<ServerInner>>,
+    events: EventHub,
 }
 
 struct ServerInner {
@@ -29,6 +55,12 @@ struct ServerInner {
     utxo: UTXOSet,
     blocks_in_transit: Vec<String>,
     mempool: HashMap<String, Transaction>,
+    /// the clock offset of each peer host, in milliseconds
+    time_offsets: HashMap<IpAddr, i64>,
+    /// the loaded wallets by name, kept open while the node runs
+    wallets: HashMap<String, Wallets>,
+    /// when each unlocked wallet locks again, in milliseconds
+    unlocked_until: HashMap<String, u128>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -67,6 +99,7 @@ struct Versionmsg {
     addr_from: String,
     version: i32,
     best_height: i32,
+    timestamp: u128,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -81,7 +114,12 @@ enum Message {
 }
 
 impl Server {
-    pub fn new(port: &str, miner_address: &str, utxo: UTXOSet) -> Result<Server> {
+    pub fn new(
+        port: &str,
+        miner_address: &str,
+        utxo: UTXOSet,
+        wallets: HashMap<String, Wallets>,
+    ) -> Result<Server> {
         let mut node_set = HashSet::new();
         node_set.insert(String::from(KNOWN_NODE1));
         Ok(Server {
@@ -92,12 +130,16 @@ impl Server {
                 utxo,
                 blocks_in_transit: Vec::new(),
                 mempool: HashMap::new(),
+                time_offsets: HashMap::new(),
+                wallets,
+                unlocked_until: HashMap::new(),
             })),
+            events: EventHub::default(),
         })
     }
 
     pub fn send_transaction(tx: &Transaction, utxoset: UTXOSet) -> Result<()> {
-        let server = Server::new("7000", "", utxoset)?;
+        let server = Server::new("7000", "", utxoset, HashMap::new())?;
         server.send_tx(KNOWN_NODE1, tx)?;
         Ok(())
     }
@@ -107,6 +149,7 @@ impl Server {
             node_address: self.node_address.clone(),
             mining_address: self.mining_address.clone(),
             inner: Arc::clone(&self.inner),
+            events: self.events.clone(),
         };
         info!(
             "Starting server on {}, mining address: {}",
@@ -129,12 +172,680 @@ impl Server {
                 node_address: self.node_address.clone(),
                 mining_address: self.mining_address.clone(),
                 inner: Arc::clone(&self.inner),
+                events: self.events.clone(),
             };
             thread::spawn(move || server1.handle_connection(stream));
         }
         Ok(())
     }
 
+    /// StartRpc serves the JSON-RPC interface in the background. Once a client
+    /// calls stop, the block database is flushed and the process exits
+    pub fn start_rpc(&self, config: RpcConfig) {
+        let server = Server {
+            node_address: self.node_address.clone(),
+            mining_address: self.mining_address.clone(),
+            inner: Arc::clone(&self.inner),
+            events: self.events.clone(),
+        };
+        let rpc_server = server.clone();
+        let rest_server = server.clone();
+        thread::spawn(move || {
+            let served = rpc::serve(
+                &config,
+                move |call| rpc_server.handle_rpc(call),
+                move |request| rest_server.handle_rest(request),
+                &server.events,
+            );
+            if let Err(e) = served {
+                warn!("RPC server on {} failed: {}", &config.bind, e);
+                return;
+            }
+            info!("stopping on RPC request");
+            if let Err(e) = server.inner.lock().unwrap().utxo.blockchain.flush() {
+                warn!("flushing the block database failed: {}", e);
+            }
+            std::process::exit(0);
+        });
+    }
+
+    fn handle_rpc(&self, call: &RpcCall) -> Result<Value> {
+        info!("receive rpc call: {} {:?}", &call.method, &call.params);
+        match call.method.as_str() {
+            "getblockcount" => Ok(json!(self.get_best_height()?)),
+            "getblockchaininfo" => Ok(json!({
+                "chain": current_network().to_string(),
+                "blocks": self.get_best_height()?,
+            })),
+            "getblockhash" => {
+                let height = match call.param(0).and_then(Value::as_i64) {
+                    Some(height) => height,
+                    None => return Err(invalid_params("height must be a number")),
+                };
+                match self.get_block_hash_at(height)? {
+                    Some(hash) => Ok(json!(hash)),
+                    None => Err(invalid_params("block height out of range")),
+                }
+            }
+            "getblock" => {
+                let block = self.get_block(call.str_param(0, "blockhash")?)?;
+                // verbosity 0 asks for the serialized block
+                match call.param(1).and_then(Value::as_u64) {
+                    Some(0) => Ok(json!(hex::encode(bincode::serialize(&block)?))),
+                    _ => Ok(block_json(&block)),
+                }
+            }
+            "gettransaction" => self.rpc_get_transaction(call.str_param(0, "txid")?),
+            "getbalance" => self.rpc_get_balance(call),
+            "sendtoaddress" => {
+                let recipients = [(
+                    call.str_param(0, "address")?.to_string(),
+                    call.amount_param(1, "amount")?,
+                )];
+                self.rpc_send(call, &recipients, 2, &SendOptions::default())
+            }
+            "sendmany" => {
+                let recipients = call.recipients_param(0)?;
+                let options = match call.param(2) {
+                    Some(options) => serde_json::from_value(options.clone())
+                        .map_err(|e| invalid_params(&format!("invalid options: {}", e)))?,
+                    None => SendOptions::default(),
+                };
+                self.rpc_send(call, &recipients, 1, &options)
+            }
+            "sendrawtransaction" => {
+                let data = hex::decode(call.str_param(0, "hexstring")?)?;
+                let tx: Transaction = bincode::deserialize(&data)?;
+                self.submit_tx(tx)
+            }
+            "getmempoolinfo" => {
+                let mut txids: Vec<String> = self.get_mempool().into_keys().collect();
+                txids.sort();
+                Ok(json!({"size": txids.len(), "txids": txids}))
+            }
+            "getpeerinfo" => {
+                let inner = self.inner.lock().unwrap();
+                let mut peers: Vec<&String> = inner
+                    .known_nodes
+                    .iter()
+                    .filter(|node| **node != self.node_address)
+                    .collect();
+                peers.sort();
+                let peers: Vec<Value> = peers
+                    .into_iter()
+                    .map(|node| {
+                        let offset = node_ips(node)
+                            .iter()
+                            .find_map(|ip| inner.time_offsets.get(ip));
+                        json!({"addr": node, "timeoffset": offset})
+                    })
+                    .collect();
+                Ok(json!(peers))
+            }
+            "reindex" => {
+                let inner = self.inner.lock().unwrap();
+                inner.utxo.reindex()?;
+                Ok(json!(inner.utxo.count_transactions()?))
+            }
+            "finddata" => {
+                let data = hex::decode(call.str_param(0, "hexdata")?)?;
+                anchor_json(&self.inner.lock().unwrap().utxo.blockchain, &data)
+            }
+            "auditswap" => {
+                let contract = call.str_param(0, "contract")?;
+                swap_json(&self.inner.lock().unwrap().utxo.blockchain, contract)
+            }
+            "senddata" => self.rpc_send_data(call),
+            "initiateswap" => self.rpc_initiate_swap(call),
+            "redeemswap" => {
+                let secret = hex::decode(call.str_param(1, "secret")?)?;
+                self.rpc_spend_swap(call, Some(&secret))
+            }
+            "refundswap" => self.rpc_spend_swap(call, None),
+            "stop" => Ok(json!("node stopping")),
+            _ => self.handle_wallet_rpc(call),
+        }
+    }
+
+    /// handle_wallet_rpc answers the methods managing the loaded wallets and
+    /// using the wallet the call is for
+    fn handle_wallet_rpc(&self, call: &RpcCall) -> Result<Value> {
+        let mut inner = self.inner.lock().unwrap();
+        let inner = &mut *inner;
+        match call.method.as_str() {
+            "createwallet" => {
+                let name = call.str_param(0, "wallet_name")?;
+                if Wallets::exists(name)? {
+                    return Err(format_err!("wallet {} already exists", name));
+                }
+                let wallets = Wallets::new(name)?;
+                Wallets::load(name)?;
+                inner.wallets.insert(name.to_string(), wallets);
+                Ok(json!(name))
+            }
+            "loadwallet" => {
+                let name = call.str_param(0, "wallet_name")?;
+                if !Wallets::exists(name)? {
+                    return Err(format_err!("wallet {} does not exist", name));
+                }
+                if inner.wallets.contains_key(name) {
+                    return Err(format_err!("wallet {} is already loaded", name));
+                }
+                let mut wallets = Wallets::new(name)?;
+                // catch up with the blocks connected while the wallet was not loaded
+                wallets.rescan(&inner.utxo.blockchain, 0)?;
+                wallets.save_all()?;
+                Wallets::load(name)?;
+                inner.wallets.insert(name.to_string(), wallets);
+                Ok(json!(name))
+            }
+            "unloadwallet" => {
+                let name = call.str_param(0, "wallet_name")?;
+                Wallets::unload(name)?;
+                inner.wallets.remove(name);
+                inner.unlocked_until.remove(name);
+                Ok(json!(name))
+            }
+            "encryptwallet" => {
+                let passphrase = call.str_param(0, "passphrase")?;
+                call_wallets(&mut inner.wallets, call)?.check_encryptable(passphrase)?;
+                let name = call_wallet_name(call);
+                let wallets = match inner.wallets.remove(name) {
+                    Some(wallets) => wallets,
+                    None => return Err(format_err!("wallet {} is not loaded", name)),
+                };
+                let wallets = match wallets.encrypt_wallet(passphrase) {
+                    Ok(wallets) => wallets,
+                    Err(e) => {
+                        // reopen whichever database encrypting left in place
+                        inner.wallets.insert(name.to_string(), Wallets::new(name)?);
+                        return Err(e);
+                    }
+                };
+                inner.wallets.insert(name.to_string(), wallets);
+                Ok(Value::Null)
+            }
+            "walletpassphrase" => {
+                let passphrase = call.str_param(0, "passphrase")?;
+                let timeout = match call.param(1).and_then(Value::as_u64) {
+                    Some(timeout) => timeout,
+                    None => return Err(invalid_params("timeout must be a number of seconds")),
+                };
+                call_wallets(&mut inner.wallets, call)?.unlock(passphrase)?;
+                let name = call_wallet_name(call).to_string();
+                let expires = now_millis()? + u128::from(timeout) * 1000;
+                inner.unlocked_until.insert(name.clone(), expires);
+                let shared = Arc::clone(&self.inner);
+                thread::spawn(move || {
+                    thread::sleep(Duration::from_secs(timeout));
+                    relock_expired(&shared, &name);
+                });
+                Ok(Value::Null)
+            }
+            "walletlock" => {
+                call_wallets(&mut inner.wallets, call)?.lock()?;
+                inner.unlocked_until.remove(call_wallet_name(call));
+                Ok(Value::Null)
+            }
+            "getnewaddress" => {
+                let label = AddressLabel {
+                    label: call
+                        .opt_str_param(0, "label")?
+                        .unwrap_or_default()
+                        .to_string(),
+                    account: call
+                        .opt_str_param(1, "account")?
+                        .unwrap_or_default()
+                        .to_string(),
+                };
+                let wallets = call_wallets(&mut inner.wallets, call)?;
+                let (address, mnemonic) = wallets.new_address(&label)?;
+                wallets.save_all()?;
+                Ok(json!({"address": address, "mnemonic": mnemonic}))
+            }
+            "setlabel" => {
+                let wallets = call_wallets(&mut inner.wallets, call)?;
+                wallets.set_label(call.str_param(0, "address")?, call.str_param(1, "label")?)?;
+                wallets.save_all()?;
+                Ok(Value::Null)
+            }
+            "setaccount" => {
+                let wallets = call_wallets(&mut inner.wallets, call)?;
+                wallets
+                    .set_account(call.str_param(0, "address")?, call.str_param(1, "account")?)?;
+                wallets.save_all()?;
+                Ok(Value::Null)
+            }
+            "listaccounts" => accounts_json(call_wallets(&mut inner.wallets, call)?),
+            "listaddresses" => {
+                let account = call.opt_str_param(0, "account")?;
+                Ok(addresses_json(
+                    call_wallets(&mut inner.wallets, call)?,
+                    account,
+                ))
+            }
+            "listunspent" => {
+                let address = call.opt_str_param(0, "address")?;
+                let best_height = inner.utxo.blockchain.get_best_height()?;
+                let wallets = call_wallets(&mut inner.wallets, call)?;
+                Ok(unspent_json(wallets, address, best_height))
+            }
+            "getaddressinfo" => {
+                let address = Address::decode(call.str_param(0, "address")?)?.encode();
+                let wallets = call_wallets(&mut inner.wallets, call)?;
+                Ok(json!({
+                    "ismine": wallets.is_mine(&address),
+                    "iswatchonly": wallets.is_watch_only(&address),
+                }))
+            }
+            "getpubkey" => {
+                let address = call.str_param(0, "address")?;
+                match call_wallets(&mut inner.wallets, call)?.get_wallet(address) {
+                    Some(wallet) => Ok(json!(hex::encode(&wallet.public_key))),
+                    None => Err(format_err!("{} is not in the wallet", address)),
+                }
+            }
+            "dumpprivkey" => {
+                let address = call.str_param(0, "address")?;
+                let private_key =
+                    call_wallets(&mut inner.wallets, call)?.dump_private_key(address)?;
+                Ok(json!(hex::encode(private_key)))
+            }
+            "dumpwallet" => Ok(json!(call_wallets(&mut inner.wallets, call)?.dump()?)),
+            "signmessage" => {
+                let address = Address::decode(call.str_param(0, "address")?)?.encode();
+                let message = call.str_param(1, "message")?;
+                let wallets = call_wallets(&mut inner.wallets, call)?;
+                let signature = wallets.get_signing_wallet(&address)?.sign_message(message);
+                Ok(json!(hex::encode(signature)))
+            }
+            "addmultisigaddress" => {
+                let m = match call.param(0).and_then(Value::as_u64) {
+                    Some(m) => m as usize,
+                    None => return Err(invalid_params("nrequired must be a number")),
+                };
+                let keys = match call.param(1).and_then(Value::as_array) {
+                    Some(keys) => keys,
+                    None => return Err(invalid_params("keys must be an array")),
+                };
+                let wallets = call_wallets(&mut inner.wallets, call)?;
+                let mut pub_keys = Vec::new();
+                for key in keys {
+                    match key.as_str() {
+                        Some(key) => pub_keys.push(wallets.resolve_public_key(key)?),
+                        None => return Err(invalid_params("keys must be strings")),
+                    }
+                }
+                let address = wallets.create_multisig(m, &pub_keys)?;
+                wallets.save_all()?;
+                Ok(json!(address))
+            }
+            "rescan" => {
+                let from_height = match call.param(0) {
+                    Some(height) => match height.as_i64() {
+                        Some(height) => height as i32,
+                        None => return Err(invalid_params("start_height must be a number")),
+                    },
+                    None => 0,
+                };
+                let wallets = call_wallets(&mut inner.wallets, call)?;
+                let blocks = wallets.rescan(&inner.utxo.blockchain, from_height)?;
+                wallets.save_all()?;
+                Ok(json!(blocks))
+            }
+            "restorewallet" => {
+                let mnemonic = call.str_param(0, "mnemonic")?;
+                let wallets = call_wallets(&mut inner.wallets, call)?;
+                let restored = wallets.restore_seed(mnemonic, &inner.utxo.blockchain)?;
+                wallets.save_all()?;
+                Ok(json!({"restored": restored, "addresses": wallets.get_all_address()}))
+            }
+            "importprivkey" => {
+                let private_key = hex::decode(call.str_param(0, "privkey")?)
+                    .map_err(|_| invalid_params("the private key must be hex"))?;
+                let wallets = call_wallets(&mut inner.wallets, call)?;
+                let address = wallets.import_private_key(&private_key)?;
+                rescan_imported(wallets, &inner.utxo.blockchain)?;
+                Ok(json!(address))
+            }
+            "importaddress" => {
+                let address = call.str_param(0, "address")?;
+                let wallets = call_wallets(&mut inner.wallets, call)?;
+                wallets.import_address(address)?;
+                rescan_imported(wallets, &inner.utxo.blockchain)?;
+                Ok(Value::Null)
+            }
+            "importwallet" => {
+                let dump = call.str_param(0, "dump")?;
+                let wallets = call_wallets(&mut inner.wallets, call)?;
+                let entries = wallets.import_dump(dump)?;
+                rescan_imported(wallets, &inner.utxo.blockchain)?;
+                Ok(json!(entries))
+            }
+            "walletcreatefundedpsbt" => {
+                let from = call.str_param(0, "from")?;
+                let recipients = call.recipients_param(1)?;
+                let options = match call.param(2) {
+                    Some(options) => serde_json::from_value(options.clone())
+                        .map_err(|e| invalid_params(&format!("invalid options: {}", e)))?,
+                    None => SendOptions::default(),
+                };
+                let wallets = call_wallets(&mut inner.wallets, call)?;
+                let tx = Transaction::new_unsigned_many(
+                    from,
+                    wallets.get_multisig(from),
+                    &recipients,
+                    &options,
+                    &inner.utxo,
+                )?;
+                let psbt = PartiallySignedTransaction::new(tx, &inner.utxo.blockchain)?;
+                Ok(json!(psbt.encode()?))
+            }
+            "createmultisigtransaction" => {
+                let from = call.str_param(0, "from")?;
+                let to = call.str_param(1, "to")?;
+                let amount = call.amount_param(2, "amount")?;
+                let wallets = call_wallets(&mut inner.wallets, call)?;
+                let redeem_script = match wallets.get_multisig(from) {
+                    Some(script) => script.clone(),
+                    None => {
+                        return Err(format_err!(
+                            "{} is not a multisig address of the wallet",
+                            from
+                        ))
+                    }
+                };
+                let mut tx =
+                    Transaction::new_multisig_UTXO(&redeem_script, to, amount, &inner.utxo)?;
+                wallets.sign_transaction(&inner.utxo.blockchain, &mut tx)?;
+                Ok(json!(hex::encode(bincode::serialize(&tx)?)))
+            }
+            "signrawtransactionwithwallet" => {
+                let data = hex::decode(call.str_param(0, "hexstring")?)?;
+                let mut tx: Transaction = bincode::deserialize(&data)?;
+                let wallets = call_wallets(&mut inner.wallets, call)?;
+                wallets.sign_transaction(&inner.utxo.blockchain, &mut tx)?;
+                Ok(json!(hex::encode(bincode::serialize(&tx)?)))
+            }
+            "walletprocesspsbt" => {
+                let mut psbt = PartiallySignedTransaction::decode(call.str_param(0, "psbt")?)?;
+                let added = psbt.sign(call_wallets(&mut inner.wallets, call)?)?;
+                Ok(json!({"psbt": psbt.encode()?, "added": added}))
+            }
+            method => Err(RpcError::new(
+                rpc::RPC_METHOD_NOT_FOUND,
+                &format!("method {} not found", method),
+            )
+            .into()),
+        }
+    }
+
+    /// handle_rest answers the read-only explorer resources, None for unknown ones.
+    /// Blocks and transactions are also served as raw bincode
+    fn handle_rest(&self, request: &RestRequest) -> Result<Option<RestBody>> {
+        info!("receive rest request: {:?}", &request.segments);
+        let raw = request.format == RestFormat::Bin;
+        let segments: Vec<&str> = request.segments.iter().map(String::as_str).collect();
+        let value = match segments.as_slice() {
+            ["block", hash] => return self.rest_block(hash, raw),
+            ["block-height", height] => {
+                let height: i64 = height
+                    .parse()
+                    .map_err(|_| format_err!("block height '{}' is not a number", height))?;
+                return match self.get_block_hash_at(height)? {
+                    Some(hash) => self.rest_block(&hash, raw),
+                    None => Ok(None),
+                };
+            }
+            ["tx", txid] => return self.rest_tx(txid, raw),
+            ["address", address, "utxo"] => self.rest_address_utxo(address)?,
+            ["address", address, "txs"] => self.rest_address_txs(address)?,
+            ["mempool"] => {
+                let mut txs: Vec<Transaction> = self.get_mempool().into_values().collect();
+                txs.sort_by(|a, b| a.id.cmp(&b.id));
+                let txs: Vec<Value> = txs.iter().map(tx_json).collect();
+                json!({"size": txs.len(), "txs": txs})
+            }
+            ["chaininfo"] => {
+                let mempool_size = self.get_mempool().len();
+                let inner = self.inner.lock().unwrap();
+                let bc = &inner.utxo.blockchain;
+                let best_hash = match bc.iter().next() {
+                    Some(block) => Some(block?.get_hash()),
+                    None => None,
+                };
+                let median_time = match &best_hash {
+                    Some(hash) => bc.median_time_past(hash)?.map(|time| time as u64),
+                    None => None,
+                };
+                json!({
+                    "network": current_network().to_string(),
+                    "blocks": bc.get_best_height()?,
+                    "bestblockhash": best_hash,
+                    "mediantime": median_time,
+                    "mempoolsize": mempool_size,
+                })
+            }
+            _ => return Ok(None),
+        };
+        if raw {
+            return Err(format_err!(
+                "only blocks and transactions are served as raw bincode"
+            ));
+        }
+        Ok(Some(RestBody::Json(value)))
+    }
+
+    /// rest_block describes a block with its transactions in full
+    fn rest_block(&self, hash: &str, raw: bool) -> Result<Option<RestBody>> {
+        let block = match self.get_block(hash) {
+            Ok(block) => block,
+            Err(_) => return Ok(None),
+        };
+        if raw {
+            return Ok(Some(RestBody::Bin(bincode::serialize(&block)?)));
+        }
+        let mut result = block_json(&block);
+        result["tx"] = json!(block
+            .get_transaction()
+            .iter()
+            .map(tx_json)
+            .collect::<Vec<Value>>());
+        Ok(Some(RestBody::Json(result)))
+    }
+
+    /// rest_tx describes a transaction of the mempool or the best chain
+    fn rest_tx(&self, txid: &str, raw: bool) -> Result<Option<RestBody>> {
+        let tx = match self.get_mempool_tx(txid) {
+            Some(tx) => tx,
+            None => match self
+                .inner
+                .lock()
+                .unwrap()
+                .utxo
+                .blockchain
+                .find_transaction(txid)
+            {
+                Ok(tx) => tx,
+                Err(_) => return Ok(None),
+            },
+        };
+        if raw {
+            return Ok(Some(RestBody::Bin(bincode::serialize(&tx)?)));
+        }
+        Ok(Some(RestBody::Json(self.rpc_get_transaction(&tx.id)?)))
+    }
+
+    /// rest_address_utxo lists the unspent outputs of an address, including timelocked ones
+    fn rest_address_utxo(&self, address: &str) -> Result<Value> {
+        let lock = address_to_script(address)?;
+        let mut outpoints = self.inner.lock().unwrap().utxo.find_outpoints(&lock)?;
+        outpoints.sort_by(|a, b| (&a.0, a.1).cmp(&(&b.0, b.1)));
+        let utxos: Vec<Value> = outpoints
+            .iter()
+            .map(|(txid, vout, out)| {
+                json!({"txid": txid, "vout": vout, "value": out.value.to_string()})
+            })
+            .collect();
+        Ok(json!(utxos))
+    }
+
+    /// rest_address_txs lists the transactions paying to or spending from an
+    /// address, the unconfirmed ones first
+    fn rest_address_txs(&self, address: &str) -> Result<Value> {
+        let lock = address_to_script(address)?;
+        let pending: Vec<Transaction> = self.get_mempool().into_values().collect();
+        let inner = self.inner.lock().unwrap();
+        let bc = &inner.utxo.blockchain;
+        let best_height = bc.get_best_height()?;
+        let txs: Vec<Value> = bc
+            .find_address_transactions(&lock, &pending)?
+            .iter()
+            .map(|(height, tx)| {
+                let mut result = tx_json(tx);
+                result["height"] = json!(height);
+                result["confirmations"] = json!(height.map_or(0, |h| best_height - h + 1));
+                result
+            })
+            .collect();
+        Ok(json!(txs))
+    }
+
+    /// rpc_get_transaction finds a transaction in the mempool or the best chain
+    fn rpc_get_transaction(&self, txid: &str) -> Result<Value> {
+        if let Some(tx) = self.get_mempool_tx(txid) {
+            let mut result = tx_json(&tx);
+            result["confirmations"] = json!(0);
+            return Ok(result);
+        }
+        let inner = self.inner.lock().unwrap();
+        let bc = &inner.utxo.blockchain;
+        let tx = bc.find_transaction(txid)?;
+        let (height, _) = bc.find_transaction_block(txid)?;
+        let mut result = tx_json(&tx);
+        result["height"] = json!(height);
+        result["confirmations"] = json!(bc.get_best_height()? - height + 1);
+        Ok(result)
+    }
+
+    /// rpc_get_balance returns the balance of an address from the UTXO set, or
+    /// without one the balance of the wallet
+    fn rpc_get_balance(&self, call: &RpcCall) -> Result<Value> {
+        let balance = match call.param(0) {
+            Some(_) => {
+                let lock = address_to_script(call.str_param(0, "address")?)?;
+                let utxos = self.inner.lock().unwrap().utxo.find_UTXO(&lock)?;
+                Amount::checked_sum(utxos.iter().map(|out| out.value))
+            }
+            None => {
+                let account = call.opt_str_param(1, "account")?;
+                let mut inner = self.inner.lock().unwrap();
+                call_wallets(&mut inner.wallets, call)?.total_balance(account)
+            }
+        };
+        match balance {
+            Some(balance) => Ok(json!(balance.to_string())),
+            None => Err(format_err!("balance overflows the amount range")),
+        }
+    }
+
+    /// rpc_send pays the recipients from the wallet address in parameter
+    /// `from_param` or, without one, from the wallet address with the largest
+    /// balance
+    fn rpc_send(
+        &self,
+        call: &RpcCall,
+        recipients: &[(String, Amount)],
+        from_param: usize,
+        options: &SendOptions,
+    ) -> Result<Value> {
+        let tx = {
+            let mut inner = self.inner.lock().unwrap();
+            let inner = &mut *inner;
+            let wallets = call_wallets(&mut inner.wallets, call)?;
+            let from = match call.param(from_param) {
+                Some(_) => Address::decode(call.str_param(from_param, "from")?)?.encode(),
+                None => wallets
+                    .get_all_address()
+                    .into_iter()
+                    .max_by_key(|address| wallets.balance(address))
+                    .ok_or_else(|| format_err!("the wallet has no address"))?,
+            };
+            let wallet = wallets.get_signing_wallet(&from)?;
+            Transaction::new_many_UTXO(wallet, recipients, options, &inner.utxo)?
+        };
+        self.submit_tx(tx)
+    }
+
+    /// rpc_send_data anchors data in a transaction from an address of the wallet
+    fn rpc_send_data(&self, call: &RpcCall) -> Result<Value> {
+        let from = Address::decode(call.str_param(0, "from")?)?.encode();
+        let data = hex::decode(call.str_param(1, "hexdata")?)?;
+        let tx = {
+            let mut inner = self.inner.lock().unwrap();
+            let inner = &mut *inner;
+            let wallet = call_wallets(&mut inner.wallets, call)?.get_signing_wallet(&from)?;
+            Transaction::new_data_UTXO(wallet, &data, &inner.utxo)?
+        };
+        self.submit_tx(tx)
+    }
+
+    /// rpc_initiate_swap locks coins of the wallet in a swap contract with the
+    /// participant, returning the contract id
+    fn rpc_initiate_swap(&self, call: &RpcCall) -> Result<Value> {
+        let from = Address::decode(call.str_param(0, "from")?)?.encode();
+        let participant = call.str_param(1, "participant")?;
+        let amount = call.amount_param(2, "amount")?;
+        let secret_hash = hex::decode(call.str_param(3, "secrethash")?)?;
+        let timeout = match call.param(4).and_then(Value::as_u64) {
+            Some(timeout) => timeout,
+            None => return Err(invalid_params("timeout must be a number")),
+        };
+        let tx = {
+            let mut inner = self.inner.lock().unwrap();
+            let inner = &mut *inner;
+            let wallet = call_wallets(&mut inner.wallets, call)?.get_signing_wallet(&from)?;
+            let htlc = Htlc::new(participant, &wallet.public_key, secret_hash, timeout)?;
+            Transaction::new_htlc_UTXO(wallet, &htlc, amount, &inner.utxo)?
+        };
+        self.submit_tx(tx)
+    }
+
+    /// rpc_spend_swap redeems a swap contract with the secret or, without one, refunds it
+    fn rpc_spend_swap(&self, call: &RpcCall, secret: Option<&[u8]>) -> Result<Value> {
+        let contract = call.str_param(0, "contract")?;
+        let tx = {
+            let mut inner = self.inner.lock().unwrap();
+            let inner = &mut *inner;
+            let contract_tx = inner.utxo.blockchain.find_transaction(contract)?;
+            let (vout, htlc) = contract_tx.htlc_output()?;
+            let pub_key_hash = match secret {
+                Some(_) => &htlc.recipient,
+                None => &htlc.refund,
+            };
+            let wallets = call_wallets(&mut inner.wallets, call)?;
+            let wallet = wallets.get_signing_wallet(&key_address(pub_key_hash))?;
+            Transaction::new_htlc_spend(wallet, &contract_tx, vout, secret)?
+        };
+        self.submit_tx(tx)
+    }
+
+    /// submit_tx hands a transaction of a local client to the network like a
+    /// received one, returning its id or why the mempool refused it
+    fn submit_tx(&self, tx: Transaction) -> Result<Value> {
+        let txid = tx.id.clone();
+        self.accept_tx(&tx)?;
+        self.relay_tx(Txmsg {
+            addr_from: self.node_address.clone(),
+            transaction: tx.clone(),
+        })?;
+        // nodes that neither relay nor mine pass the transaction to the central node
+        if self.node_address != KNOWN_NODE1 && self.mining_address.is_empty() {
+            self.send_tx(KNOWN_NODE1, &tx)?;
+        }
+        Ok(json!(txid))
+    }
+
     fn request_blocks(&self) -> Result<()> {
         for node in self.get_known_nodes() {
             self.send_get_blocks(&node)?
@@ -204,6 +915,7 @@ impl Server {
             addr_from: self.node_address.clone(),
             version: VERSION,
             best_height: self.get_best_height()?,
+            timestamp: now_millis()?,
         };
         let data = bincode::serialize(&(cmd_to_bytes("version"), data))?;
         self.send_data(addr, &data)
@@ -275,7 +987,94 @@ impl Server {
     }
 
     fn add_block(&self, block: Block) -> Result<()> {
-        self.inner.lock().unwrap().utxo.blockchain.add_block(block)
+        let change = {
+            let mut inner = self.inner.lock().unwrap();
+            let change = inner.utxo.blockchain.add_block(block)?;
+            if !change.connected.is_empty() {
+                update_wallets(&mut inner.wallets, |wallets| {
+                    wallets.apply_tip_change(&change)
+                });
+            }
+            change
+        };
+        for block in &change.disconnected {
+            self.publish_block(EventKind::BlockDisconnected, block);
+        }
+        for block in &change.connected {
+            self.block_connected(block);
+        }
+        Ok(())
+    }
+
+    /// block_connected announces a block joining the best chain and drops its
+    /// transactions from the mempool
+    fn block_connected(&self, block: &Block) {
+        self.publish_block(EventKind::BlockConnected, block);
+        for tx in block.get_transaction() {
+            let removed = self.inner.lock().unwrap().mempool.remove(&tx.id);
+            if let Some(tx) = removed {
+                self.publish_tx(EventKind::TxRemoved, &tx, Some("confirmed"));
+            }
+        }
+    }
+
+    /// publish_block announces a best chain change to the event subscribers
+    fn publish_block(&self, kind: EventKind, block: &Block) {
+        if self.events.is_empty() {
+            return;
+        }
+        let addresses = self.tx_addresses(block.get_transaction());
+        self.events.publish(&ChainEvent {
+            kind,
+            data: block_json(block),
+            addresses,
+        });
+    }
+
+    /// publish_tx announces a mempool change to the event subscribers
+    fn publish_tx(&self, kind: EventKind, tx: &Transaction, reason: Option<&str>) {
+        if self.events.is_empty() {
+            return;
+        }
+        let mut data = tx_json(tx);
+        if let Some(reason) = reason {
+            data["reason"] = json!(reason);
+        }
+        let addresses = self.tx_addresses(std::slice::from_ref(tx));
+        self.events.publish(&ChainEvent {
+            kind,
+            data,
+            addresses,
+        });
+    }
+
+    /// tx_addresses returns the addresses the transactions pay to or spend
+    /// from, finding the spent outputs in the mempool or the best chain
+    fn tx_addresses(&self, txs: &[Transaction]) -> HashSet<String> {
+        let inner = self.inner.lock().unwrap();
+        let mut addresses = HashSet::new();
+        for tx in txs {
+            addresses.extend(
+                tx.vout
+                    .iter()
+                    .filter_map(|out| script_address(&out.script_pub_key)),
+            );
+            if tx.is_coinbase() {
+                continue;
+            }
+            for vin in &tx.vin {
+                let prev = match inner.mempool.get(&vin.txid) {
+                    Some(prev) => Some(prev.clone()),
+                    None => inner.utxo.blockchain.find_transaction(&vin.txid).ok(),
+                };
+                let spent = prev.and_then(|prev| {
+                    let out = prev.vout.get(vin.vout as usize)?;
+                    script_address(&out.script_pub_key)
+                });
+                addresses.extend(spent);
+            }
+        }
+        addresses
     }
 
     fn handle_get_data(&self, msg: GetDatamsg) -> Result<()> {
@@ -299,17 +1098,33 @@ impl Server {
             .get_block(block_hash)
     }
 
+    /// get_block_hash_at returns the hash of the best chain block at a height
+    fn get_block_hash_at(&self, height: i64) -> Result<Option<String>> {
+        let inner = self.inner.lock().unwrap();
+        for block in inner.utxo.blockchain.iter() {
+            let block = block?;
+            if block.get_height() as i64 == height {
+                return Ok(Some(block.get_hash()));
+            }
+        }
+        Ok(None)
+    }
+
+    /// verify_tx checks a pending transaction spends outputs of the UTXO set
+    /// and unlocks them
     fn verify_tx(&self, tx: &Transaction) -> Result<bool> {
-        self.inner
-            .lock()
-            .unwrap()
-            .utxo
-            .blockchain
-            .verify_transaction(tx)
+        let inner = self.inner.lock().unwrap();
+        for vin in &tx.vin {
+            if !inner.utxo.is_unspent(&vin.txid, vin.vout)? {
+                return Ok(false);
+            }
+        }
+        inner.utxo.blockchain.verify_transaction(tx)
     }
 
-    fn handle_version(&self, msg: Versionmsg) -> Result<()> {
+    fn handle_version(&self, msg: Versionmsg, peer: IpAddr) -> Result<()> {
         info!("receive version msg: {:#?}", msg);
+        self.add_time_sample(peer, msg.timestamp)?;
         let my_best_height = self.get_best_height()?;
         if my_best_height < msg.best_height {
             self.send_get_blocks(&msg.addr_from)?;
@@ -323,6 +1138,33 @@ impl Server {
         Ok(())
     }
 
+    /// add_time_sample records the clock offset of the host a version message
+    /// came from and corrects the network time used for block validation by
+    /// the median of all offsets. A host has one sample however many nodes or
+    /// addresses it announces
+    fn add_time_sample(&self, peer: IpAddr, peer_time: u128) -> Result<()> {
+        let offset = peer_time as i128 - now_millis()? as i128;
+        let bound = MAX_TIME_SAMPLE_OFFSET as i128;
+        let offset = offset.clamp(-bound, bound) as i64;
+        let mut inner = self.inner.lock().unwrap();
+        if inner.time_offsets.len() >= MAX_TIME_SAMPLES && !inner.time_offsets.contains_key(&peer) {
+            return Ok(());
+        }
+        inner.time_offsets.insert(peer, offset);
+        let mut offsets: Vec<i64> = inner.time_offsets.values().cloned().collect();
+        offsets.sort();
+        let mut median = offsets[offsets.len() / 2];
+        if median.abs() > MAX_TIME_ADJUSTMENT {
+            warn!(
+                "peer clocks are {} ms off, please check your computer's date and time",
+                median
+            );
+            median = 0;
+        }
+        inner.utxo.blockchain.set_time_offset(median);
+        Ok(())
+    }
+
     fn node_is_known(&self, addr: &str) -> bool {
         self.inner.lock().unwrap().known_nodes.get(addr).is_some()
     }
@@ -333,23 +1175,86 @@ impl Server {
 
     fn handle_get_blocks(&self, msg: GetBlocksmsg) -> Result<()> {
         info!("receive get blocks msg: {:#?}", msg);
-        let block_hashs = self.get_block_hashs();
+        let block_hashs = self.get_block_hashs()?;
         self.send_inv(&msg.addr_from, "block", block_hashs)?;
         Ok(())
     }
 
     fn mine_block(&self, txs: Vec<Transaction>) -> Result<Block> {
-        self.inner.lock().unwrap().utxo.blockchain.mine_block(txs)
+        let block = {
+            let mut inner = self.inner.lock().unwrap();
+            let block = inner.utxo.blockchain.mine_block(txs)?;
+            update_wallets(&mut inner.wallets, |wallets| wallets.connect_block(&block));
+            block
+        };
+        self.block_connected(&block);
+        Ok(block)
     }
 
     fn utxo_reindex(&self) -> Result<()> {
         self.inner.lock().unwrap().utxo.reindex()
     }
 
+    /// check_lock_times checks a transaction could be included in the next block
+    fn check_lock_times(&self, tx: &Transaction) -> Result<()> {
+        let inner = self.inner.lock().unwrap();
+        inner.utxo.blockchain.check_pending_lock_times(tx)
+    }
+
+    /// accept_tx adds a transaction to the mempool when it could go in the next
+    /// block: its inputs are in the UTXO set and not spent by another pending
+    /// transaction, it unlocks them and its lock times have passed
+    fn accept_tx(&self, tx: &Transaction) -> Result<()> {
+        {
+            let mut inner = self.inner.lock().unwrap();
+            if inner.mempool.contains_key(&tx.id) {
+                return Ok(());
+            }
+            for vin in &tx.vin {
+                if !inner.utxo.is_unspent(&vin.txid, vin.vout)? {
+                    return Err(format_err!(
+                        "output {}:{} is not in the UTXO set",
+                        vin.txid,
+                        vin.vout
+                    ));
+                }
+                let conflict = inner.mempool.values().find(|other| {
+                    other
+                        .vin
+                        .iter()
+                        .any(|spent| spent.txid == vin.txid && spent.vout == vin.vout)
+                });
+                if let Some(other) = conflict {
+                    return Err(format_err!(
+                        "output {}:{} is already spent by pending transaction {}",
+                        vin.txid,
+                        vin.vout,
+                        other.id
+                    ));
+                }
+            }
+            if !inner.utxo.blockchain.verify_transaction(tx)? {
+                return Err(format_err!("transaction {} is invalid", tx.id));
+            }
+            inner.utxo.blockchain.check_pending_lock_times(tx)?;
+            inner.mempool.insert(tx.id.clone(), tx.clone());
+        }
+        self.publish_tx(EventKind::TxAdded, tx, None);
+        Ok(())
+    }
+
     fn handle_tx(&self, msg: Txmsg) -> Result<()> {
         info!("receive tx msg: {} {}", msg.addr_from, &msg.transaction.id);
-        self.insert_mempool(msg.transaction.clone());
+        if let Err(e) = self.accept_tx(&msg.transaction) {
+            info!("reject tx {}: {}", &msg.transaction.id, e);
+            return Ok(());
+        }
+        self.relay_tx(msg)
+    }
 
+    /// relay_tx passes a mempool transaction on from the central node, or mines
+    /// the mempool on a miner
+    fn relay_tx(&self, msg: Txmsg) -> Result<()> {
         let known_nodes = self.get_known_nodes();
         if self.node_address == KNOWN_NODE1 {
             for node in known_nodes {
@@ -363,8 +1268,15 @@ impl Server {
             if mempool.len() >= 1 && !self.mining_address.is_empty() {
                 loop {
                     let mut txs = Vec::new();
+                    let mut spent = HashSet::new();
                     for (_, tx) in &mempool {
-                        if self.verify_tx(tx)? {
+                        // a transaction spending an output a picked one spends waits
+                        let conflicts = tx
+                            .vin
+                            .iter()
+                            .any(|vin| spent.contains(&(vin.txid.clone(), vin.vout)));
+                        if !conflicts && self.verify_tx(tx)? && self.check_lock_times(tx).is_ok() {
+                            spent.extend(tx.vin.iter().map(|vin| (vin.txid.clone(), vin.vout)));
                             txs.push(tx.clone());
                         }
                     }
@@ -374,7 +1286,7 @@ impl Server {
                     }
                     let cbtx =
                         Transaction::new_coinbase(self.mining_address.clone(), String::new())?;
-                    txs.push(cbtx);
+                    txs.insert(0, cbtx);
 
                     for tx in &txs {
                         mempool.remove(&tx.id);
@@ -428,11 +1340,13 @@ impl Server {
     }
 
     fn clear_mempool(&self) {
-        self.inner.lock().unwrap().mempool.clear()
-    }
-
-    fn insert_mempool(&self, tx: Transaction) {
-        self.inner.lock().unwrap().mempool.insert(tx.id.clone(), tx);
+        let evicted: Vec<Transaction> = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.mempool.drain().map(|(_, tx)| tx).collect()
+        };
+        for tx in &evicted {
+            self.publish_tx(EventKind::TxRemoved, tx, Some("evicted"));
+        }
     }
 
     fn get_mempool_tx(&self, addr: &str) -> Option<Transaction> {
@@ -446,7 +1360,7 @@ impl Server {
         self.inner.lock().unwrap().mempool.clone()
     }
 
-    fn get_block_hashs(&self) -> Vec<String> {
+    fn get_block_hashs(&self) -> Result<Vec<String>> {
         self.inner.lock().unwrap().utxo.blockchain.get_block_hashs()
     }
 
@@ -473,7 +1387,7 @@ impl Server {
             Message::Block(data) => self.handle_block(data)?,
             Message::Inv(data) => self.handle_inv(data)?,
             Message::GetBlock(data) => self.handle_get_blocks(data)?,
-            Message::Version(data) => self.handle_version(data)?,
+            Message::Version(data) => self.handle_version(data, stream.peer_addr()?.ip())?,
             Message::Tx(data) => self.handle_tx(data)?,
             Message::GetData(data) => self.handle_get_data(data)?,
         }
@@ -481,6 +1395,68 @@ impl Server {
     }
 }
 
+/// node_ips resolves the hosts a node address may connect from
+fn node_ips(node: &str) -> Vec<IpAddr> {
+    match node.to_socket_addrs() {
+        Ok(addrs) => addrs.map(|addr| addr.ip()).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// call_wallet_name is the name of the wallet the call is for
+fn call_wallet_name(call: &RpcCall) -> &str {
+    call.wallet.as_deref().unwrap_or(DEFAULT_WALLET)
+}
+
+/// call_wallets returns the loaded wallet the call is for
+fn call_wallets<'a>(
+    wallets: &'a mut HashMap<String, Wallets>,
+    call: &RpcCall,
+) -> Result<&'a mut Wallets> {
+    let name = call_wallet_name(call);
+    wallets
+        .get_mut(name)
+        .ok_or_else(|| format_err!("wallet {} is not loaded", name))
+}
+
+/// rescan_imported finds the coins of the addresses just imported into the wallet
+fn rescan_imported(wallets: &mut Wallets, bc: &Blockchain) -> Result<()> {
+    wallets.rescan(bc, 0)?;
+    wallets.save_all()
+}
+
+/// relock_expired wipes the keys of a wallet whose unlock timeout passed,
+/// unless it was unlocked again since
+fn relock_expired(inner: &Mutex<ServerInner>, name: &str) {
+    let mut inner = inner.lock().unwrap();
+    let expired = match (inner.unlocked_until.get(name), now_millis()) {
+        (Some(expires), Ok(now)) => *expires <= now,
+        _ => false,
+    };
+    if !expired {
+        return;
+    }
+    inner.unlocked_until.remove(name);
+    if let Some(wallets) = inner.wallets.get_mut(name) {
+        match wallets.lock() {
+            Ok(()) => info!("wallet {} locked after its unlock timeout", name),
+            Err(e) => warn!("locking wallet {} failed: {}", name, e),
+        }
+    }
+}
+
+/// update_wallets applies a best chain change to the loaded wallets and saves
+/// them. The blocks are already stored, so a wallet failing to save is logged
+/// rather than failing the block
+fn update_wallets(wallets: &mut HashMap<String, Wallets>, update: impl Fn(&mut Wallets)) {
+    for (name, wallets) in wallets.iter_mut() {
+        update(wallets);
+        if let Err(e) = wallets.save_all() {
+            warn!("saving wallet {} failed: {}", name, e);
+        }
+    }
+}
+
 // cargo run addr data
 fn bytes_to_cmd(bytes: &[u8]) -> Result<Message> {
     let mut cmd = Vec::new();
@@ -528,3 +1504,59 @@ fn cmd_to_bytes(cmd: &str) -> [u8; CMD_LEN] {
     }
     data
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::tests::{test_chain, test_wallet};
+
+    /// rest answers a JSON REST request of the path segments
+    fn rest(server: &Server, segments: &[&str]) -> Result<Option<Value>> {
+        let request = RestRequest {
+            segments: segments.iter().map(|s| s.to_string()).collect(),
+            format: RestFormat::Json,
+        };
+        Ok(match server.handle_rest(&request)? {
+            Some(RestBody::Json(value)) => Some(value),
+            Some(RestBody::Bin(_)) => panic!("a JSON request answered with bincode"),
+            None => None,
+        })
+    }
+
+    #[test]
+    fn test_handle_rest() {
+        let authority = test_wallet(1);
+        let bc = test_chain(&authority);
+        let genesis = bc.iter().next().unwrap().unwrap();
+        let coinbase_id = genesis.get_transaction()[0].id.clone();
+        let server = Server::new("0", "", UTXOSet { blockchain: bc }, HashMap::new()).unwrap();
+
+        let block = rest(&server, &["block", &genesis.get_hash()])
+            .unwrap()
+            .unwrap();
+        assert_eq!(block["tx"][0]["txid"], json!(coinbase_id));
+        let unknown = "00".repeat(32);
+        assert_eq!(rest(&server, &["block", &unknown]).unwrap(), None);
+        assert_eq!(rest(&server, &["tx", &unknown]).unwrap(), None);
+        assert_eq!(rest(&server, &["block-height", "1"]).unwrap(), None);
+        assert_eq!(rest(&server, &["blocks"]).unwrap(), None);
+        assert!(rest(&server, &["block-height", "tip"]).is_err());
+
+        let address = authority.get_address();
+        let txs = rest(&server, &["address", &address, "txs"])
+            .unwrap()
+            .unwrap();
+        let txs = txs.as_array().unwrap();
+        assert_eq!(txs.len(), 1);
+        assert_eq!(txs[0]["txid"], json!(coinbase_id));
+        assert_eq!(txs[0]["vout"][0]["address"], json!(address));
+        assert_eq!(
+            (&txs[0]["height"], &txs[0]["confirmations"]),
+            (&json!(0), &json!(1))
+        );
+        let other = test_wallet(2).get_address();
+        let txs = rest(&server, &["address", &other, "txs"]).unwrap().unwrap();
+        assert_eq!(txs, json!([]));
+        assert!(rest(&server, &["address", "nowhere", "txs"]).is_err());
+    }
+}
@@ -1,10 +1,13 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use crate::{
+    amount::{Amount, SUBSIDY},
+    coinselect::CoinSelection,
     errors::Result,
-    tx::{TXInput, TXOutput},
+    script::{verify_script, Htlc, Op, Script, SignatureChecker, LOCKTIME_THRESHOLD, MAX_DATA_LEN},
+    tx::{address_to_script, TXInput, TXOutput, SEQUENCE_FINAL},
     utxoset::UTXOSet,
-    wallet::Wallet,
+    wallet::{multisig_address, Wallet},
 };
 use crypto::{digest::Digest, ed25519};
 use crypto::{ripemd160::Ripemd160, sha2::Sha256};
@@ -12,33 +15,263 @@ use failure::format_err;
 use log::error;
 use serde::{Deserialize, Serialize};
 
+/// SIGHASH_ALL signs every input and every output
+pub const SIGHASH_ALL: u8 = 0x01;
+/// SIGHASH_NONE signs the inputs but none of the outputs
+pub const SIGHASH_NONE: u8 = 0x02;
+/// SIGHASH_SINGLE signs the inputs and only the output at the index of the signed input
+pub const SIGHASH_SINGLE: u8 = 0x03;
+/// SIGHASH_ANYONECANPAY combines with the other types to sign only the signed input
+pub const SIGHASH_ANYONECANPAY: u8 = 0x80;
+
+const SIGNATURE_LEN: usize = 64;
+/// an ed25519 secret key is the 32 byte seed followed by the public key
+const SECRET_KEY_LEN: usize = 64;
+
+/// SendOptions adjusts how the wallet builds a payment
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SendOptions {
+    /// LockTime is the height or timestamp before which the transaction cannot be mined
+    pub lock_time: u64,
+    /// LockOutputUntil locks the payment so the recipient cannot spend it before this height or timestamp
+    pub lock_output_until: Option<u64>,
+    /// SighashType selects which parts of the transaction the wallet signatures commit to
+    pub sighash_type: u8,
+    /// CoinSelection chooses the wallet outputs the transaction spends
+    pub coin_selection: CoinSelection,
+}
+
+impl Default for SendOptions {
+    fn default() -> Self {
+        SendOptions {
+            lock_time: 0,
+            lock_output_until: None,
+            sighash_type: SIGHASH_ALL,
+            coin_selection: CoinSelection::default(),
+        }
+    }
+}
+
+/// IsValidSighashType checks that the hash type byte of a signature is one of the defined types
+pub fn is_valid_sighash_type(hash_type: u8) -> bool {
+    matches!(
+        hash_type & !SIGHASH_ANYONECANPAY,
+        SIGHASH_ALL | SIGHASH_NONE | SIGHASH_SINGLE
+    )
+}
+
+/// SignatureMessage is what an input signature commits to, see Transaction::signature_hash
+#[derive(Serialize)]
+struct SignatureMessage<'a> {
+    hash_type: u8,
+    inputs: Vec<(&'a str, i32, u32)>,
+    outpoint: (&'a str, i32),
+    script_code: &'a Script,
+    value: Amount,
+    sequence: u32,
+    outputs: Vec<&'a TXOutput>,
+    lock_time: u64,
+}
+
 /// Transaction represents a Bitcoin transaction
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Transaction {
     pub id: String,
     pub vin: Vec<TXInput>,
     pub vout: Vec<TXOutput>,
+    pub lock_time: u64,
 }
 
 impl Transaction {
-    /// NewUTXOTransaction creates a new transaction
-    pub fn new_UTXO(wallet: &Wallet, to: &str, amount: i32, bc: &UTXOSet) -> Result<Transaction> {
-        let mut vin = Vec::new();
+    /// NewManyUTXO creates a single transaction paying every recipient, with
+    /// one change output back to the wallet
+    pub fn new_many_UTXO(
+        wallet: &Wallet,
+        recipients: &[(String, Amount)],
+        options: &SendOptions,
+        bc: &UTXOSet,
+    ) -> Result<Transaction> {
+        let mut tx =
+            Transaction::new_unsigned_many(&wallet.get_address(), None, recipients, options, bc)?;
+        bc.blockchain
+            .sign_transaction(&mut tx, &wallet.secret_key, options.sighash_type)?;
+        Ok(tx)
+    }
 
-        // let wallets = Wallets::new()?;
-        // let wallet = match wallets.get_wallet(from) {
-        //     Some(w) => w,
-        //     None => return Err(format_err!("from wallet not found in wallet")),
-        // };
-        // if let None = wallets.get_wallet(&to) {
-        //     return Err(format_err!("to wallet not found in wallet"));
-        // };
+    /// NewUnsignedMany builds, without signing it, a transaction paying every
+    /// recipient from a key address, or from a multisig address given its
+    /// redeem script, with one change output back to `from`
+    pub fn new_unsigned_many(
+        from: &str,
+        redeem_script: Option<&Script>,
+        recipients: &[(String, Amount)],
+        options: &SendOptions,
+        bc: &UTXOSet,
+    ) -> Result<Transaction> {
+        if recipients.is_empty() {
+            return Err(format_err!("no recipients to pay"));
+        }
+        let (lock, unlock) = match redeem_script {
+            Some(redeem_script) => (
+                Script::new_p2sh(&redeem_script.hash()?),
+                Script::new_multisig_unlock(redeem_script)?,
+            ),
+            None => (address_to_script(from)?, Script::default()),
+        };
+        if redeem_script.is_none() && lock.p2pkh_hash().is_none() {
+            return Err(format_err!("{} is not a key address", from));
+        }
 
+        let mut payments = Vec::new();
+        for (to, amount) in recipients {
+            let mut payee = address_to_script(to)?;
+            if let Some(until) = options.lock_output_until {
+                payee = Script::new_timelocked(until, &payee);
+            }
+            payments.push(TXOutput {
+                value: *amount,
+                script_pub_key: payee,
+            });
+        }
+        Transaction::new_unsigned(&lock, &unlock, from, payments, options, bc)
+    }
+
+    /// NewHtlcUTXO creates a transaction locking `amount` from the wallet in a
+    /// hash time-locked contract
+    pub fn new_htlc_UTXO(
+        wallet: &Wallet,
+        htlc: &Htlc,
+        amount: Amount,
+        bc: &UTXOSet,
+    ) -> Result<Transaction> {
         let mut pub_key_hash = wallet.public_key.clone();
         hash_pub_key(&mut pub_key_hash);
 
-        let acc_v = bc.find_spendable_outputs(&pub_key_hash, amount)?;
-        if acc_v.0 < amount {
+        let mut tx = Transaction::new_unsigned(
+            &Script::new_p2pkh(&pub_key_hash),
+            &Script::default(),
+            &wallet.get_address(),
+            vec![TXOutput {
+                value: amount,
+                script_pub_key: Script::new_htlc(htlc),
+            }],
+            &SendOptions::default(),
+            bc,
+        )?;
+        bc.blockchain
+            .sign_transaction(&mut tx, &wallet.secret_key, SIGHASH_ALL)?;
+        Ok(tx)
+    }
+
+    /// NewDataUTXO creates a transaction anchoring `data` in an unspendable data
+    /// output, it spends one of the wallet outputs back to the wallet as change
+    pub fn new_data_UTXO(wallet: &Wallet, data: &[u8], bc: &UTXOSet) -> Result<Transaction> {
+        let mut pub_key_hash = wallet.public_key.clone();
+        hash_pub_key(&mut pub_key_hash);
+
+        let mut tx = Transaction::new_unsigned(
+            &Script::new_p2pkh(&pub_key_hash),
+            &Script::default(),
+            &wallet.get_address(),
+            vec![TXOutput::new_data(data)?],
+            &SendOptions::default(),
+            bc,
+        )?;
+        bc.blockchain
+            .sign_transaction(&mut tx, &wallet.secret_key, SIGHASH_ALL)?;
+        Ok(tx)
+    }
+
+    /// NewHtlcSpend spends a contract output to the wallet, redeeming it with the
+    /// secret or, without one, refunding it once the timeout has passed
+    pub fn new_htlc_spend(
+        wallet: &Wallet,
+        contract: &Transaction,
+        vout: i32,
+        secret: Option<&[u8]>,
+    ) -> Result<Transaction> {
+        let out = match contract.vout.get(vout as usize) {
+            Some(out) => out,
+            None => return Err(format_err!("contract output {} does not exist", vout)),
+        };
+        let htlc = match out.script_pub_key.htlc_params() {
+            Some(htlc) => htlc,
+            None => return Err(format_err!("output {} is not a swap contract", vout)),
+        };
+        let (lock_time, sequence) = match secret {
+            Some(_) => (0, SEQUENCE_FINAL),
+            None => (htlc.timeout, SEQUENCE_FINAL - 1),
+        };
+        let mut tx = Transaction {
+            id: String::new(),
+            vin: vec![TXInput {
+                txid: contract.id.clone(),
+                vout,
+                script_sig: Script::default(),
+                sequence,
+            }],
+            vout: vec![TXOutput::new(out.value, wallet.get_address())?],
+            lock_time,
+        };
+        tx.id = tx.hash()?;
+        let signature = tx.sign_input(
+            0,
+            &out.script_pub_key,
+            out.value,
+            SIGHASH_ALL,
+            &wallet.secret_key,
+        )?;
+        tx.vin[0].script_sig = match secret {
+            Some(secret) => Script::new_htlc_redeem(&signature, &wallet.public_key, secret),
+            None => Script::new_htlc_refund(&signature, &wallet.public_key),
+        };
+        Ok(tx)
+    }
+
+    /// NewMultisigUTXO creates a transaction spending from a multisig address,
+    /// it is unsigned and each cosigner adds a signature with Sign
+    pub fn new_multisig_UTXO(
+        redeem_script: &Script,
+        to: &str,
+        amount: Amount,
+        bc: &UTXOSet,
+    ) -> Result<Transaction> {
+        Transaction::new_unsigned(
+            &Script::new_p2sh(&redeem_script.hash()?),
+            &Script::new_multisig_unlock(redeem_script)?,
+            &multisig_address(redeem_script)?,
+            vec![TXOutput::new(amount, to.to_string())?],
+            &SendOptions::default(),
+            bc,
+        )
+    }
+
+    /// new_unsigned spends outputs locked with `lock`, paying the `payments`
+    /// outputs and the change back to `change_address`, inputs start with `unlock`
+    fn new_unsigned(
+        lock: &Script,
+        unlock: &Script,
+        change_address: &str,
+        payments: Vec<TXOutput>,
+        options: &SendOptions,
+        bc: &UTXOSet,
+    ) -> Result<Transaction> {
+        if payments
+            .iter()
+            .any(|out| out.value.is_zero() && !out.is_data())
+        {
+            return Err(format_err!("cannot send a zero amount"));
+        }
+        let amount = match Amount::checked_sum(payments.iter().map(|out| out.value)) {
+            Some(amount) => amount,
+            None => return Err(format_err!("total payment overflows the amount range")),
+        };
+        let mut vin = Vec::new();
+        // even a payment of nothing, such as a data output, spends at least one output
+        let needed = amount.max(Amount::from_units(1));
+        let acc_v = bc.find_spendable_outputs(lock, needed, &options.coin_selection)?;
+        if acc_v.0 < needed {
             error!("Not Enough balance");
             return Err(format_err!(
                 "Not Enough balance: current balance {}",
@@ -46,32 +279,53 @@ impl Transaction {
             ));
         }
 
+        // spending timelocked outputs needs a lock time at least as late as theirs
+        let mut lock_time = options.lock_time;
         for tx in acc_v.1 {
+            let prev_tx = bc.blockchain.find_transaction(&tx.0)?;
             for out in tx.1 {
+                if let Some(out_lock_time) = prev_tx.vout[out as usize].lock_time() {
+                    if lock_time != 0
+                        && (lock_time < LOCKTIME_THRESHOLD) != (out_lock_time < LOCKTIME_THRESHOLD)
+                    {
+                        return Err(format_err!(
+                            "cannot spend height and time locked outputs in one transaction"
+                        ));
+                    }
+                    lock_time = lock_time.max(out_lock_time);
+                }
                 let input = TXInput {
                     txid: tx.0.clone(),
                     vout: out,
-                    signature: Vec::new(),
-                    pub_key: wallet.public_key.clone(),
+                    script_sig: unlock.clone(),
+                    sequence: SEQUENCE_FINAL,
                 };
                 vin.push(input);
             }
         }
+        if lock_time != 0 {
+            // a lock time only applies when some input is not final
+            for input in vin.iter_mut() {
+                input.sequence = SEQUENCE_FINAL - 1;
+            }
+        }
 
-        let mut vout = vec![TXOutput::new(amount, to.to_string())?];
+        let mut vout = payments;
         if acc_v.0 > amount {
-            vout.push(TXOutput::new(acc_v.0 - amount, wallet.get_address())?)
+            // acc_v.0 > amount, so the subtraction cannot underflow
+            let change = acc_v.0.checked_sub(amount).unwrap();
+            vout.push(TXOutput::new(change, change_address.to_string())?)
         }
         let mut tx = Transaction {
             id: String::new(),
             vin,
             vout,
+            lock_time,
         };
         tx.id = tx.hash()?;
-        bc.blockchain
-            .sign_transaction(&mut tx, &wallet.secret_key)?;
         Ok(tx)
     }
+
     pub fn new_coinbase(to: String, mut data: String) -> Result<Transaction> {
         if data == String::from("") {
             data += &format!("Reward to '{}'", to);
@@ -81,87 +335,298 @@ impl Transaction {
             vin: vec![TXInput {
                 txid: String::new(),
                 vout: -1,
-                signature: Vec::new(),
-                pub_key: Vec::from(data.as_bytes()),
+                script_sig: Script(vec![Op::Push(Vec::from(data.as_bytes()))]),
+                sequence: SEQUENCE_FINAL,
             }],
-            vout: vec![TXOutput::new(100, to)?],
+            vout: vec![TXOutput::new(SUBSIDY, to)?],
+            lock_time: 0,
         };
         tx.id = tx.hash()?;
 
         Ok(tx)
     }
 
+    /// SetExtraNonce puts an extra nonce after the coinbase data, giving the miner
+    /// a fresh block header once the block nonce space is used up
+    pub fn set_extra_nonce(&mut self, extra_nonce: u64) -> Result<()> {
+        let script_sig = &mut self.vin[0].script_sig.0;
+        script_sig.truncate(1);
+        script_sig.push(Op::Push(extra_nonce.to_le_bytes().to_vec()));
+        self.id = self.hash()?;
+        Ok(())
+    }
+
+    /// IsFinal checks the lock time against the height of the block that would
+    /// include the transaction and the median time past of its parent
+    pub fn is_final(&self, height: i32, time: u128) -> bool {
+        if self.lock_time == 0 {
+            return true;
+        }
+        let reached = if self.lock_time < LOCKTIME_THRESHOLD {
+            (self.lock_time as i64) < height as i64
+        } else {
+            (self.lock_time as u128) < time
+        };
+        reached || self.vin.iter().all(|vin| vin.sequence == SEQUENCE_FINAL)
+    }
+
     /// IsCoinbase checks whether the transaction is coinbase
     pub fn is_coinbase(&self) -> bool {
         self.vin.len() == 1 && self.vin[0].txid.is_empty() && self.vin[0].vout == -1
     }
 
     pub fn verify(&self, prev_TXs: HashMap<String, Transaction>) -> Result<bool> {
+        if self.id != self.hash()? {
+            return Ok(false);
+        }
+        for out in &self.vout {
+            match out.script_pub_key.data_payload() {
+                Some(data) if data.len() > MAX_DATA_LEN => return Ok(false),
+                Some(_) => {}
+                // only data outputs may carry nothing
+                None if out.value.is_zero() => return Ok(false),
+                None => {}
+            }
+        }
+        let output_total = match Amount::checked_sum(self.vout.iter().map(|out| out.value)) {
+            Some(total) => total,
+            None => return Ok(false),
+        };
         if self.is_coinbase() {
-            return Ok(true);
+            return Ok(output_total <= SUBSIDY);
         }
 
+        let mut outpoints = HashSet::new();
         for vin in &self.vin {
             if prev_TXs.get(&vin.txid).unwrap().id.is_empty() {
                 return Err(format_err!("ERROR: Previous transaction is not correct"));
             }
+            // spending an output twice would count its value twice
+            if !outpoints.insert((&vin.txid, vin.vout)) {
+                return Ok(false);
+            }
         }
 
-        let mut tx_copy = self.trim_copy();
-
+        let mut input_total = Amount::ZERO;
         for in_id in 0..self.vin.len() {
             let prev_tx = prev_TXs.get(&self.vin[in_id].txid).unwrap();
-            tx_copy.vin[in_id].signature.clear();
-            tx_copy.vin[in_id].pub_key = prev_tx.vout[self.vin[in_id].vout as usize]
-                .pub_key_hash
-                .clone();
-
-            tx_copy.id = tx_copy.hash()?;
-            tx_copy.vin[in_id].pub_key = Vec::new();
-
-            if !ed25519::verify(
-                &tx_copy.id.as_bytes(),
-                &self.vin[in_id].pub_key,
-                &self.vin[in_id].signature,
-            ) {
+            let prev_out = match prev_tx.vout.get(self.vin[in_id].vout as usize) {
+                Some(out) => out,
+                None => return Err(format_err!("ERROR: Previous output does not exist")),
+            };
+            input_total = match input_total.checked_add(prev_out.value) {
+                Some(total) => total,
+                None => return Ok(false),
+            };
+            let checker = TransactionSignatureChecker {
+                tx: self,
+                in_id,
+                script_code: &prev_out.script_pub_key,
+                value: prev_out.value,
+            };
+            if !verify_script(
+                &self.vin[in_id].script_sig,
+                &prev_out.script_pub_key,
+                &checker,
+            )? {
                 return Ok(false);
             }
         }
 
-        Ok(true)
+        Ok(input_total >= output_total)
     }
 
+    /// Sign signs every input the key can unlock: pay-to-pubkey-hash outputs of
+    /// the key, and multisig outputs where it fills in its own signature slot
     pub fn sign(
         &mut self,
         private_key: &[u8],
         prev_TXs: HashMap<String, Transaction>,
+        hash_type: u8,
     ) -> Result<()> {
+        if !is_valid_sighash_type(hash_type) {
+            return Err(format_err!("unknown sighash type {:#04x}", hash_type));
+        }
         if self.is_coinbase() {
             return Ok(());
         }
 
+        check_secret_key(private_key)?;
         for vin in &self.vin {
             if prev_TXs.get(&vin.txid).unwrap().id.is_empty() {
                 return Err(format_err!("ERROR: Previous transaction is not correct"));
             }
         }
-        let mut tx_copy = self.trim_copy();
+        // an ed25519 secret key is the seed followed by the public key
+        let pub_key = &private_key[32..];
+        let mut pub_key_hash = pub_key.to_vec();
+        hash_pub_key(&mut pub_key_hash);
 
-        for in_id in 0..tx_copy.vin.len() {
-            let prev_Tx = prev_TXs.get(&tx_copy.vin[in_id].txid).unwrap();
-            tx_copy.vin[in_id].signature.clear();
-            tx_copy.vin[in_id].pub_key = prev_Tx.vout[tx_copy.vin[in_id].vout as usize]
-                .pub_key_hash
-                .clone();
-            tx_copy.id = tx_copy.hash()?;
-            tx_copy.vin[in_id].pub_key = Vec::new();
-            let signature = ed25519::signature(tx_copy.id.as_bytes(), private_key);
-            self.vin[in_id].signature = signature.to_vec();
+        for in_id in 0..self.vin.len() {
+            let prev_Tx = prev_TXs.get(&self.vin[in_id].txid).unwrap();
+            let prev_out = &prev_Tx.vout[self.vin[in_id].vout as usize];
+            let script_code = &prev_out.script_pub_key;
+            if script_code.split_timelock().1.p2pkh_hash() == Some(&pub_key_hash[..]) {
+                let signature =
+                    self.sign_input(in_id, script_code, prev_out.value, hash_type, private_key)?;
+                self.vin[in_id].script_sig = Script::new_p2pkh_unlock(&signature, pub_key);
+            } else if let Some(slot) = self.multisig_slot(in_id, script_code, pub_key)? {
+                let signature =
+                    self.sign_input(in_id, script_code, prev_out.value, hash_type, private_key)?;
+                self.vin[in_id].script_sig.0[slot] = Op::Push(signature);
+            }
         }
         Ok(())
     }
 
+    /// multisig_slot finds where the key signs a multisig input spending `script_code`
+    fn multisig_slot(
+        &self,
+        in_id: usize,
+        script_code: &Script,
+        pub_key: &[u8],
+    ) -> Result<Option<usize>> {
+        let script_hash = match script_code.p2sh_hash() {
+            Some(hash) => hash,
+            None => return Ok(None),
+        };
+        let script_sig = &self.vin[in_id].script_sig;
+        let redeem_script = match script_sig.redeem_script() {
+            Some(script) if script.hash()? == script_hash => script,
+            _ => return Ok(None),
+        };
+        let pub_keys = match redeem_script.multisig_params() {
+            Some((_, pub_keys)) if script_sig.0.len() == pub_keys.len() + 1 => pub_keys,
+            _ => return Ok(None),
+        };
+        Ok(pub_keys.iter().position(|k| k == pub_key))
+    }
+
+    /// HtlcOutput returns the index and terms of the contract output of the transaction
+    pub fn htlc_output(&self) -> Result<(i32, Htlc)> {
+        for (i, out) in self.vout.iter().enumerate() {
+            if let Some(htlc) = out.script_pub_key.htlc_params() {
+                return Ok((i as i32, htlc));
+            }
+        }
+        Err(format_err!("{} is not a swap contract", self.id))
+    }
+
+    /// MultisigStatus returns, for each multisig input, how many signatures it
+    /// holds and how many it needs
+    pub fn multisig_status(&self) -> Vec<(usize, usize)> {
+        let mut status = Vec::new();
+        for vin in &self.vin {
+            if let Some((m, pub_keys)) = vin
+                .script_sig
+                .redeem_script()
+                .and_then(|s| s.multisig_params())
+            {
+                let signed = vin.script_sig.0[..pub_keys.len().min(vin.script_sig.0.len())]
+                    .iter()
+                    .filter(|op| !matches!(op, Op::Push(data) if data.is_empty()))
+                    .count();
+                status.push((signed, m));
+            }
+        }
+        status
+    }
+
+    /// SignInput signs the input spending an output locked by `script_code`
+    /// worth `value`, the signature is followed by the hash type byte
+    pub fn sign_input(
+        &self,
+        in_id: usize,
+        script_code: &Script,
+        value: Amount,
+        hash_type: u8,
+        private_key: &[u8],
+    ) -> Result<Vec<u8>> {
+        check_secret_key(private_key)?;
+        let sighash = self.signature_hash(in_id, script_code, value, hash_type)?;
+        let mut signature = ed25519::signature(&sighash, private_key).to_vec();
+        signature.push(hash_type);
+        Ok(signature)
+    }
+
+    /// SignatureHash returns the SHA256 digest an input signature commits to: the
+    /// hash type, the spent outpoint with its locking script and value, the lock
+    /// time, and the other inputs and outputs selected by the hash type.
+    /// Unlocking scripts are never part of it, so signatures can be added in any order
+    fn signature_hash(
+        &self,
+        in_id: usize,
+        script_code: &Script,
+        value: Amount,
+        hash_type: u8,
+    ) -> Result<Vec<u8>> {
+        let base_type = hash_type & !SIGHASH_ANYONECANPAY;
+        let signed = &self.vin[in_id];
+
+        let mut inputs = Vec::new();
+        if hash_type & SIGHASH_ANYONECANPAY == 0 {
+            for (i, vin) in self.vin.iter().enumerate() {
+                // with NONE and SINGLE the other inputs may update their sequence
+                let sequence = if i == in_id || base_type == SIGHASH_ALL {
+                    vin.sequence
+                } else {
+                    0
+                };
+                inputs.push((vin.txid.as_str(), vin.vout, sequence));
+            }
+        }
+
+        let outputs = match base_type {
+            SIGHASH_ALL => self.vout.iter().collect(),
+            SIGHASH_NONE => Vec::new(),
+            SIGHASH_SINGLE => match self.vout.get(in_id) {
+                Some(out) => vec![out],
+                None => {
+                    return Err(format_err!(
+                        "SIGHASH_SINGLE input {} has no matching output",
+                        in_id
+                    ))
+                }
+            },
+            _ => return Err(format_err!("unknown sighash type {:#04x}", hash_type)),
+        };
+
+        let message = SignatureMessage {
+            hash_type,
+            inputs,
+            outpoint: (&signed.txid, signed.vout),
+            script_code,
+            value,
+            sequence: signed.sequence,
+            outputs,
+            lock_time: self.lock_time,
+        };
+        let data = bincode::serialize(&message)?;
+        let mut hasher = Sha256::new();
+        hasher.input(&data[..]);
+        let mut digest = vec![0; hasher.output_bytes()];
+        hasher.result(&mut digest);
+        Ok(digest)
+    }
+
+    /// Hash returns the transaction ID, it leaves out the unlocking scripts of
+    /// regular inputs so adding or changing signatures cannot change it
     pub fn hash(&self) -> Result<String> {
+        let mut copy = if self.is_coinbase() {
+            self.clone()
+        } else {
+            self.trim_copy()
+        };
+        copy.id = String::new();
+        let data = bincode::serialize(&copy)?;
+        let mut hasher = Sha256::new();
+        hasher.input(&data[..]);
+        Ok(hasher.result_str())
+    }
+
+    /// WitnessHash hashes the whole transaction including its unlocking scripts,
+    /// blocks commit to it so their signatures cannot be swapped out
+    pub fn witness_hash(&self) -> Result<String> {
         let mut copy = self.clone();
         copy.id = String::new();
         let data = bincode::serialize(&copy)?;
@@ -177,15 +642,15 @@ impl Transaction {
             vin.push(TXInput {
                 txid: v.txid.clone(),
                 vout: v.vout.clone(),
-                signature: Vec::new(),
-                pub_key: Vec::new(),
+                script_sig: Script::default(),
+                sequence: v.sequence,
             })
         }
 
         for v in &self.vout {
             vout.push(TXOutput {
                 value: v.value,
-                pub_key_hash: v.pub_key_hash.clone(),
+                script_pub_key: v.script_pub_key.clone(),
             })
         }
 
@@ -193,10 +658,66 @@ impl Transaction {
             id: self.id.clone(),
             vin,
             vout,
+            lock_time: self.lock_time,
         }
     }
 }
 
+/// TransactionSignatureChecker checks signatures and lock times for one input of a transaction
+struct TransactionSignatureChecker<'a> {
+    tx: &'a Transaction,
+    in_id: usize,
+    script_code: &'a Script,
+    value: Amount,
+}
+
+impl SignatureChecker for TransactionSignatureChecker<'_> {
+    fn check_sig(&self, signature: &[u8], pub_key: &[u8]) -> Result<bool> {
+        if signature.len() != SIGNATURE_LEN + 1 || pub_key.len() != 32 {
+            return Ok(false);
+        }
+        let (signature, hash_type) = signature.split_at(SIGNATURE_LEN);
+        let hash_type = hash_type[0];
+        if !is_valid_sighash_type(hash_type) {
+            return Ok(false);
+        }
+        let sighash =
+            match self
+                .tx
+                .signature_hash(self.in_id, self.script_code, self.value, hash_type)
+            {
+                Ok(sighash) => sighash,
+                Err(_) => return Ok(false),
+            };
+        Ok(ed25519::verify(&sighash, pub_key, signature))
+    }
+
+    fn check_lock_time(&self, lock_time: u64) -> bool {
+        // a final input would let the transaction ignore its own lock time
+        if self.tx.vin[self.in_id].sequence == SEQUENCE_FINAL {
+            return false;
+        }
+        // heights and timestamps cannot be compared with each other
+        if (lock_time < LOCKTIME_THRESHOLD) != (self.tx.lock_time < LOCKTIME_THRESHOLD) {
+            return false;
+        }
+        self.tx.lock_time >= lock_time
+    }
+}
+
+/// check_secret_key rejects a key that is not an ed25519 secret key, which
+/// would make the signing code panic
+fn check_secret_key(private_key: &[u8]) -> Result<()> {
+    if private_key.len() != SECRET_KEY_LEN {
+        return Err(format_err!(
+            "secret key is {} bytes, not {}",
+            private_key.len(),
+            SECRET_KEY_LEN
+        ));
+    }
+    Ok(())
+}
+
 pub fn hash_pub_key(pub_key: &mut Vec<u8>) {
     let mut hasher1 = Sha256::new();
     hasher1.input(pub_key);
@@ -206,3 +727,133 @@ pub fn hash_pub_key(pub_key: &mut Vec<u8>) {
     pub_key.resize(20, 0);
     hasher2.result(pub_key);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// spend_of builds an unsigned transaction spending output 0 of `prev`
+    fn spend_of(prev: &Transaction, outputs: Vec<TXOutput>) -> Transaction {
+        let mut tx = Transaction {
+            id: String::new(),
+            vin: vec![TXInput {
+                txid: prev.id.clone(),
+                vout: 0,
+                script_sig: Script::default(),
+                sequence: SEQUENCE_FINAL,
+            }],
+            vout: outputs,
+            lock_time: 0,
+        };
+        tx.id = tx.hash().unwrap();
+        tx
+    }
+
+    #[test]
+    fn test_sign_key_length() {
+        let (secret_key, public_key) = ed25519::keypair(&[1; 32]);
+        let mut pub_key_hash = public_key.to_vec();
+        hash_pub_key(&mut pub_key_hash);
+        let owner = crate::wallet::key_address(&pub_key_hash);
+        let prev = Transaction::new_coinbase(owner.clone(), String::new()).unwrap();
+        let mut tx = spend_of(&prev, vec![TXOutput::new(SUBSIDY, owner).unwrap()]);
+        let prev_txs = HashMap::from([(prev.id.clone(), prev.clone())]);
+
+        for key in [&secret_key[..32], &secret_key[..0], &[0; 65][..]] {
+            let err = tx.sign(key, prev_txs.clone(), SIGHASH_ALL).unwrap_err();
+            assert!(err.to_string().contains("secret key"), "{}", err);
+        }
+        tx.sign(&secret_key, prev_txs.clone(), SIGHASH_ALL).unwrap();
+        assert!(tx.verify(prev_txs).unwrap());
+    }
+
+    #[test]
+    fn test_witness_hash() {
+        let (secret_key, public_key) = ed25519::keypair(&[1; 32]);
+        let mut pub_key_hash = public_key.to_vec();
+        hash_pub_key(&mut pub_key_hash);
+        let owner = crate::wallet::key_address(&pub_key_hash);
+        let prev = Transaction::new_coinbase(owner.clone(), String::new()).unwrap();
+        let mut tx = spend_of(&prev, vec![TXOutput::new(SUBSIDY, owner).unwrap()]);
+        let prev_txs = HashMap::from([(prev.id.clone(), prev)]);
+        let unsigned_witness = tx.witness_hash().unwrap();
+        tx.sign(&secret_key, prev_txs.clone(), SIGHASH_ALL).unwrap();
+
+        let id = tx.id.clone();
+        let witness = tx.witness_hash().unwrap();
+        assert_ne!(witness, unsigned_witness);
+        tx.vin[0].script_sig.0.push(Op::Push(vec![1]));
+        assert_eq!(tx.hash().unwrap(), id);
+        assert_ne!(tx.witness_hash().unwrap(), witness);
+        assert!(!tx.verify(prev_txs).unwrap());
+    }
+
+    #[test]
+    fn test_signature_hash_types() {
+        let output = |units| TXOutput {
+            value: Amount::from_units(units),
+            script_pub_key: Script::default(),
+        };
+        let input = |txid: &str| TXInput {
+            txid: txid.to_string(),
+            vout: 0,
+            script_sig: Script::default(),
+            sequence: SEQUENCE_FINAL,
+        };
+        let tx = Transaction {
+            id: String::new(),
+            vin: vec![input("a"), input("b")],
+            vout: vec![output(1), output(2)],
+            lock_time: 0,
+        };
+        let changes: [fn(&mut Transaction); 4] = [
+            |tx| tx.vout[0].value = Amount::from_units(10),
+            |tx| tx.vout[1].value = Amount::from_units(20),
+            |tx| tx.vin[1].vout = 1,
+            |tx| tx.vin[1].sequence = 0,
+        ];
+        // whether the signature of input 0 commits to output 0, output 1, the
+        // outpoint of input 1 and the sequence of input 1
+        let expected = [
+            (SIGHASH_ALL, [true, true, true, true]),
+            (SIGHASH_NONE, [false, false, true, false]),
+            (SIGHASH_SINGLE, [true, false, true, false]),
+            (
+                SIGHASH_ALL | SIGHASH_ANYONECANPAY,
+                [true, true, false, false],
+            ),
+            (
+                SIGHASH_NONE | SIGHASH_ANYONECANPAY,
+                [false, false, false, false],
+            ),
+            (
+                SIGHASH_SINGLE | SIGHASH_ANYONECANPAY,
+                [true, false, false, false],
+            ),
+        ];
+        let script_code = Script::default();
+        let sighash = |tx: &Transaction, hash_type| {
+            tx.signature_hash(0, &script_code, SUBSIDY, hash_type)
+                .unwrap()
+        };
+        for (hash_type, commits) in expected {
+            for (change, commits) in changes.iter().zip(commits) {
+                let mut changed = tx.clone();
+                change(&mut changed);
+                let committed = sighash(&changed, hash_type) != sighash(&tx, hash_type);
+                assert_eq!(committed, commits, "hash type {:#04x}", hash_type);
+            }
+        }
+        // every hash type commits to the signed input itself
+        for (hash_type, _) in expected {
+            let mut changed = tx.clone();
+            changed.vin[0].vout = 1;
+            assert_ne!(sighash(&changed, hash_type), sighash(&tx, hash_type));
+        }
+        let mut single = tx.clone();
+        single.vout.truncate(1);
+        assert!(single
+            .signature_hash(1, &script_code, SUBSIDY, SIGHASH_SINGLE)
+            .is_err());
+    }
+}
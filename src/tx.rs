@@ -1,13 +1,18 @@
-use crate::{errors::Result, transaction::Transaction, wallet::hash_pub_key};
-use bitcoincash_addr::Address;
+use crate::{
+    address::Address,
+    amount::Amount,
+    errors::Result,
+    script::{Script, MAX_DATA_LEN},
+};
+use failure::format_err;
 use log::debug;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
-// TXOutputs collects TXOutput
+// TXOutputs collects the unspent TXOutput of a transaction by output index
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TXOutputs {
-    pub outputs: Vec<TXOutput>,
+    pub outputs: BTreeMap<i32, TXOutput>,
 }
 
 /// TXInput represents a transaction input
@@ -15,46 +20,101 @@ pub struct TXOutputs {
 pub struct TXInput {
     pub txid: String,
     pub vout: i32,
-    pub signature: Vec<u8>,
-    pub pub_key: Vec<u8>,
+    pub script_sig: Script,
+    pub sequence: u32,
+}
+
+/// an input with this sequence opts out of the transaction lock time and relative locks
+pub const SEQUENCE_FINAL: u32 = 0xffff_ffff;
+/// set when the sequence does not encode a relative lock
+pub const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1 << 31;
+/// set when the relative lock is a time, clear when it is a number of blocks
+pub const SEQUENCE_LOCKTIME_TYPE_FLAG: u32 = 1 << 22;
+pub const SEQUENCE_LOCKTIME_MASK: u32 = 0x0000_ffff;
+/// relative time locks count in units of 512 seconds, in milliseconds
+pub const SEQUENCE_LOCKTIME_GRANULARITY: u128 = 512 * 1000;
+
+/// RelativeLock is how long after its output was confirmed an input may be spent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelativeLock {
+    Blocks(i32),
+    Millis(u128),
 }
 
 /// TXOutput represents a transaction output
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TXOutput {
-    pub value: i32,
-    pub pub_key_hash: Vec<u8>,
+    pub value: Amount,
+    pub script_pub_key: Script,
 }
 
 impl TXInput {
-    /// CanUnlockOutputWith checks whether the address initiated the transaction
-    pub fn can_unlock_output_with(&self, unlocking_data: &[u8]) -> bool {
-        let mut pubkeyhash = self.pub_key.clone();
-        hash_pub_key(&mut pubkeyhash);
-        pubkeyhash == unlocking_data
+    /// RelativeLock decodes the relative lock carried in the input sequence
+    pub fn relative_lock(&self) -> Option<RelativeLock> {
+        if self.sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+            return None;
+        }
+        let value = self.sequence & SEQUENCE_LOCKTIME_MASK;
+        if self.sequence & SEQUENCE_LOCKTIME_TYPE_FLAG != 0 {
+            Some(RelativeLock::Millis(
+                value as u128 * SEQUENCE_LOCKTIME_GRANULARITY,
+            ))
+        } else {
+            Some(RelativeLock::Blocks(value as i32))
+        }
     }
 }
 
 impl TXOutput {
-    /// CanBeUnlockedWith checks if the output can be unlocked with the provided data
-    pub fn can_be_unlock_with(&self, unlocking_data: &[u8]) -> bool {
-        self.pub_key_hash == unlocking_data
+    /// IsLockedTo checks whether the output pays to `lock`, directly or behind an absolute timelock
+    pub fn is_locked_to(&self, lock: &Script) -> bool {
+        self.script_pub_key.split_timelock().1 == *lock
     }
 
-    /// Lock signs the output
+    /// LockTime returns the absolute timelock of the output, if it has one
+    pub fn lock_time(&self) -> Option<u64> {
+        self.script_pub_key.split_timelock().0
+    }
+
+    /// Lock locks the output to the address
     fn lock(&mut self, address: &str) -> Result<()> {
-        let pub_key_hash = Address::decode(address).unwrap().body;
         debug!("lock: {:?}", address);
-        self.pub_key_hash = pub_key_hash;
+        self.script_pub_key = address_to_script(address)?;
         Ok(())
     }
 
-    pub fn new(value: i32, address: String) -> Result<Self> {
+    /// NewData creates an unspendable output carrying up to MAX_DATA_LEN bytes of data
+    pub fn new_data(data: &[u8]) -> Result<Self> {
+        if data.len() > MAX_DATA_LEN {
+            return Err(format_err!(
+                "data output carries {} bytes, at most {} are allowed",
+                data.len(),
+                MAX_DATA_LEN
+            ));
+        }
+        Ok(TXOutput {
+            value: Amount::ZERO,
+            script_pub_key: Script::new_data(data),
+        })
+    }
+
+    /// IsData checks whether the output is an unspendable data carrier, which never enters the UTXO set
+    pub fn is_data(&self) -> bool {
+        self.script_pub_key.is_unspendable()
+    }
+
+    pub fn new(value: Amount, address: String) -> Result<Self> {
         let mut txo = TXOutput {
             value,
-            pub_key_hash: Vec::new(),
+            script_pub_key: Script::default(),
         };
         txo.lock(&address)?;
         Ok(txo)
     }
 }
+
+/// AddressToScript returns the locking script paying to an address: key
+/// addresses are pay-to-pubkey-hash, script addresses pay-to-script-hash
+pub fn address_to_script(address: &str) -> Result<Script> {
+    Ok(Address::decode(address)?.script_pub_key())
+}
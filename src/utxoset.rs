@@ -1,11 +1,15 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
+use failure::format_err;
 use log::info;
 
+use crate::amount::Amount;
 use crate::block::Block;
 use crate::blockchain::Blockchain;
+use crate::coinselect::{select_coins, Coin, CoinSelection};
 use crate::errors::Result;
-use crate::tx::TXOutputs;
+use crate::script::{Script, LOCKTIME_THRESHOLD};
+use crate::tx::{TXOutput, TXOutputs};
 
 /// UTXOSet represents UTXO set
 pub struct UTXOSet {
@@ -19,7 +23,7 @@ impl UTXOSet {
             info!("not exist any utxos to delete")
         }
         let db = sled::open("data/utxos")?;
-        let utxos = self.blockchain.find_UTXO();
+        let utxos = self.blockchain.find_UTXO()?;
 
         for (txid, outs) in utxos {
             db.insert(txid.as_bytes(), bincode::serialize(&outs)?)?;
@@ -27,47 +31,77 @@ impl UTXOSet {
         Ok(())
     }
 
-    /// FindUnspentTransactions returns a list of transactions containing unspent outputs
+    /// FindSpendableOutputs picks outputs locked with the script covering the
+    /// amount with the coin selection strategy
     pub fn find_spendable_outputs(
         &self,
-        address: &[u8],
-        amount: i32,
-    ) -> Result<(i32, HashMap<String, Vec<i32>>)> {
+        lock: &Script,
+        amount: Amount,
+        strategy: &CoinSelection,
+    ) -> Result<(Amount, HashMap<String, Vec<i32>>)> {
+        let coins = select_coins(self.find_coins(lock)?, amount, strategy)?;
         let mut unspent_outputs: HashMap<String, Vec<i32>> = HashMap::new();
-        let mut accumulated = 0;
+        let mut accumulated = Amount::ZERO;
+        for coin in coins {
+            accumulated = accumulated
+                .checked_add(coin.value)
+                .ok_or_else(|| format_err!("spendable outputs overflow the amount range"))?;
+            unspent_outputs
+                .entry(coin.txid)
+                .or_default()
+                .push(coin.vout);
+        }
+        Ok((accumulated, unspent_outputs))
+    }
+
+    /// FindCoins returns the outputs locked with the script that can be spent
+    /// now, skipping timelocked outputs that have not matured
+    pub fn find_coins(&self, lock: &Script) -> Result<Vec<Coin>> {
+        let mut coins = Vec::new();
+        let (height, time) = self.blockchain.lock_time_context()?;
         let db = sled::open("data/utxos")?;
         for kv in db.iter() {
             let (k, v) = kv?;
             let txid = String::from_utf8(k.to_vec())?;
             let outs: TXOutputs = bincode::deserialize(&v.to_vec())?;
-            for out_idx in 0..outs.outputs.len() {
-                if outs.outputs[out_idx].can_be_unlock_with(address) && accumulated < amount {
-                    accumulated += outs.outputs[out_idx].value;
-                    match unspent_outputs.get_mut(&txid) {
-                        Some(v) => v.push(out_idx as i32),
-                        None => {
-                            unspent_outputs.insert(txid.clone(), vec![out_idx as i32]);
-                        }
+            for (out_idx, out) in &outs.outputs {
+                let mature = match out.lock_time() {
+                    Some(lock_time) if lock_time < LOCKTIME_THRESHOLD => {
+                        (lock_time as i64) < height as i64
                     }
+                    Some(lock_time) => (lock_time as u128) < time,
+                    None => true,
+                };
+                if out.is_locked_to(lock) && mature {
+                    coins.push(Coin {
+                        txid: txid.clone(),
+                        vout: *out_idx,
+                        value: out.value,
+                    });
                 }
             }
         }
+        Ok(coins)
+    }
 
-        Ok((accumulated, unspent_outputs))
+    /// FindUTXO finds UTXO locked with the script, including timelocked ones
+    pub fn find_UTXO(&self, lock: &Script) -> Result<Vec<TXOutput>> {
+        let outpoints = self.find_outpoints(lock)?;
+        Ok(outpoints.into_iter().map(|(_, _, out)| out).collect())
     }
 
-    /// FindUTXO finds UTXO for a public key hash
-    pub fn find_UTXO(&self, pub_key_hash: &[u8]) -> Result<TXOutputs> {
-        let mut utxos = TXOutputs {
-            outputs: Vec::new(),
-        };
+    /// FindOutpoints finds UTXO locked with the script with the transaction id
+    /// and index of each, including timelocked ones
+    pub fn find_outpoints(&self, lock: &Script) -> Result<Vec<(String, i32, TXOutput)>> {
+        let mut utxos = Vec::new();
         let db = sled::open("data/utxos")?;
         for kv in db.iter() {
-            let (_, v) = kv?;
+            let (k, v) = kv?;
+            let txid = String::from_utf8(k.to_vec())?;
             let outs: TXOutputs = bincode::deserialize(&v.to_vec())?;
-            for out in outs.outputs {
-                if out.can_be_unlock_with(pub_key_hash) {
-                    utxos.outputs.push(out.clone());
+            for (out_idx, out) in outs.outputs {
+                if out.is_locked_to(lock) {
+                    utxos.push((txid.clone(), out_idx, out));
                 }
             }
         }
@@ -82,16 +116,9 @@ impl UTXOSet {
         for tx in block.get_transaction() {
             if !tx.is_coinbase() {
                 for vin in &tx.vin {
-                    let mut update_outputs = TXOutputs {
-                        outputs: Vec::new(),
-                    };
-                    let outs: TXOutputs =
+                    let mut update_outputs: TXOutputs =
                         bincode::deserialize(&db.get(&vin.txid)?.unwrap().to_vec())?;
-                    for out_idx in 0..outs.outputs.len() {
-                        if out_idx != vin.vout as usize {
-                            update_outputs.outputs.push(outs.outputs[out_idx].clone());
-                        }
-                    }
+                    update_outputs.outputs.remove(&vin.vout);
                     if update_outputs.outputs.is_empty() {
                         db.remove(&vin.txid)?;
                     } else {
@@ -100,16 +127,32 @@ impl UTXOSet {
                 }
             }
             let mut new_outputs = TXOutputs {
-                outputs: Vec::new(),
+                outputs: BTreeMap::new(),
             };
-            for out in &tx.vout {
-                new_outputs.outputs.push(out.clone());
+            for (out_idx, out) in tx.vout.iter().enumerate() {
+                if !out.is_data() {
+                    new_outputs.outputs.insert(out_idx as i32, out.clone());
+                }
+            }
+            if !new_outputs.outputs.is_empty() {
+                db.insert(tx.id.as_bytes(), bincode::serialize(&new_outputs)?)?;
             }
-            db.insert(tx.id.as_bytes(), bincode::serialize(&new_outputs)?)?;
         }
         Ok(())
     }
 
+    /// IsUnspent checks whether an output is in the UTXO set
+    pub fn is_unspent(&self, txid: &str, vout: i32) -> Result<bool> {
+        let db = sled::open("data/utxos")?;
+        match db.get(txid)? {
+            Some(data) => {
+                let outs: TXOutputs = bincode::deserialize(&data)?;
+                Ok(outs.outputs.contains_key(&vout))
+            }
+            None => Ok(false),
+        }
+    }
+
     /// CountTransactions returns the number of transactions in the UTXO set
     pub fn count_transactions(&self) -> Result<i32> {
         let mut counter = 0;
@@ -1,14 +1,22 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
+use crate::address::{Address, AddressKind};
+use crate::amount::Amount;
+use crate::block::{now_millis, Block};
+use crate::blockchain::{Blockchain, TipChange};
+use crate::crypter::{self, KdfParams, KEY_LEN};
 use crate::errors::Result;
-use bitcoincash_addr::{Address, HashType, Scheme};
+use crate::hdwallet;
+use crate::rpc::COOKIE_FILE;
+use crate::script::{sha256_hash, Script};
+use crate::transaction::{Transaction, SIGHASH_ALL};
+use crate::tx::address_to_script;
 use crypto::digest::Digest;
 use crypto::ed25519;
 use crypto::ripemd160::Ripemd160;
 use crypto::sha2::Sha256;
+use failure::format_err;
 use log::info;
-use rand::rngs::OsRng;
-use rand::RngCore;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -18,15 +26,11 @@ pub struct Wallet {
 }
 
 impl Wallet {
-    fn new() -> Self {
-        // 这段代码是一个创建钱包的函数，其功能是生成一个新的密钥对并返回一个包含密钥对的钱包对象。
+    fn new(key: &[u8; 32]) -> Self {
+        // 这段代码是一个创建钱包的函数，其功能是由种子派生的32字节key生成密钥对并返回一个包含密钥对的钱包对象。
         // 1. 函数定义了一个名为new的方法，返回类型为Self（即钱包对象）。
-        // 2. 创建一个长度为32的u8数组key，并将其所有元素初始化为0。
-        let mut key: [u8; 32] = [0; 32];
-        // 3. 使用操作系统提供的随机数生成器OsRng填充key数组。
-        OsRng.fill_bytes(&mut key);
-        // 4. 调用ed25519::keypair方法，传入key数组作为参数，生成一个密钥对，分别赋值给secret_key和public_key。
-        let (secret_key, public_key) = ed25519::keypair(&key);
+        // 2. 调用ed25519::keypair方法，传入key数组作为参数，生成一个密钥对，分别赋值给secret_key和public_key。
+        let (secret_key, public_key) = ed25519::keypair(key);
         // 5. 将secret_key转换为Vec<u8>类型，并赋值给secret_key变量。
         let secret_key = secret_key.to_vec();
         // 6. 将public_key转换为Vec<u8>类型，并赋值给public_key变量。
@@ -44,17 +48,75 @@ impl Wallet {
         let mut pub_hash = self.public_key.clone();
         // 2. 调用hash_pub_key函数，对pub_hash进行哈希处理。
         hash_pub_key(&mut pub_hash);
-        // 3. 创建一个Address结构体，其中body字段为pub_hash，scheme字段为Scheme::Base58，hash_type字段为HashType::Script，其他字段使用默认值。
-        let address = Address {
-            body: pub_hash,
-            scheme: Scheme::Base58,
-            hash_type: HashType::Script,
-            ..Default::default()
-        };
-        // 0 O 1 I
-        // 4. 调用address的encode方法，并使用unwrap()解包结果，得到最终的地址。
-        address.encode().unwrap()
+        // 3. 由公钥哈希得到最终的地址。
+        key_address(&pub_hash)
+    }
+
+    /// SignMessage signs the prefixed hash of a message, the signature carries
+    /// the public key so it can be checked against the address alone
+    pub fn sign_message(&self, message: &str) -> Vec<u8> {
+        let mut signature = self.public_key.clone();
+        signature.extend_from_slice(&ed25519::signature(
+            &message_hash(message),
+            &self.secret_key,
+        ));
+        signature
+    }
+}
+
+/// VerifyMessage checks that a signature made by SignMessage is over the message
+/// and from the key of the address
+pub fn verify_message(address: &str, signature: &[u8], message: &str) -> Result<bool> {
+    let decoded = Address::decode(address)?;
+    if decoded.kind != AddressKind::KeyHash {
+        return Err(format_err!("{} is not a key address", address));
+    }
+    if signature.len() != MESSAGE_SIGNATURE_LEN {
+        return Err(format_err!(
+            "a message signature is {} bytes, got {}",
+            MESSAGE_SIGNATURE_LEN,
+            signature.len()
+        ));
+    }
+    let (pub_key, signature) = signature.split_at(MESSAGE_SIGNATURE_LEN - 64);
+    let mut pub_key_hash = pub_key.to_vec();
+    hash_pub_key(&mut pub_key_hash);
+    if pub_key_hash != decoded.hash {
+        return Ok(false);
+    }
+    Ok(ed25519::verify(&message_hash(message), pub_key, signature))
+}
+
+/// message_hash is sha256(MESSAGE_MAGIC || length || message). The digests
+/// transactions and blocks sign never cover the magic, so a message signature
+/// cannot be replayed as theirs
+fn message_hash(message: &str) -> Vec<u8> {
+    let mut data = MESSAGE_MAGIC.as_bytes().to_vec();
+    data.extend_from_slice(&(message.len() as u64).to_le_bytes());
+    data.extend_from_slice(message.as_bytes());
+    sha256_hash(&data)
+}
+
+/// KeyAddress returns the pay-to-pubkey-hash address of a public key hash
+pub fn key_address(pub_key_hash: &[u8]) -> String {
+    Address::new(AddressKind::KeyHash, pub_key_hash).encode()
+}
+
+/// MultisigAddress returns the pay-to-script-hash address of a multisig redeem script
+pub fn multisig_address(redeem_script: &Script) -> Result<String> {
+    Ok(Address::new(AddressKind::Multisig, &redeem_script.hash()?).encode())
+}
+
+/// ScriptAddress returns the address a locking script pays to, if any. Script
+/// hashes show as multisig addresses, the only redeem scripts wallets create
+pub fn script_address(script: &Script) -> Option<String> {
+    let script = script.split_timelock().1;
+    if let Some(hash) = script.p2pkh_hash() {
+        return Some(key_address(hash));
     }
+    script
+        .p2sh_hash()
+        .map(|hash| Address::new(AddressKind::Multisig, hash).encode())
 }
 
 pub fn hash_pub_key(pub_key: &mut Vec<u8>) {
@@ -65,7 +127,8 @@ pub fn hash_pub_key(pub_key: &mut Vec<u8>) {
     let mut hasher1 = Sha256::new();
     // 3. 将 pub_key 作为输入传递给 hasher1。
     hasher1.input(pub_key);
-    // 4. 将 hasher1 的结果保存到 pub_key 中。
+    // 4. 将 pub_key 的大小调整为 32，并将 hasher1 的结果保存到 pub_key 中。
+    pub_key.resize(32, 0);
     hasher1.result(pub_key);
     // 5. 创建一个 Ripemd160 类型的哈希器 hasher2。
     let mut hasher2 = Ripemd160::new();
@@ -77,42 +140,412 @@ pub fn hash_pub_key(pub_key: &mut Vec<u8>) {
     hasher2.result(pub_key);
 }
 
+/// WalletCoin is an unspent output owned by the wallet
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WalletCoin {
+    pub txid: String,
+    pub vout: i32,
+    pub address: String,
+    pub value: Amount,
+    pub height: i32,
+}
+
+/// WalletTx records a confirmed transaction touching the wallet, with the
+/// coins it paid to the wallet and the wallet coins it spent
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WalletTx {
+    pub txid: String,
+    pub block_hash: String,
+    pub height: i32,
+    pub received: Vec<WalletCoin>,
+    pub spent: Vec<WalletCoin>,
+}
+
+/// the wallet commands use unless another one is selected, it is always loaded
+pub const DEFAULT_WALLET: &str = "default";
+/// the file listing the named wallets that are loaded
+const LOADED_WALLETS_FILE: &str = "data/loaded_wallets";
+const ENCRYPTION_KEY: &str = "encryption";
+
+/// Encryption describes how the secret keys of the wallet are encrypted: a
+/// random master key encrypts every secret key, and the master key itself is
+/// encrypted with a key derived from the passphrase
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Encryption {
+    kdf: KdfParams,
+    master_key: Vec<u8>,
+}
+
+/// length of an ed25519 private key, the secret key being it followed by the public key
+const PRIVATE_KEY_LEN: usize = 32;
+/// the number of unused addresses after which restoring stops looking for more
+const GAP_LIMIT: u32 = 20;
+/// MESSAGE_MAGIC prefixes every signed message
+const MESSAGE_MAGIC: &str = "Blockchain Rust Signed Message:\n";
+/// a message signature is the 32 byte public key and the 64 byte ed25519 signature
+const MESSAGE_SIGNATURE_LEN: usize = 96;
+const SEED_KEY: &str = "seed";
+const NEXT_INDEX_KEY: &str = "next_index";
+
+/// HdKey is a key derived from the seed, its public key is kept so that the
+/// address stays known while an encrypted wallet is locked
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct HdKey {
+    index: u32,
+    public_key: Vec<u8>,
+}
+
+/// AddressLabel is the label and account an address is filed under
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AddressLabel {
+    pub label: String,
+    pub account: String,
+}
+
 pub struct Wallets {
+    name: String,
+    /// the database directory of the wallet
+    path: String,
+    /// the wallet database, locked against other processes while the wallet is open
+    db: sled::Db,
     wallets: HashMap<String, Wallet>,
+    multisig: HashMap<String, Script>,
+    coins: BTreeMap<(String, i32), WalletCoin>,
+    transactions: HashMap<String, WalletTx>,
+    encryption: Option<Encryption>,
+    /// encrypted secret keys by address, only used once the wallet is encrypted
+    encrypted_keys: HashMap<String, Vec<u8>>,
+    /// master key of an encrypted wallet while it is unlocked, only ever kept in memory
+    master_key: Option<Vec<u8>>,
+    /// BIP39 seed the keys derive from, None while an encrypted wallet is locked
+    seed: Option<Vec<u8>>,
+    /// the seed encrypted with the master key, only used once the wallet is encrypted
+    encrypted_seed: Option<Vec<u8>>,
+    /// derivation index by address of the keys derived from the seed
+    hd_keys: HashMap<String, u32>,
+    next_index: u32,
+    /// addresses the wallet tracks without being able to sign for them
+    watch_only: HashSet<String>,
+    labels: HashMap<String, AddressLabel>,
 }
 
 impl Wallets {
-    pub fn new() -> Result<Wallets> {
+    /// New opens the wallet of the given name, creating it when it does not exist
+    pub fn new(name: &str) -> Result<Wallets> {
         // 这段代码的功能是创建一个新的钱包对象，并从数据库中加载现有的钱包数据。
-        // 1. 创建一个名为wlt的可变变量，类型为Wallets结构体，其中包含一个HashMap用于存储钱包数据。
+        // 1. 打开钱包的数据库，运行中的节点持有已加载钱包的数据库。
+        let path = wallet_path(name)?;
+        let db = open_db(name, &path)?;
+        Wallets::read_db(name, path, db)
+    }
+
+    /// read_db reads the wallet out of its opened database
+    fn read_db(name: &str, path: String, db: sled::Db) -> Result<Wallets> {
+        // 2. 创建一个名为wlt的可变变量，类型为Wallets结构体，其中包含一个HashMap用于存储钱包数据。
         let mut wlt = Wallets {
+            name: name.to_string(),
+            path,
+            db: db.clone(),
             wallets: HashMap::<String, Wallet>::new(),
+            multisig: HashMap::new(),
+            coins: BTreeMap::new(),
+            transactions: HashMap::new(),
+            encryption: None,
+            encrypted_keys: HashMap::new(),
+            master_key: None,
+            seed: None,
+            encrypted_seed: None,
+            hd_keys: HashMap::new(),
+            next_index: 0,
+            watch_only: HashSet::new(),
+            labels: HashMap::new(),
         };
-        // 2. 使用sled::open函数打开名为"data/wallets"的数据库，并将返回的结果赋值给db变量。
-        let db = sled::open("data/wallets")?;
         // 3. 对数据库进行迭代操作，使用for循环遍历db中的每个元素。
         for item in db.into_iter() {
             // 4. 在循环中，将当前元素赋值给变量i。
             let i = item?;
-            // 5. 使用i.0.to_vec()将i的第一个元素转换成UTF-8编码的字符串，并将结果赋值给address变量。
-            let address = String::from_utf8(i.0.to_vec())?;
-            // 6. 使用i.1.to_vec()将i的第二个元素转换成字节向量，并使用bincode::deserialize函数将其反序列化为钱包对象，并将结果赋值给wallet变量。
-            let wallet = bincode::deserialize(&i.1.to_vec())?;
+            // 5. 使用i.1.to_vec()将i的第二个元素转换成字节向量，并使用bincode::deserialize函数将其反序列化为钱包对象，并将结果赋值给wallet变量。
+            let wallet: Wallet = bincode::deserialize(&i.1.to_vec())?;
+            // 6. 地址由公钥重新计算，旧版本保存的地址键会被更新为当前的地址格式。
+            let address = wallet.get_address();
             // 7. 将address和wallet插入到wlt的wallets HashMap中。
             wlt.wallets.insert(address, wallet);
         }
-        // 8. 使用drop函数释放db的所有权，确保数据库资源被正确释放。
-        drop(db);
+        if let Some(data) = db.open_tree("meta")?.get(ENCRYPTION_KEY)? {
+            wlt.encryption = Some(bincode::deserialize(&data)?);
+        }
+        for item in db.open_tree("multisig")?.iter() {
+            let (k, v) = item?;
+            let address = String::from_utf8(k.to_vec())?;
+            wlt.multisig.insert(address, bincode::deserialize(&v)?);
+        }
+        let hd = db.open_tree("hd")?;
+        let seed = hd.get(SEED_KEY)?.map(|data| data.to_vec());
+        if let Some(data) = hd.get(NEXT_INDEX_KEY)? {
+            wlt.next_index = bincode::deserialize(&data)?;
+        }
+        let mut hd_keys = Vec::new();
+        for item in db.open_tree("hd_keys")?.iter() {
+            let (k, v) = item?;
+            let key: HdKey = bincode::deserialize(&v)?;
+            hd_keys.push((String::from_utf8(k.to_vec())?, key));
+        }
+        for item in db.open_tree("labels")?.iter() {
+            let (k, v) = item?;
+            wlt.labels
+                .insert(String::from_utf8(k.to_vec())?, bincode::deserialize(&v)?);
+        }
+        for item in db.open_tree("watch_only")?.iter() {
+            let (k, _) = item?;
+            wlt.watch_only.insert(String::from_utf8(k.to_vec())?);
+        }
+        for item in db.open_tree("coins")?.iter() {
+            let (_, v) = item?;
+            let coin: WalletCoin = bincode::deserialize(&v)?;
+            wlt.coins.insert((coin.txid.clone(), coin.vout), coin);
+        }
+        for item in db.open_tree("transactions")?.iter() {
+            let (_, v) = item?;
+            let wtx: WalletTx = bincode::deserialize(&v)?;
+            wlt.transactions.insert(wtx.txid.clone(), wtx);
+        }
+        // 8. 数据库句柄保存在wlt中，钱包存在期间一直持有数据库的锁。
+        if wlt.encryption.is_some() {
+            // the stored secret keys are encrypted, they stay unusable until unlocked
+            for (address, wallet) in wlt.wallets.iter_mut() {
+                let sealed = std::mem::take(&mut wallet.secret_key);
+                wlt.encrypted_keys.insert(address.clone(), sealed);
+            }
+        }
+        for (address, key) in hd_keys {
+            wlt.hd_keys.insert(address.clone(), key.index);
+            let wallet = Wallet {
+                secret_key: Vec::new(),
+                public_key: key.public_key,
+            };
+            wlt.wallets.insert(address, wallet);
+        }
+        if wlt.encryption.is_some() {
+            wlt.encrypted_seed = seed;
+            // older versions kept the master key of an unlocked wallet in this file
+            match std::fs::remove_file(format!("{}.session", wlt.path)) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        } else {
+            wlt.seed = seed;
+            wlt.derive_secret_keys();
+        }
         // 9. 返回一个包含wlt的Result对象，表示钱包对象的创建成功。
         Ok(wlt)
     }
 
-    pub fn create_wallet(&mut self) -> String {
-        let wallet = Wallet::new();
+    /// CreateWallet derives the key of the next address from the seed
+    pub fn create_wallet(&mut self) -> Result<String> {
+        if self.is_locked() {
+            return Err(locked_error());
+        }
+        let seed = match &self.seed {
+            Some(seed) => seed,
+            None => return Err(format_err!("the wallet has no seed")),
+        };
+        let wallet = Wallet::new(&hdwallet::derive_key(seed, self.next_index));
         let address = wallet.get_address();
+        self.hd_keys.insert(address.clone(), self.next_index);
+        self.next_index += 1;
         self.wallets.insert(address.clone(), wallet);
         info!("Created wallet with address: {}", address);
-        address
+        Ok(address)
+    }
+
+    /// NewAddress creates the next address filed under the label, first giving
+    /// a wallet without a seed a new one whose mnemonic it returns
+    pub fn new_address(&mut self, label: &AddressLabel) -> Result<(String, Option<String>)> {
+        let mnemonic = if self.has_seed() {
+            None
+        } else {
+            Some(self.generate_seed()?)
+        };
+        let address = self.create_wallet()?;
+        if !label.label.is_empty() {
+            self.set_label(&address, &label.label)?;
+        }
+        if !label.account.is_empty() {
+            self.set_account(&address, &label.account)?;
+        }
+        Ok((address, mnemonic))
+    }
+
+    /// HasSeed checks whether the wallet has a seed to derive keys from
+    pub fn has_seed(&self) -> bool {
+        self.seed.is_some() || self.encrypted_seed.is_some()
+    }
+
+    /// GenerateSeed gives the wallet a new random seed and returns its mnemonic
+    pub fn generate_seed(&mut self) -> Result<String> {
+        let mnemonic = hdwallet::generate_mnemonic();
+        self.set_seed(hdwallet::mnemonic_to_seed(&mnemonic, "")?)?;
+        Ok(mnemonic)
+    }
+
+    /// RestoreSeed gives the wallet the seed of the mnemonic and derives its
+    /// addresses until GAP_LIMIT consecutive ones never appear in the
+    /// blockchain, then rescans the blockchain, returning the number of addresses
+    pub fn restore_seed(&mut self, mnemonic: &str, bc: &Blockchain) -> Result<u32> {
+        let seed = hdwallet::mnemonic_to_seed(mnemonic, "")?;
+        let used = used_addresses(bc)?;
+        let mut restored = 0;
+        let mut index = 0;
+        while index < restored + GAP_LIMIT {
+            let address = Wallet::new(&hdwallet::derive_key(&seed, index)).get_address();
+            if used.contains(&address) {
+                restored = index + 1;
+            }
+            index += 1;
+        }
+        self.set_seed(seed)?;
+        for _ in 0..restored {
+            self.create_wallet()?;
+        }
+        self.rescan(bc, 0)?;
+        Ok(restored)
+    }
+
+    fn set_seed(&mut self, seed: Vec<u8>) -> Result<()> {
+        if self.has_seed() {
+            return Err(format_err!("the wallet already has a seed"));
+        }
+        if self.encryption.is_some() {
+            let master_key = self.master_key.as_ref().ok_or_else(locked_error)?;
+            self.encrypted_seed = Some(crypter::encrypt(master_key, &seed));
+        }
+        self.seed = Some(seed);
+        self.next_index = 0;
+        Ok(())
+    }
+
+    /// derive_secret_keys fills in the secret keys derived from the seed
+    fn derive_secret_keys(&mut self) {
+        let seed = match &self.seed {
+            Some(seed) => seed,
+            None => return,
+        };
+        for (address, index) in &self.hd_keys {
+            if let Some(wallet) = self.wallets.get_mut(address) {
+                wallet.secret_key = Wallet::new(&hdwallet::derive_key(seed, *index)).secret_key;
+            }
+        }
+    }
+
+    /// Exists checks whether a wallet of the given name was created
+    pub fn exists(name: &str) -> Result<bool> {
+        Ok(std::path::Path::new(&wallet_path(name)?).exists())
+    }
+
+    /// Loaded returns the names of the loaded wallets, the default one first
+    pub fn loaded() -> Result<Vec<String>> {
+        let mut names = vec![DEFAULT_WALLET.to_string()];
+        match std::fs::read_to_string(LOADED_WALLETS_FILE) {
+            Ok(text) => names.extend(text.lines().map(|line| line.to_string())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        Ok(names)
+    }
+
+    /// OpenLoaded opens every loaded wallet by name, for a node to keep them open
+    pub fn open_loaded() -> Result<HashMap<String, Wallets>> {
+        let mut loaded = HashMap::new();
+        for name in Wallets::loaded()? {
+            loaded.insert(name.clone(), Wallets::new(&name)?);
+        }
+        Ok(loaded)
+    }
+
+    /// UpdateLoaded applies a change to every loaded wallet and saves it
+    pub fn update_loaded(update: impl Fn(&mut Wallets) -> Result<()>) -> Result<()> {
+        for name in Wallets::loaded()? {
+            let mut wallets = Wallets::new(&name)?;
+            update(&mut wallets)?;
+            wallets.save_all()?;
+        }
+        Ok(())
+    }
+
+    /// Load marks an existing wallet as loaded
+    pub fn load(name: &str) -> Result<()> {
+        if !Wallets::exists(name)? {
+            return Err(format_err!("wallet {} does not exist", name));
+        }
+        let mut names = Wallets::loaded()?;
+        if names.iter().any(|n| n == name) {
+            return Err(format_err!("wallet {} is already loaded", name));
+        }
+        names.push(name.to_string());
+        write_loaded(&names)
+    }
+
+    /// Unload marks a wallet as no longer loaded, the default wallet stays loaded
+    pub fn unload(name: &str) -> Result<()> {
+        if name == DEFAULT_WALLET {
+            return Err(format_err!("the default wallet cannot be unloaded"));
+        }
+        let mut names = Wallets::loaded()?;
+        if !names.iter().any(|n| n == name) {
+            return Err(format_err!("wallet {} is not loaded", name));
+        }
+        names.retain(|n| n != name);
+        write_loaded(&names)
+    }
+
+    /// SetLabel files an address of the wallet under a label
+    pub fn set_label(&mut self, address: &str, label: &str) -> Result<()> {
+        if !self.is_mine(address) {
+            return Err(format_err!("{} is not in the wallet", address));
+        }
+        self.labels.entry(address.to_string()).or_default().label = label.to_string();
+        Ok(())
+    }
+
+    /// SetAccount groups an address of the wallet under an account
+    pub fn set_account(&mut self, address: &str, account: &str) -> Result<()> {
+        if !self.is_mine(address) {
+            return Err(format_err!("{} is not in the wallet", address));
+        }
+        self.labels.entry(address.to_string()).or_default().account = account.to_string();
+        Ok(())
+    }
+
+    /// GetLabel returns the label and account of an address, empty when it has none
+    pub fn get_label(&self, address: &str) -> AddressLabel {
+        self.labels.get(address).cloned().unwrap_or_default()
+    }
+
+    /// GetAccountAddresses returns the key, watch-only and multisig addresses of an account
+    pub fn get_account_addresses(&self, account: &str) -> Vec<String> {
+        let mut addresses: Vec<String> = self
+            .wallets
+            .keys()
+            .chain(&self.watch_only)
+            .chain(self.multisig.keys())
+            .filter(|address| self.get_label(address).account == account)
+            .cloned()
+            .collect();
+        addresses.sort();
+        addresses
+    }
+
+    /// GetAccounts returns the accounts addresses are grouped under, the
+    /// default account being the empty name
+    pub fn get_accounts(&self) -> Vec<String> {
+        let mut accounts = vec![String::new()];
+        for label in self.labels.values() {
+            if !accounts.contains(&label.account) {
+                accounts.push(label.account.clone());
+            }
+        }
+        accounts.sort();
+        accounts
     }
 
     pub fn get_all_address(&self) -> Vec<String> {
@@ -127,14 +560,852 @@ impl Wallets {
         self.wallets.get(address)
     }
 
+    /// ResolvePublicKey accepts a hex ed25519 public key or the address of a wallet key
+    pub fn resolve_public_key(&self, key: &str) -> Result<Vec<u8>> {
+        if let Some(wallet) = self.get_wallet(key) {
+            return Ok(wallet.public_key.clone());
+        }
+        match hex::decode(key) {
+            Ok(public_key) if public_key.len() == 32 => Ok(public_key),
+            _ => Err(format_err!(
+                "{} is neither a public key nor a wallet address",
+                key
+            )),
+        }
+    }
+
+    /// SignTransaction adds the signature of every key of the wallet the transaction needs
+    pub fn sign_transaction(&self, bc: &Blockchain, tx: &mut Transaction) -> Result<()> {
+        for address in self.get_all_address() {
+            let wallet = self.get_signing_wallet(&address)?;
+            bc.sign_transaction(tx, &wallet.secret_key, SIGHASH_ALL)?;
+        }
+        Ok(())
+    }
+
+    /// GetSigningWallet returns the wallet of the address with its secret key,
+    /// failing when the address is unknown or the wallet is locked
+    pub fn get_signing_wallet(&self, address: &str) -> Result<&Wallet> {
+        let wallet = match self.wallets.get(address) {
+            Some(wallet) => wallet,
+            None if self.watch_only.contains(address) => {
+                return Err(format_err!(
+                    "{} is watch-only, the wallet cannot sign for it",
+                    address
+                ))
+            }
+            None => return Err(format_err!("{} is not in the wallet", address)),
+        };
+        if self.is_locked() {
+            return Err(locked_error());
+        }
+        Ok(wallet)
+    }
+
+    /// DumpPrivateKey returns the 32 byte ed25519 private key of the address
+    pub fn dump_private_key(&self, address: &str) -> Result<Vec<u8>> {
+        let wallet = self.get_signing_wallet(address)?;
+        Ok(wallet.secret_key[..PRIVATE_KEY_LEN].to_vec())
+    }
+
+    /// ImportPrivateKey adds a 32 byte ed25519 private key and returns its address
+    pub fn import_private_key(&mut self, private_key: &[u8]) -> Result<String> {
+        if private_key.len() != PRIVATE_KEY_LEN {
+            return Err(format_err!(
+                "a private key has {} bytes, got {}",
+                PRIVATE_KEY_LEN,
+                private_key.len()
+            ));
+        }
+        if self.is_locked() {
+            return Err(locked_error());
+        }
+        let mut key = [0; PRIVATE_KEY_LEN];
+        key.copy_from_slice(private_key);
+        let wallet = Wallet::new(&key);
+        let address = wallet.get_address();
+        if self.wallets.contains_key(&address) {
+            return Ok(address);
+        }
+        if let Some(master_key) = &self.master_key {
+            let sealed = crypter::encrypt(master_key, &wallet.secret_key);
+            self.encrypted_keys.insert(address.clone(), sealed);
+        }
+        self.watch_only.remove(&address);
+        self.wallets.insert(address.clone(), wallet);
+        info!("Imported key of address: {}", address);
+        Ok(address)
+    }
+
+    /// ImportAddress tracks the coins of a key address without its private key
+    pub fn import_address(&mut self, address: &str) -> Result<()> {
+        if address_to_script(address)?.p2pkh_hash().is_none() {
+            return Err(format_err!("{} is not a key address", address));
+        }
+        if !self.wallets.contains_key(address) {
+            self.watch_only.insert(address.to_string());
+        }
+        Ok(())
+    }
+
+    /// IsWatchOnly checks whether the wallet tracks the address without its private key
+    pub fn is_watch_only(&self, address: &str) -> bool {
+        self.watch_only.contains(address)
+    }
+
+    /// GetWatchOnly returns the watch-only addresses
+    pub fn get_watch_only(&self) -> Vec<String> {
+        self.watch_only.iter().cloned().collect()
+    }
+
+    /// Dump writes the whole wallet in the backup text format: one entry per
+    /// line, `#` starting a comment,
+    ///
+    ///     seed <SEED_HEX> <NEXT_INDEX>         the BIP39 seed and how many addresses it derived
+    ///     key <PRIVATE_KEY_HEX> <ADDRESS>      an imported private key
+    ///     watch <ADDRESS>                      a watch-only address
+    ///     multisig <ADDRESS> <M> <PUBKEY_HEX>... an M-of-N multisig address
+    pub fn dump(&self) -> Result<String> {
+        if self.is_locked() {
+            return Err(locked_error());
+        }
+        let mut lines = vec![
+            "# blockchain-rust wallet backup".to_string(),
+            format!("# created at {}", now_millis()?),
+            "# this file holds private keys, keep it secret".to_string(),
+        ];
+        if let Some(seed) = &self.seed {
+            lines.push(format!("seed {} {}", hex::encode(seed), self.next_index));
+        }
+        let mut keys: Vec<&String> = self
+            .wallets
+            .keys()
+            .filter(|address| !self.hd_keys.contains_key(*address))
+            .collect();
+        keys.sort();
+        for address in keys {
+            lines.push(format!(
+                "key {} {}",
+                hex::encode(self.dump_private_key(address)?),
+                address
+            ));
+        }
+        let mut watch_only = self.get_watch_only();
+        watch_only.sort();
+        for address in watch_only {
+            lines.push(format!("watch {}", address));
+        }
+        let mut multisig: Vec<(&String, &Script)> = self.multisig.iter().collect();
+        multisig.sort_by_key(|(address, _)| *address);
+        for (address, redeem_script) in multisig {
+            if let Some((m, pub_keys)) = redeem_script.multisig_params() {
+                let pub_keys: Vec<String> = pub_keys.iter().map(hex::encode).collect();
+                lines.push(format!("multisig {} {} {}", address, m, pub_keys.join(" ")));
+            }
+        }
+        lines.push(String::new());
+        Ok(lines.join("\n"))
+    }
+
+    /// ImportDump adds the entries of a backup written by Dump and returns
+    /// their number. A seed other than the wallet's own has its derived keys
+    /// imported as private keys
+    pub fn import_dump(&mut self, text: &str) -> Result<usize> {
+        let mut entries = 0;
+        for (n, line) in text.lines().enumerate() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.is_empty() || fields[0].starts_with('#') {
+                continue;
+            }
+            let invalid = || format_err!("line {}: invalid entry '{}'", n + 1, line);
+            match fields[..] {
+                ["seed", seed, next_index] => {
+                    let seed = hex::decode(seed).map_err(|_| invalid())?;
+                    let next_index: u32 = next_index.parse().map_err(|_| invalid())?;
+                    self.import_seed(seed, next_index)?;
+                }
+                ["key", private_key, address] => {
+                    let private_key = hex::decode(private_key).map_err(|_| invalid())?;
+                    if self.import_private_key(&private_key)? != address {
+                        return Err(format_err!(
+                            "line {}: the key is not the key of {}",
+                            n + 1,
+                            address
+                        ));
+                    }
+                }
+                ["watch", address] => self.import_address(address)?,
+                ["multisig", address, m, ref pub_keys @ ..] if !pub_keys.is_empty() => {
+                    let m: usize = m.parse().map_err(|_| invalid())?;
+                    let pub_keys = pub_keys
+                        .iter()
+                        .map(|key| hex::decode(key).map_err(|_| invalid()))
+                        .collect::<Result<Vec<Vec<u8>>>>()?;
+                    if self.create_multisig(m, &pub_keys)? != address {
+                        return Err(format_err!(
+                            "line {}: the keys do not make {}",
+                            n + 1,
+                            address
+                        ));
+                    }
+                }
+                _ => return Err(invalid()),
+            }
+            entries += 1;
+        }
+        Ok(entries)
+    }
+
+    /// import_seed adopts the seed when the wallet has none, and otherwise
+    /// imports its first `next_index` keys
+    fn import_seed(&mut self, seed: Vec<u8>, next_index: u32) -> Result<()> {
+        if self.is_locked() {
+            return Err(locked_error());
+        }
+        if !self.has_seed() {
+            self.set_seed(seed.clone())?;
+        }
+        if self.seed.as_ref() == Some(&seed) {
+            while self.next_index < next_index {
+                self.create_wallet()?;
+            }
+            return Ok(());
+        }
+        for index in 0..next_index {
+            self.import_private_key(&hdwallet::derive_key(&seed, index))?;
+        }
+        Ok(())
+    }
+
+    /// IsEncrypted checks whether the secret keys are stored encrypted
+    pub fn is_encrypted(&self) -> bool {
+        self.encryption.is_some()
+    }
+
+    /// IsLocked checks whether the secret keys are encrypted and not unlocked
+    pub fn is_locked(&self) -> bool {
+        self.encryption.is_some() && self.master_key.is_none()
+    }
+
+    /// CheckEncryptable fails when the wallet cannot be encrypted with the passphrase
+    pub fn check_encryptable(&self, passphrase: &str) -> Result<()> {
+        if self.encryption.is_some() {
+            return Err(format_err!("the wallet is already encrypted"));
+        }
+        if passphrase.is_empty() {
+            return Err(format_err!("the passphrase must not be empty"));
+        }
+        Ok(())
+    }
+
+    /// EncryptWallet encrypts the secret keys with the passphrase and returns
+    /// the wallet locked. The encrypted wallet is written to a fresh database
+    /// that replaces the old one, so no plaintext key is left in the database
+    pub fn encrypt_wallet(mut self, passphrase: &str) -> Result<Wallets> {
+        self.check_encryptable(passphrase)?;
+        let kdf = KdfParams::new();
+        let mut master_key = crypter::random_bytes(KEY_LEN);
+        let mut key = kdf.derive_key(passphrase);
+        self.encryption = Some(Encryption {
+            master_key: crypter::encrypt(&key, &master_key),
+            kdf,
+        });
+        crypter::wipe(&mut key);
+        for (address, wallet) in &self.wallets {
+            if !self.hd_keys.contains_key(address) {
+                let sealed = crypter::encrypt(&master_key, &wallet.secret_key);
+                self.encrypted_keys.insert(address.clone(), sealed);
+            }
+        }
+        self.encrypted_seed = self
+            .seed
+            .as_ref()
+            .map(|seed| crypter::encrypt(&master_key, seed));
+        crypter::wipe(&mut master_key);
+        self.lock()?;
+
+        let fresh_path = format!("{}.encrypted", self.path);
+        let old_path = format!("{}.old", self.path);
+        for path in [&fresh_path, &old_path] {
+            match std::fs::remove_dir_all(path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        let fresh = sled::open(&fresh_path)?;
+        self.save_to(&fresh)?;
+        drop(fresh);
+        let (name, path) = (self.name.clone(), self.path.clone());
+        // the old database closes with the wallet before its files are removed
+        drop(self);
+        std::fs::rename(&path, &old_path)?;
+        std::fs::rename(&fresh_path, &path)?;
+        std::fs::remove_dir_all(&old_path)?;
+        Wallets::new(&name)
+    }
+
+    /// Unlock decrypts the secret keys with the passphrase. They are kept in
+    /// memory only and wiped by Lock or when the wallet is dropped
+    pub fn unlock(&mut self, passphrase: &str) -> Result<()> {
+        let encryption = match &self.encryption {
+            Some(encryption) => encryption,
+            None => return Err(format_err!("the wallet is not encrypted")),
+        };
+        let mut key = encryption.kdf.derive_key(passphrase);
+        let master_key = crypter::decrypt(&key, &encryption.master_key);
+        crypter::wipe(&mut key);
+        let master_key =
+            master_key.map_err(|_| format_err!("the wallet passphrase is incorrect"))?;
+        self.decrypt_keys(master_key)
+    }
+
+    /// Lock wipes the master key and the secret keys from memory
+    pub fn lock(&mut self) -> Result<()> {
+        if self.encryption.is_none() {
+            return Err(format_err!("the wallet is not encrypted"));
+        }
+        if let Some(mut master_key) = self.master_key.take() {
+            crypter::wipe(&mut master_key);
+        }
+        if let Some(mut seed) = self.seed.take() {
+            crypter::wipe(&mut seed);
+        }
+        for wallet in self.wallets.values_mut() {
+            crypter::wipe(&mut wallet.secret_key);
+        }
+        Ok(())
+    }
+
+    fn decrypt_keys(&mut self, master_key: Vec<u8>) -> Result<()> {
+        for (address, sealed) in &self.encrypted_keys {
+            if let Some(wallet) = self.wallets.get_mut(address) {
+                wallet.secret_key = crypter::decrypt(&master_key, sealed)?;
+            }
+        }
+        if let Some(sealed) = &self.encrypted_seed {
+            self.seed = Some(crypter::decrypt(&master_key, sealed)?);
+            self.derive_secret_keys();
+        }
+        self.master_key = Some(master_key);
+        Ok(())
+    }
+
+    /// CreateMultisig registers an M-of-N redeem script and returns its address
+    pub fn create_multisig(&mut self, m: usize, pub_keys: &[Vec<u8>]) -> Result<String> {
+        let redeem_script = Script::new_multisig(m, pub_keys);
+        if redeem_script.multisig_params().is_none() {
+            return Err(format_err!(
+                "a multisig address needs 1 <= M <= N keys, got {} of {}",
+                m,
+                pub_keys.len()
+            ));
+        }
+        let address = multisig_address(&redeem_script)?;
+        self.multisig.insert(address.clone(), redeem_script);
+        info!("Created multisig address: {}", address);
+        Ok(address)
+    }
+
+    /// GetMultisig returns the redeem script of a multisig address
+    pub fn get_multisig(&self, address: &str) -> Option<&Script> {
+        self.multisig.get(address)
+    }
+
+    /// OwnerOf returns the wallet or multisig address a locking script pays to,
+    /// if it belongs to the wallet
+    pub fn owner_of(&self, script: &Script) -> Option<String> {
+        let script = script.split_timelock().1;
+        let address = if let Some(hash) = script.p2pkh_hash() {
+            key_address(hash)
+        } else if script.p2sh_hash().is_some() {
+            self.multisig
+                .iter()
+                .find(|(_, redeem)| redeem.hash().ok().as_deref() == script.p2sh_hash())
+                .map(|(address, _)| address.clone())?
+        } else {
+            return None;
+        };
+        if self.is_mine(&address) {
+            Some(address)
+        } else {
+            None
+        }
+    }
+
+    /// ConnectBlock records the wallet coins the block creates and spends
+    pub fn connect_block(&mut self, block: &Block) {
+        for tx in block.get_transaction() {
+            let mut spent = Vec::new();
+            if !tx.is_coinbase() {
+                for vin in &tx.vin {
+                    if let Some(coin) = self.coins.remove(&(vin.txid.clone(), vin.vout)) {
+                        spent.push(coin);
+                    }
+                }
+            }
+            let mut received = Vec::new();
+            for (vout, out) in tx.vout.iter().enumerate() {
+                if let Some(address) = self.owner_of(&out.script_pub_key) {
+                    let coin = WalletCoin {
+                        txid: tx.id.clone(),
+                        vout: vout as i32,
+                        address,
+                        value: out.value,
+                        height: block.get_height(),
+                    };
+                    self.coins
+                        .insert((coin.txid.clone(), coin.vout), coin.clone());
+                    received.push(coin);
+                }
+            }
+            if !spent.is_empty() || !received.is_empty() {
+                self.transactions.insert(
+                    tx.id.clone(),
+                    WalletTx {
+                        txid: tx.id.clone(),
+                        block_hash: block.get_hash(),
+                        height: block.get_height(),
+                        received,
+                        spent,
+                    },
+                );
+            }
+        }
+    }
+
+    /// DisconnectBlock undoes ConnectBlock when the block leaves the best chain
+    pub fn disconnect_block(&mut self, block: &Block) {
+        for tx in block.get_transaction().iter().rev() {
+            if let Some(wtx) = self.transactions.remove(&tx.id) {
+                for coin in wtx.received {
+                    self.coins.remove(&(coin.txid, coin.vout));
+                }
+                for coin in wtx.spent {
+                    self.coins.insert((coin.txid.clone(), coin.vout), coin);
+                }
+            }
+        }
+    }
+
+    /// ApplyTipChange disconnects the blocks that left the best chain and connects the new ones
+    pub fn apply_tip_change(&mut self, change: &TipChange) {
+        for block in &change.disconnected {
+            self.disconnect_block(block);
+        }
+        for block in &change.connected {
+            self.connect_block(block);
+        }
+    }
+
+    /// Rescan forgets what the wallet learned from blocks at or above `from_height`
+    /// and scans them again, returning the number of blocks scanned
+    pub fn rescan(&mut self, bc: &Blockchain, from_height: i32) -> Result<usize> {
+        let mut blocks: Vec<Block> = Vec::new();
+        for block in bc.iter() {
+            let block = block?;
+            if block.get_height() < from_height {
+                break;
+            }
+            blocks.push(block);
+        }
+        self.coins.retain(|_, coin| coin.height < from_height);
+        let forgotten: Vec<String> = self
+            .transactions
+            .values()
+            .filter(|wtx| wtx.height >= from_height)
+            .map(|wtx| wtx.txid.clone())
+            .collect();
+        for txid in forgotten {
+            if let Some(wtx) = self.transactions.remove(&txid) {
+                for coin in wtx.spent {
+                    if coin.height < from_height {
+                        self.coins.insert((coin.txid.clone(), coin.vout), coin);
+                    }
+                }
+            }
+        }
+        blocks.reverse();
+        for block in &blocks {
+            self.connect_block(block);
+        }
+        Ok(blocks.len())
+    }
+
+    /// ListUnspent returns the unspent coins of the wallet
+    pub fn list_unspent(&self) -> Vec<&WalletCoin> {
+        self.coins.values().collect()
+    }
+
+    /// Balance returns the value of the unspent coins of an address of the wallet
+    pub fn balance(&self, address: &str) -> Option<Amount> {
+        Amount::checked_sum(
+            self.coins
+                .values()
+                .filter(|coin| coin.address == address)
+                .map(|coin| coin.value),
+        )
+    }
+
+    /// TotalBalance returns the value of the unspent coins of the wallet, or of one of its accounts
+    pub fn total_balance(&self, account: Option<&str>) -> Option<Amount> {
+        let in_account = |coin: &&WalletCoin| match account {
+            Some(account) => self.get_label(&coin.address).account == account,
+            None => true,
+        };
+        Amount::checked_sum(
+            self.coins
+                .values()
+                .filter(in_account)
+                .map(|coin| coin.value),
+        )
+    }
+
+    /// IsMine checks whether the address is a key, watch-only or multisig address of the wallet
+    pub fn is_mine(&self, address: &str) -> bool {
+        self.wallets.contains_key(address)
+            || self.watch_only.contains(address)
+            || self.multisig.contains_key(address)
+    }
+
     pub fn save_all(&self) -> Result<()> {
-        let db = sled::open("data/wallets")?;
+        self.save_to(&self.db)
+    }
+
+    /// save_to writes the wallet into the database
+    fn save_to(&self, db: &sled::Db) -> Result<()> {
+        let hd_keys = db.open_tree("hd_keys")?;
         for (address, wallet) in &self.wallets {
-            let data = bincode::serialize(wallet)?;
+            if let Some(index) = self.hd_keys.get(address) {
+                let key = HdKey {
+                    index: *index,
+                    public_key: wallet.public_key.clone(),
+                };
+                hd_keys.insert(address, bincode::serialize(&key)?)?;
+                continue;
+            }
+            let data = match self.encrypted_keys.get(address) {
+                Some(sealed) if self.encryption.is_some() => bincode::serialize(&Wallet {
+                    secret_key: sealed.clone(),
+                    public_key: wallet.public_key.clone(),
+                })?,
+                _ => bincode::serialize(wallet)?,
+            };
             db.insert(address, data)?;
         }
+        let seed = if self.encryption.is_some() {
+            &self.encrypted_seed
+        } else {
+            &self.seed
+        };
+        if let Some(seed) = seed {
+            let hd = db.open_tree("hd")?;
+            hd.insert(SEED_KEY, seed.as_slice())?;
+            hd.insert(NEXT_INDEX_KEY, bincode::serialize(&self.next_index)?)?;
+        }
+        if let Some(encryption) = &self.encryption {
+            db.open_tree("meta")?
+                .insert(ENCRYPTION_KEY, bincode::serialize(encryption)?)?;
+        }
+        let multisig = db.open_tree("multisig")?;
+        for (address, redeem_script) in &self.multisig {
+            multisig.insert(address, bincode::serialize(redeem_script)?)?;
+        }
+        let labels = db.open_tree("labels")?;
+        for (address, label) in &self.labels {
+            labels.insert(address, bincode::serialize(label)?)?;
+        }
+        // watch-only addresses, coins and transactions also shrink, so their trees are rewritten
+        let watch_only = db.open_tree("watch_only")?;
+        watch_only.clear()?;
+        for address in &self.watch_only {
+            watch_only.insert(address, &[])?;
+        }
+        let coins = db.open_tree("coins")?;
+        coins.clear()?;
+        for ((txid, vout), coin) in &self.coins {
+            coins.insert(format!("{}:{}", txid, vout), bincode::serialize(coin)?)?;
+        }
+        let transactions = db.open_tree("transactions")?;
+        transactions.clear()?;
+        for (txid, wtx) in &self.transactions {
+            transactions.insert(txid, bincode::serialize(wtx)?)?;
+        }
         db.flush()?;
-        drop(db);
         Ok(())
     }
 }
+
+impl Drop for Wallets {
+    fn drop(&mut self) {
+        if self.encryption.is_some() {
+            let _ = self.lock();
+        }
+    }
+}
+
+/// used_addresses collects the key addresses the outputs of the blockchain pay to
+fn used_addresses(bc: &Blockchain) -> Result<HashSet<String>> {
+    let mut used = HashSet::new();
+    for block in bc.iter() {
+        for tx in block?.get_transaction() {
+            for out in &tx.vout {
+                let script = out.script_pub_key.split_timelock().1;
+                if let Some(hash) = script.p2pkh_hash() {
+                    used.insert(key_address(hash));
+                } else if let Some(htlc) = script.htlc_params() {
+                    used.insert(key_address(&htlc.recipient));
+                    used.insert(key_address(&htlc.refund));
+                }
+            }
+        }
+    }
+    Ok(used)
+}
+
+/// wallet_path returns the database directory of a wallet
+fn wallet_path(name: &str) -> Result<String> {
+    if name == DEFAULT_WALLET {
+        return Ok(String::from("data/wallets"));
+    }
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format_err!(
+            "invalid wallet name '{}', use letters, digits, '-' and '_'",
+            name
+        ));
+    }
+    Ok(format!("data/wallets-{}", name))
+}
+
+/// open_db opens the database of a wallet, explaining the failure when the
+/// running node holds it
+fn open_db(name: &str, path: &str) -> Result<sled::Db> {
+    match sled::open(path) {
+        Ok(db) => Ok(db),
+        Err(e) if std::path::Path::new(COOKIE_FILE).exists() => Err(format_err!(
+            "the running node holds wallet {}, unload it there to run this command: {}",
+            name,
+            e
+        )),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// write_loaded saves the names of the loaded named wallets
+fn write_loaded(names: &[String]) -> Result<()> {
+    std::fs::create_dir_all("data")?;
+    let named: Vec<&str> = names
+        .iter()
+        .map(|n| n.as_str())
+        .filter(|n| *n != DEFAULT_WALLET)
+        .collect();
+    std::fs::write(LOADED_WALLETS_FILE, named.join("\n"))?;
+    Ok(())
+}
+
+fn locked_error() -> failure::Error {
+    format_err!("the wallet is locked, unlock it with walletpassphrase first")
+}
+
+/// WritePrivateFile writes a file readable by the owner only
+pub fn write_private_file(path: &str, data: &[u8]) -> Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    std::io::Write::write_all(&mut options.open(path)?, data)?;
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::amount::SUBSIDY;
+    use crate::blockchain::tests::{coinbase, next_block, spend, test_chain, test_wallet};
+
+    /// test_wallets opens an empty wallet in a temporary database
+    pub(crate) fn test_wallets() -> Wallets {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        Wallets::read_db("test", String::new(), db).unwrap()
+    }
+
+    fn coin_heights(wallets: &Wallets) -> Vec<i32> {
+        wallets
+            .list_unspent()
+            .iter()
+            .map(|coin| coin.height)
+            .collect()
+    }
+
+    #[test]
+    fn test_coin_tracking() {
+        let alice = test_wallet(1);
+        let bob = test_wallet(2);
+        let mut bc = test_chain(&alice);
+        let mut wallets = test_wallets();
+        assert_eq!(
+            wallets.import_private_key(&[1; 32]).unwrap(),
+            alice.get_address()
+        );
+        assert_eq!(wallets.rescan(&bc, 0).unwrap(), 1);
+        assert_eq!(wallets.total_balance(None), Some(SUBSIDY));
+
+        // paying bob spends the genesis coin, disconnecting the block gives it back
+        let genesis = bc.iter().next().unwrap().unwrap();
+        let payment = spend(&bc, &alice, &genesis.get_transaction()[0], &bob);
+        let block = next_block(&bc, vec![coinbase(&bob, "1"), payment.clone()]);
+        wallets.apply_tip_change(&bc.add_block(block.clone()).unwrap());
+        assert_eq!(wallets.total_balance(None), Some(Amount::ZERO));
+        assert_eq!(wallets.transactions[&payment.id].spent.len(), 1);
+        wallets.disconnect_block(&block);
+        assert_eq!(coin_heights(&wallets), [0]);
+        assert!(!wallets.transactions.contains_key(&payment.id));
+        wallets.connect_block(&block);
+        assert!(wallets.list_unspent().is_empty());
+
+        let block = next_block(&bc, vec![coinbase(&alice, "2")]);
+        wallets.apply_tip_change(&bc.add_block(block).unwrap());
+        assert_eq!(coin_heights(&wallets), [2]);
+
+        // a rescan from a height only relearns the blocks at or above it
+        let mut restored = test_wallets();
+        restored.import_private_key(&[1; 32]).unwrap();
+        assert_eq!(restored.rescan(&bc, 2).unwrap(), 1);
+        assert_eq!(coin_heights(&restored), [2]);
+        assert_eq!(restored.rescan(&bc, 0).unwrap(), 3);
+        assert_eq!(coin_heights(&restored), [2]);
+        assert_eq!(wallets.rescan(&bc, 1).unwrap(), 2);
+        assert_eq!(coin_heights(&wallets), [2]);
+        assert_eq!(restored.transactions.len(), wallets.transactions.len());
+    }
+
+    /// backup_entries drops the comments of a dump, which hold its creation time
+    fn backup_entries(dump: &str) -> Vec<&str> {
+        dump.lines().filter(|line| !line.starts_with('#')).collect()
+    }
+
+    #[test]
+    fn test_dump_import() {
+        let alice = test_wallet(1);
+        let bc = test_chain(&alice);
+        let mut wallets = test_wallets();
+        let (first, mnemonic) = wallets.new_address(&AddressLabel::default()).unwrap();
+        assert!(mnemonic.is_some());
+        let (second, _) = wallets.new_address(&AddressLabel::default()).unwrap();
+        let imported = wallets.import_private_key(&[3; 32]).unwrap();
+        wallets.import_address(&alice.get_address()).unwrap();
+        let pub_keys = vec![
+            wallets.get_wallet(&first).unwrap().public_key.clone(),
+            test_wallet(4).public_key,
+        ];
+        let multisig = wallets.create_multisig(1, &pub_keys).unwrap();
+        let dump = wallets.dump().unwrap();
+
+        let mut restored = test_wallets();
+        assert_eq!(restored.import_dump(&dump).unwrap(), 4);
+        for address in [&first, &second, &imported, &multisig] {
+            assert!(restored.is_mine(address), "{}", address);
+        }
+        for address in [&first, &second, &imported] {
+            assert_eq!(
+                restored.dump_private_key(address).unwrap(),
+                wallets.dump_private_key(address).unwrap()
+            );
+        }
+        let restored_dump = restored.dump().unwrap();
+        assert_eq!(backup_entries(&restored_dump), backup_entries(&dump));
+        assert!(restored.import_dump("key 00 nope").is_err());
+
+        // the watch-only address counts toward the balance but cannot sign
+        assert!(restored.is_watch_only(&alice.get_address()));
+        assert_eq!(restored.rescan(&bc, 0).unwrap(), 1);
+        assert_eq!(restored.balance(&alice.get_address()), Some(SUBSIDY));
+        assert_eq!(restored.total_balance(None), Some(SUBSIDY));
+        let err = restored
+            .get_signing_wallet(&alice.get_address())
+            .unwrap_err();
+        assert!(err.to_string().contains("watch-only"), "{}", err);
+        assert!(restored.dump_private_key(&alice.get_address()).is_err());
+    }
+
+    #[test]
+    fn test_accounts() {
+        for name in ["", "a b", "../wallets", "a/b", "caf\u{e9}"] {
+            assert!(wallet_path(name).is_err(), "{:?}", name);
+            assert!(Wallets::new(name).is_err(), "{:?}", name);
+        }
+        assert_eq!(wallet_path(DEFAULT_WALLET).unwrap(), "data/wallets");
+        assert_eq!(wallet_path("cold-2_b").unwrap(), "data/wallets-cold-2_b");
+
+        let alice = test_wallet(1);
+        let bob = test_wallet(2);
+        let mut bc = test_chain(&alice);
+        let block = next_block(&bc, vec![coinbase(&bob, "1")]);
+        bc.add_block(block).unwrap();
+        let mut wallets = test_wallets();
+        let alice_address = wallets.import_private_key(&[1; 32]).unwrap();
+        let bob_address = wallets.import_private_key(&[2; 32]).unwrap();
+        wallets.set_account(&alice_address, "savings").unwrap();
+        wallets.set_label(&bob_address, "spending").unwrap();
+        let label = AddressLabel {
+            label: String::from("later"),
+            account: String::from("savings"),
+        };
+        let (unused, _) = wallets.new_address(&label).unwrap();
+        assert!(wallets
+            .set_account(&test_wallet(3).get_address(), "x")
+            .is_err());
+        wallets.rescan(&bc, 0).unwrap();
+
+        let total = SUBSIDY.checked_add(SUBSIDY);
+        assert_eq!(wallets.total_balance(None), total);
+        assert_eq!(wallets.total_balance(Some("savings")), Some(SUBSIDY));
+        assert_eq!(wallets.total_balance(Some("")), Some(SUBSIDY));
+        assert_eq!(wallets.total_balance(Some("other")), Some(Amount::ZERO));
+
+        let mut savings = vec![alice_address, unused];
+        savings.sort();
+        assert_eq!(wallets.get_account_addresses("savings"), savings);
+        assert_eq!(wallets.get_account_addresses(""), [bob_address]);
+        assert!(wallets.get_account_addresses("other").is_empty());
+        assert_eq!(wallets.get_accounts(), ["", "savings"]);
+        let listed = crate::rpc::addresses_json(&wallets, Some("savings"));
+        let listed: Vec<&str> = listed["addresses"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| entry["address"].as_str().unwrap())
+            .collect();
+        assert_eq!(listed, savings);
+    }
+
+    #[test]
+    fn test_sign_message() {
+        let alice = test_wallet(1);
+        let address = alice.get_address();
+        let signature = alice.sign_message("hello");
+        assert_eq!(signature.len(), MESSAGE_SIGNATURE_LEN);
+        assert!(verify_message(&address, &signature, "hello").unwrap());
+
+        assert!(!verify_message(&address, &signature, "hello!").unwrap());
+        let other = test_wallet(2).get_address();
+        assert!(!verify_message(&other, &signature, "hello").unwrap());
+        let mut tampered = signature.clone();
+        tampered[MESSAGE_SIGNATURE_LEN - 1] ^= 1;
+        assert!(!verify_message(&address, &tampered, "hello").unwrap());
+
+        // a short or long signature is refused, never read past its end
+        for len in [0, 32, MESSAGE_SIGNATURE_LEN - 1] {
+            assert!(verify_message(&address, &signature[..len], "hello").is_err());
+        }
+        let mut long = signature.clone();
+        long.push(0);
+        assert!(verify_message(&address, &long, "hello").is_err());
+        let redeem_script = Script::new_multisig(1, std::slice::from_ref(&alice.public_key));
+        let multisig = multisig_address(&redeem_script).unwrap();
+        assert!(verify_message(&multisig, &signature, "hello").is_err());
+    }
+}
@@ -0,0 +1,96 @@
+use std::fs;
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+const NODE_PORT: &str = "3917";
+const RPC_BIND: &str = "127.0.0.1:18917";
+const FIXTURE_ADDRESS: &str = "BHiXGtopH6jKuamEZd3SbpK3EKMr9VmBS9";
+
+/// Node runs the binary as a node on a copy of the fixture data, it is killed
+/// and its directory removed when dropped
+struct Node {
+    dir: PathBuf,
+    child: Child,
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn copy_dir(from: &Path, to: &Path) {
+    fs::create_dir_all(to).unwrap();
+    for entry in fs::read_dir(from).unwrap() {
+        let entry = entry.unwrap();
+        let target = to.join(entry.file_name());
+        if entry.file_type().unwrap().is_dir() {
+            copy_dir(&entry.path(), &target);
+        } else {
+            fs::copy(entry.path(), target).unwrap();
+        }
+    }
+}
+
+fn start_node() -> Node {
+    let dir = std::env::temp_dir().join(format!("blockchain-rust-cli-node-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    copy_dir(
+        &Path::new(env!("CARGO_MANIFEST_DIR")).join("data"),
+        &dir.join("data"),
+    );
+    let child = Command::new(env!("CARGO_BIN_EXE_blockchain-rust"))
+        .args(["startnode", NODE_PORT, "--rpcbind", RPC_BIND])
+        .current_dir(&dir)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let node = Node { dir, child };
+    for _ in 0..100 {
+        if node.dir.join("data/.cookie").exists() && TcpStream::connect(RPC_BIND).is_ok() {
+            return node;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    panic!("the node did not start serving RPC");
+}
+
+/// run runs a CLI command next to the node, returning whether it succeeded and its output
+fn run(node: &Node, args: &[&str]) -> (bool, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_blockchain-rust"))
+        .args(args)
+        .current_dir(&node.dir)
+        .output()
+        .unwrap();
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    (output.status.success(), text)
+}
+
+#[test]
+fn test_cli_against_running_node() {
+    let node = start_node();
+    for (args, expected) in [
+        (vec!["printchain"], "block:"),
+        (vec!["reindex"], "Done!"),
+        (vec!["rescan"], "Rescanned"),
+        (vec!["finddata", "00ff"], "data is not anchored"),
+        (vec!["getbalance", FIXTURE_ADDRESS], "100"),
+        (vec!["listunspent"], FIXTURE_ADDRESS),
+    ] {
+        let (ok, output) = run(&node, &args);
+        assert!(ok && output.contains(expected), "{:?}: {}", args, output);
+    }
+    let (ok, output) = run(&node, &["create", FIXTURE_ADDRESS]);
+    assert!(
+        !ok && output.contains("running node holds the chain"),
+        "{}",
+        output
+    );
+}